@@ -0,0 +1,176 @@
+use glam::{DMat2, DMat3, DVec2, DVec3};
+use opencv::core::{Point2f, Point3f, Size, Vector};
+use opencv::prelude::*;
+
+// telecentric lenses and microscopes have no perspective: every view of the planar board is
+// an affine map of its plane, bent by radial distortion around the image center. Distortion
+// is applied to undistorted pixels, u_d = c + (u - c)(1 + k1 r^2 + k2 r^4), with r the
+// distance to the center relative to the half diagonal
+
+const ITERATIONS: usize = 30;
+const STEP: f64 = 1e-6;
+
+fn center(camera_matrix: &[f64]) -> DVec2 {
+    DVec2::new(camera_matrix[2], camera_matrix[5])
+}
+
+/// distorted pixel of an undistorted one
+pub fn distort(point: DVec2, camera_matrix: &[f64], dist_coeffs: &[f64]) -> DVec2 {
+    let c = center(camera_matrix);
+    let k = |i: usize| dist_coeffs.get(i).copied().unwrap_or(0.0);
+    let r2 = ((point - c) / c.length()).length_squared();
+    c + (point - c) * (1.0 + k(0) * r2 + k(1) * r2 * r2)
+}
+
+/// undistorted pixel of a distorted one, by fixed point iteration
+pub fn undistort(point: DVec2, camera_matrix: &[f64], dist_coeffs: &[f64]) -> DVec2 {
+    let mut undistorted = point;
+    for _ in 0..ITERATIONS {
+        undistorted += point - distort(undistorted, camera_matrix, dist_coeffs);
+    }
+    undistorted
+}
+
+// least squares affine map of board plane points onto image points, as the rows of a 2x3
+fn fit_affine(plane: &[DVec2], image: &[DVec2]) -> (DVec3, DVec3) {
+    let mut normal = DMat3::ZERO;
+    let (mut bx, mut by) = (DVec3::ZERO, DVec3::ZERO);
+    for (p, u) in plane.iter().zip(image) {
+        let row = DVec3::new(p.x, p.y, 1.0);
+        normal += DMat3::from_cols(row * row.x, row * row.y, row * row.z);
+        bx += row * u.x;
+        by += row * u.y;
+    }
+    let inverse = normal.inverse();
+    (inverse * bx, inverse * by)
+}
+
+// residuals of every corner after undistortion and a per view affine fit
+fn residuals(
+    views: &[(Vec<DVec2>, Vec<DVec2>)],
+    camera_matrix: &[f64],
+    dist_coeffs: &[f64],
+) -> Vec<f64> {
+    let mut residuals = Vec::new();
+    for (plane, image) in views {
+        let undistorted = image
+            .iter()
+            .map(|point| undistort(*point, camera_matrix, dist_coeffs))
+            .collect::<Vec<DVec2>>();
+        let (ax, ay) = fit_affine(plane, &undistorted);
+        for (p, u) in plane.iter().zip(&undistorted) {
+            let row = DVec3::new(p.x, p.y, 1.0);
+            residuals.push(ax.dot(row) - u.x);
+            residuals.push(ay.dot(row) - u.y);
+        }
+    }
+    residuals
+}
+
+/// rms error in pixels, camera matrix, k1, k2 and the rms error of every view
+pub type Solution = (f64, Vec<f64>, Vec<f64>, Vec<f64>);
+
+/// affine calibration, the camera matrix has the magnification in pixels per board unit and
+/// the image center
+pub fn calibrate_camera(
+    objpoints: &Vector<Vector<Point3f>>,
+    imgpoints: &Vector<Vector<Point2f>>,
+    size: Size,
+) -> opencv::Result<Solution> {
+    let views = objpoints
+        .iter()
+        .zip(imgpoints.iter())
+        .map(|(objp, corners)| {
+            (
+                objp.iter()
+                    .map(|p| DVec2::new(p.x as f64, p.y as f64))
+                    .collect(),
+                corners
+                    .iter()
+                    .map(|p| DVec2::new(p.x as f64, p.y as f64))
+                    .collect(),
+            )
+        })
+        .collect::<Vec<(Vec<DVec2>, Vec<DVec2>)>>();
+    let mut camera_matrix = vec![
+        1.0,
+        0.0,
+        size.width as f64 / 2.0,
+        0.0,
+        1.0,
+        size.height as f64 / 2.0,
+        0.0,
+        0.0,
+        1.0,
+    ];
+
+    // gauss-newton on k1, k2 with a numeric jacobian, the affine maps are solved linearly
+    let mut k = DVec2::ZERO;
+    for _ in 0..ITERATIONS {
+        let r = residuals(&views, &camera_matrix, &[k.x, k.y]);
+        let r1 = residuals(&views, &camera_matrix, &[k.x + STEP, k.y]);
+        let r2 = residuals(&views, &camera_matrix, &[k.x, k.y + STEP]);
+        let mut jtj = DMat2::ZERO;
+        let mut jtr = DVec2::ZERO;
+        for i in 0..r.len() {
+            let j = DVec2::new((r1[i] - r[i]) / STEP, (r2[i] - r[i]) / STEP);
+            jtj += DMat2::from_cols(j * j.x, j * j.y);
+            jtr += j * r[i];
+        }
+        let step = jtj.inverse() * jtr;
+        if !step.is_finite() {
+            break;
+        }
+        k -= step;
+        if step.length() < 1e-10 {
+            break;
+        }
+    }
+
+    let dist_coeffs = vec![k.x, k.y];
+    let r = residuals(&views, &camera_matrix, &dist_coeffs);
+    let rms = (r.iter().map(|r| r * r).sum::<f64>() / (r.len() / 2).max(1) as f64).sqrt();
+    let view_errors = views
+        .iter()
+        .map(|view| {
+            let r = residuals(std::slice::from_ref(view), &camera_matrix, &dist_coeffs);
+            (r.iter().map(|r| r * r).sum::<f64>() / (r.len() / 2).max(1) as f64).sqrt()
+        })
+        .collect::<Vec<f64>>();
+
+    // the untilted direction of each view is scaled by the magnification alone
+    let magnification = views
+        .iter()
+        .map(|(plane, image)| {
+            let undistorted = image
+                .iter()
+                .map(|point| undistort(*point, &camera_matrix, &dist_coeffs))
+                .collect::<Vec<DVec2>>();
+            let (ax, ay) = fit_affine(plane, &undistorted);
+            let m = DMat2::from_cols(DVec2::new(ax.x, ay.x), DVec2::new(ax.y, ay.y));
+            let mtm = m.transpose() * m;
+            let (trace, det) = (mtm.x_axis.x + mtm.y_axis.y, mtm.determinant());
+            (trace / 2.0 + (trace * trace / 4.0 - det).max(0.0).sqrt()).sqrt()
+        })
+        .sum::<f64>()
+        / views.len().max(1) as f64;
+    camera_matrix[0] = magnification;
+    camera_matrix[4] = magnification;
+    Ok((rms, camera_matrix, dist_coeffs, view_errors))
+}
+
+/// remap tables undistorting images of the given size
+pub fn maps(camera_matrix: &[f64], dist_coeffs: &[f64], size: Size) -> opencv::Result<(Mat, Mat)> {
+    let (mut xs, mut ys) = (Vec::new(), Vec::new());
+    for y in 0..size.height {
+        for x in 0..size.width {
+            let source = distort(DVec2::new(x as f64, y as f64), camera_matrix, dist_coeffs);
+            xs.push(source.x as f32);
+            ys.push(source.y as f32);
+        }
+    }
+    Ok((
+        Mat::new_rows_cols_with_data(size.height, size.width, &xs)?.try_clone()?,
+        Mat::new_rows_cols_with_data(size.height, size.width, &ys)?.try_clone()?,
+    ))
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fs;
+
+use log::info;
+use serde::Serialize;
+
+use crate::{Calibration, CameraModel};
+
+/// blender camera and render settings matching the calibrated pinhole, distortion is not
+/// representable and left out
+#[derive(Serialize)]
+struct BlenderCamera {
+    resolution_x: i32,
+    resolution_y: i32,
+    pixel_aspect_x: f64,
+    pixel_aspect_y: f64,
+    sensor_fit: &'static str,
+    sensor_width: f64,
+    lens: f64,
+    shift_x: f64,
+    shift_y: f64,
+}
+
+impl BlenderCamera {
+    fn new(calibration: &Calibration, width: i32, height: i32, sensor_width: f64) -> Self {
+        let k = &calibration.camera_matrix;
+        let (fx, cx, fy, cy) = (k[0], k[2], k[4], k[5]);
+        // shifts are fractions of the larger image side, y pointing up
+        let scale = width.max(height) as f64;
+        BlenderCamera {
+            resolution_x: width,
+            resolution_y: height,
+            pixel_aspect_x: 1.0,
+            pixel_aspect_y: fx / fy,
+            sensor_fit: "HORIZONTAL",
+            sensor_width,
+            lens: fx * sensor_width / width as f64,
+            shift_x: (width as f64 / 2.0 - cx) / scale,
+            shift_y: (cy - height as f64 / 2.0) / scale,
+        }
+    }
+
+    fn to_python(&self) -> String {
+        format!(
+            r#"import bpy
+
+scene = bpy.context.scene
+scene.render.resolution_x = {}
+scene.render.resolution_y = {}
+scene.render.resolution_percentage = 100
+scene.render.pixel_aspect_x = {}
+scene.render.pixel_aspect_y = {}
+
+camera = scene.camera.data
+camera.type = "PERSP"
+camera.lens_unit = "MILLIMETERS"
+camera.sensor_fit = "{}"
+camera.sensor_width = {}
+camera.lens = {}
+camera.shift_x = {}
+camera.shift_y = {}
+"#,
+            self.resolution_x,
+            self.resolution_y,
+            self.pixel_aspect_x,
+            self.pixel_aspect_y,
+            self.sensor_fit,
+            self.sensor_width,
+            self.lens,
+            self.shift_x,
+            self.shift_y
+        )
+    }
+}
+
+/// python snippet for blender's text editor, or the same values as json when `output` ends
+/// in `.json`
+pub fn export(
+    calibration_file: &str,
+    output: &str,
+    width: i32,
+    height: i32,
+    sensor_width: f64,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+    if calibration.model != CameraModel::Pinhole {
+        return Err(format!(
+            "only pinhole calibrations convert to blender cameras, not {:?}",
+            calibration.model
+        )
+        .into());
+    }
+    let camera = BlenderCamera::new(&calibration, width, height, sensor_width);
+    let content = if output.ends_with(".json") {
+        serde_json::to_string_pretty(&camera)?
+    } else {
+        camera.to_python()
+    };
+    fs::write(output, content)?;
+    info!(
+        "{output} written, lens {:.3}mm on a {sensor_width}mm sensor",
+        camera.lens
+    );
+    Ok(())
+}
@@ -0,0 +1,422 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::ops::RangeInclusive;
+
+use clap::{Args, ValueEnum};
+use log::{info, warn};
+use opencv::calib3d::{CALIB_CB_ASYMMETRIC_GRID, CALIB_CB_SYMMETRIC_GRID, find_circles_grid_1};
+use opencv::core::{Point2f, Point3f, Ptr, Size, Vector};
+use opencv::features2d::{Feature2D, SimpleBlobDetector, SimpleBlobDetector_Params};
+use opencv::imgproc;
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BOARD_HEIGHT, BOARD_WIDTH, chessboard_visible, chessboard_visible_sb, detect_corners,
+    detect_corners_sb, object_points, thermal,
+};
+
+// images `--auto-board-size` looks at and the longer side they are shrunk to for it
+const AUTO_SIZE_IMAGES: usize = 5;
+const AUTO_SIZE_SIDE: f64 = 1000.0;
+// interior corners along each side of the boards it tries
+const AUTO_SIZE_CORNERS: RangeInclusive<i32> = 3..=16;
+
+/// kind of calibration target
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pattern {
+    #[default]
+    Chessboard,
+    /// dark circles on white in a regular grid
+    Circles,
+    /// every other row of circles shifted by half the spacing
+    AsymmetricCircles,
+}
+
+/// chessboard corner detector
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Detector {
+    /// findChessboardCorners refined with cornerSubPix
+    #[default]
+    Classic,
+    /// findChessboardCornersSB, more accurate and robust to blur and noise, finds boards with
+    /// markers in the squares
+    Sb,
+}
+
+/// calibration target, counted in interior corners or circles
+#[derive(Clone, Copy, Debug)]
+pub struct Board {
+    pub pattern: Pattern,
+    pub width: i32,
+    pub height: i32,
+    /// without it everything is measured in squares
+    pub square_size_mm: Option<f32>,
+    /// 16-bit thermal images, see `thermal::read`
+    pub thermal: bool,
+    /// heated board, the dark squares appear bright
+    pub inverted: bool,
+    pub detector: Detector,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board {
+            pattern: Pattern::Chessboard,
+            width: BOARD_WIDTH,
+            height: BOARD_HEIGHT,
+            square_size_mm: None,
+            thermal: false,
+            inverted: false,
+            detector: Detector::Classic,
+        }
+    }
+}
+
+/// board geometry stored with a calibration
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Geometry {
+    #[serde(default)]
+    pub pattern: Pattern,
+    pub width: i32,
+    pub height: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub square_size_mm: Option<f32>,
+}
+
+impl Board {
+    pub fn geometry(&self) -> Geometry {
+        Geometry {
+            pattern: self.pattern,
+            width: self.width,
+            height: self.height,
+            square_size_mm: self.square_size_mm,
+        }
+    }
+
+    pub fn pattern(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+
+    /// board points in detection order, for asymmetric grids the square size is half the
+    /// spacing of the circles in a row like in opencv's calibration sample
+    pub fn object_points(&self) -> Vector<Point3f> {
+        let square_size = self.square_size_mm.unwrap_or(1.0);
+        match self.pattern {
+            Pattern::AsymmetricCircles => {
+                Vector::from_iter((0..self.width * self.height).map(|i| {
+                    let (row, column) = (i / self.width, i % self.width);
+                    Point3f::new(
+                        (2 * column + row % 2) as f32 * square_size,
+                        row as f32 * square_size,
+                        0.0,
+                    )
+                }))
+            }
+            _ => object_points(self.width, self.height, square_size),
+        }
+    }
+
+    /// corners or circle centers of the board, None when it is not visible
+    pub fn detect(&self, img: &Mat) -> opencv::Result<Option<Vector<Point2f>>> {
+        let flags = match self.pattern {
+            Pattern::Chessboard => {
+                return match self.detector {
+                    Detector::Classic => detect_corners(img, self.pattern()),
+                    Detector::Sb => detect_corners_sb(img, self.pattern()),
+                };
+            }
+            Pattern::Circles => CALIB_CB_SYMMETRIC_GRID,
+            Pattern::AsymmetricCircles => CALIB_CB_ASYMMETRIC_GRID,
+        };
+        // blobs from a few pixels up to the size of one grid cell, phone captures of printed
+        // grids are often small and soft
+        let cells = (self.width * self.height) as f32;
+        let mut params = SimpleBlobDetector_Params::default()?;
+        params.min_area = 9.0;
+        params.max_area = (img.cols() * img.rows()) as f32 / cells;
+        params.filter_by_convexity = false;
+        let detector = Ptr::<Feature2D>::from(SimpleBlobDetector::create(params)?);
+        let mut centers = Vector::<Point2f>::new();
+        if !find_circles_grid_1(img, self.pattern(), &mut centers, flags, Some(&detector))? {
+            return Ok(None);
+        }
+        Ok(Some(centers))
+    }
+
+    // a chessboard of this size in the image, without refining the corners
+    fn visible(&self, img: &Mat) -> opencv::Result<bool> {
+        match self.detector {
+            Detector::Classic => chessboard_visible(img, self.pattern()),
+            Detector::Sb => chessboard_visible_sb(img, self.pattern()),
+        }
+    }
+
+    /// the chessboard with the interior corner count found in most of the first images, the
+    /// largest board visible in each one counts, users often count squares instead of corners
+    pub fn with_detected_size(self, images: &[String]) -> Result<Self, Box<dyn Error>> {
+        if self.pattern != Pattern::Chessboard {
+            return Err(format!(
+                "--auto-board-size needs a chessboard, not {:?}",
+                self.pattern
+            )
+            .into());
+        }
+        let mut sizes = AUTO_SIZE_CORNERS
+            .flat_map(|width| {
+                AUTO_SIZE_CORNERS
+                    .filter(move |height| *height <= width)
+                    .map(move |height| (width, height))
+            })
+            .collect::<Vec<(i32, i32)>>();
+        sizes.sort_by_key(|(width, height)| Reverse(width * height));
+        let mut votes = HashMap::<(i32, i32), usize>::new();
+        let tried = images.len().min(AUTO_SIZE_IMAGES);
+        for image in &images[..tried] {
+            let img = match self.read_image(image) {
+                Ok(img) => img,
+                Err(e) => {
+                    warn!("{image}: {e}");
+                    continue;
+                }
+            };
+            let scale = (AUTO_SIZE_SIDE / img.cols().max(img.rows()) as f64).min(1.0);
+            let mut small = Mat::default();
+            imgproc::resize(
+                &img,
+                &mut small,
+                Size::default(),
+                scale,
+                scale,
+                imgproc::INTER_AREA,
+            )?;
+            for &(width, height) in &sizes {
+                let board = Board {
+                    width,
+                    height,
+                    ..self
+                };
+                if board.visible(&small)? {
+                    info!("{image}: {width}x{height} board");
+                    *votes.entry((width, height)).or_default() += 1;
+                    break;
+                }
+            }
+        }
+        let Some(((width, height), count)) = votes
+            .into_iter()
+            .max_by_key(|((width, height), count)| (*count, width * height))
+        else {
+            return Err(format!(
+                "no chessboard of {} to {} interior corners per side in the first {tried} \
+                 images",
+                AUTO_SIZE_CORNERS.start(),
+                AUTO_SIZE_CORNERS.end()
+            )
+            .into());
+        };
+        info!("board size {width}x{height} interior corners, found in {count} of {tried} images");
+        Ok(Board {
+            width,
+            height,
+            ..self
+        })
+    }
+
+    /// image prepared for detecting this board
+    pub fn read_image(&self, path: &str) -> opencv::Result<Mat> {
+        thermal::read(path, self.thermal, self.inverted)
+    }
+
+    /// file extensions of the calibration images
+    pub fn extensions(&self) -> &'static [&'static str] {
+        if self.thermal {
+            &thermal::EXTENSIONS
+        } else {
+            &crate::IMAGE_EXTENSIONS
+        }
+    }
+
+    /// kalibr style target yaml or a json descriptor, picked by the file extension
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        let descriptor = if path.ends_with(".json") {
+            serde_json::from_str::<Descriptor>(&content)?
+        } else {
+            Descriptor::from_yaml(&content)?
+        };
+        descriptor.board(path)
+    }
+}
+
+/// target descriptor as written by board generators, kalibr key names with a few aliases.
+/// Rows and columns count interior corners like kalibr does
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(alias = "target_type", alias = "type", alias = "pattern")]
+    target_type: Option<String>,
+    #[serde(alias = "columns", alias = "cols")]
+    target_cols: Option<i32>,
+    #[serde(alias = "rows")]
+    target_rows: Option<i32>,
+    col_spacing_meters: Option<f64>,
+    row_spacing_meters: Option<f64>,
+    #[serde(alias = "square_size_mm", alias = "checkerWidth")]
+    square_size_mm: Option<f64>,
+    /// circle grids, kalibr's circle spacing and grid layout
+    spacing_meters: Option<f64>,
+    #[serde(default)]
+    asymmetric_grid: bool,
+}
+
+impl Descriptor {
+    // flat `key: value` yaml, enough for kalibr target files
+    fn from_yaml(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut map = serde_json::Map::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().trim_matches(|c| c == '\'' || c == '"');
+            let value = value
+                .parse::<f64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| serde_json::Value::from(value));
+            map.insert(key.trim().to_string(), value);
+        }
+        // integers were parsed as floats above
+        for key in ["targetCols", "targetRows", "columns", "cols", "rows"] {
+            if let Some(value) = map.get(key).and_then(serde_json::Value::as_f64) {
+                map.insert(key.to_string(), serde_json::Value::from(value as i64));
+            }
+        }
+        Ok(serde_json::from_value(serde_json::Value::Object(map))?)
+    }
+
+    fn board(&self, path: &str) -> Result<Board, Box<dyn Error>> {
+        let target_type = self.target_type.as_deref().unwrap_or("checkerboard");
+        let pattern = match target_type {
+            "checkerboard" | "chessboard" => Pattern::Chessboard,
+            "circlegrid" | "circles" if self.asymmetric_grid => Pattern::AsymmetricCircles,
+            "circlegrid" | "circles" => Pattern::Circles,
+            "asymmetric-circles" => Pattern::AsymmetricCircles,
+            _ => return Err(format!("{path}: {target_type} targets are not supported").into()),
+        };
+        let (Some(width), Some(height)) = (self.target_cols, self.target_rows) else {
+            return Err(format!("{path}: target rows and columns are required").into());
+        };
+        if let (Some(cols), Some(rows)) = (self.col_spacing_meters, self.row_spacing_meters)
+            && (cols - rows).abs() > 1e-9
+        {
+            return Err(format!("{path}: only square checkers are supported").into());
+        }
+        let square_size_mm = self
+            .square_size_mm
+            .or(self
+                .row_spacing_meters
+                .or(self.spacing_meters)
+                .map(|meters| meters * 1000.0))
+            .map(|mm| mm as f32);
+        Ok(Board {
+            pattern,
+            width,
+            height,
+            square_size_mm,
+            ..Board::default()
+        })
+    }
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct BoardArgs {
+    /// target descriptor (kalibr yaml or json) with the board type, size and square size
+    #[arg(long)]
+    pub target: Option<String>,
+    /// kind of target, overrides the target descriptor
+    #[arg(long, value_enum)]
+    pub pattern: Option<Pattern>,
+    /// interior corners along the board, overrides the target descriptor
+    #[arg(long)]
+    pub board_width: Option<i32>,
+    /// interior corners across the board, overrides the target descriptor
+    #[arg(long)]
+    pub board_height: Option<i32>,
+    /// edge length of one square, calibrations and poses come out in mm instead of squares
+    #[arg(long)]
+    pub square_size_mm: Option<f32>,
+    /// thermal camera images, 16-bit tiff/png are normalized to 8 bits for detection
+    #[arg(long)]
+    pub thermal: bool,
+    /// heated board or otherwise inverted contrast, dark squares appear bright
+    #[arg(long)]
+    pub invert: bool,
+    /// chessboard corner detector, sb for opencv's more accurate sector based one
+    #[arg(long, value_enum, default_value_t = Detector::Classic)]
+    pub detector: Detector,
+}
+
+impl BoardArgs {
+    /// the board of a calibration unless the target or its size is given, images of the same
+    /// board don't need the options again
+    pub fn board_or(&self, geometry: Option<Geometry>) -> Result<Board, Box<dyn Error>> {
+        let given = self.target.is_some()
+            || self.pattern.is_some()
+            || self.board_width.is_some()
+            || self.board_height.is_some()
+            || self.square_size_mm.is_some();
+        match geometry.filter(|_| !given) {
+            Some(geometry) => Ok(Board {
+                pattern: geometry.pattern,
+                width: geometry.width,
+                height: geometry.height,
+                square_size_mm: geometry.square_size_mm,
+                thermal: self.thermal,
+                inverted: self.invert,
+                detector: self.detector,
+            }),
+            None => self.board(),
+        }
+    }
+
+    pub fn board(&self) -> Result<Board, Box<dyn Error>> {
+        let board = match &self.target {
+            Some(path) => {
+                let board = Board::load(path)?;
+                info!(
+                    "target {path}: {:?} {}x{}, square {}",
+                    board.pattern,
+                    board.width,
+                    board.height,
+                    board
+                        .square_size_mm
+                        .map_or("unknown".to_string(), |mm| format!("{mm} mm"))
+                );
+                board
+            }
+            None => Board::default(),
+        };
+        let board = Board {
+            pattern: self.pattern.unwrap_or(board.pattern),
+            width: self.board_width.unwrap_or(board.width),
+            height: self.board_height.unwrap_or(board.height),
+            square_size_mm: self.square_size_mm.or(board.square_size_mm),
+            thermal: self.thermal,
+            inverted: self.invert,
+            detector: self.detector,
+        };
+        if board.width < 2 || board.height < 2 {
+            return Err(format!(
+                "a {}x{} board has too few interior corners",
+                board.width, board.height
+            )
+            .into());
+        }
+        Ok(board)
+    }
+}
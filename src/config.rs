@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, Command, CommandFactory};
+use toml_edit::{DocumentMut, Item, Value};
+
+use crate::Args;
+
+pub const FILE_NAME: &str = "opencv-undistort.toml";
+
+/// environment variable of a long option, `--pairs-file` is `OPENCV_UNDISTORT_PAIRS_FILE`
+pub fn env_name(flag: &str) -> String {
+    format!("OPENCV_UNDISTORT_{}", flag.replace('-', "_").to_uppercase())
+}
+
+fn with_env(arg: Arg) -> Arg {
+    match arg.get_long().map(env_name) {
+        Some(env) if !matches!(arg.get_id().as_str(), "help" | "version") => arg.env(env),
+        _ => arg,
+    }
+}
+
+/// the cli with every long option also read from its environment variable. Built first so
+/// the global options are copied into every subcommand and read from the environment there too
+pub fn command() -> Command {
+    let mut command = Args::command();
+    command.build();
+    command
+        .mut_args(with_env)
+        .mut_subcommands(|subcommand| subcommand.mut_args(with_env))
+}
+
+// user config first, the project-local file overrides it
+fn config_files() -> Vec<PathBuf> {
+    let user = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join(FILE_NAME));
+    user.into_iter()
+        .chain([PathBuf::from(FILE_NAME)])
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+pub fn value_args(flag: &str, value: &Value) -> Result<Vec<String>, String> {
+    Ok(match value {
+        Value::Boolean(enabled) if *enabled.value() => vec![format!("--{flag}")],
+        Value::Boolean(_) => vec![],
+        Value::String(text) => vec![format!("--{flag}={}", text.value())],
+        Value::Integer(number) => vec![format!("--{flag}={}", number.value())],
+        Value::Float(number) => vec![format!("--{flag}={}", number.value())],
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value_args(flag, value))
+            .collect::<Result<Vec<Vec<String>>, String>>()?
+            .concat(),
+        _ => return Err(format!("unsupported value for {flag}")),
+    })
+}
+
+// long names of the options on the command line of a subcommand, `-c` counts as
+// `--calibration-file`
+fn given_flags(subcommand: &Command, args: &[OsString]) -> Vec<String> {
+    let short = |short: char| {
+        subcommand
+            .get_arguments()
+            .find(|arg| arg.get_short() == Some(short))
+    };
+    let mut given = Vec::new();
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            given.push(long.split('=').next().unwrap_or(long).to_string());
+        } else if let Some(shorts) = arg.strip_prefix('-') {
+            // -qy are two flags, -ccam.json an option and its value
+            for arg in shorts.chars().map_while(short) {
+                given.extend(arg.get_long().map(str::to_string));
+                if arg.get_action().takes_values() {
+                    break;
+                }
+            }
+        }
+    }
+    given
+}
+
+/// prepend the config file defaults to the arguments of the invoked subcommand, options given
+/// on the command line or set in the environment skip the config value and `--no-<flag>`
+/// switches off a flag the config turns on. Top level keys apply to every subcommand that has
+/// the option, `[subcommand]` tables only to that one
+pub fn with_defaults(args: Vec<OsString>) -> Result<Vec<OsString>, Box<dyn Error>> {
+    layered(args, &config_files(), |flag| {
+        std::env::var_os(env_name(flag)).is_some()
+    })
+}
+
+// the defaults of `files`, later files override earlier ones, `in_env` tells the options set
+// in the environment
+fn layered(
+    args: Vec<OsString>,
+    files: &[PathBuf],
+    in_env: impl Fn(&str) -> bool,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    // built, the subcommands also have the global options like --threads
+    let mut command = Args::command();
+    command.build();
+    let Some((position, subcommand)) = args
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(i, arg)| {
+            command
+                .find_subcommand(arg.to_string_lossy().as_ref())
+                .map(|subcommand| (i, subcommand))
+        })
+        // the help subcommand of the built command takes no options
+        .filter(|(_, subcommand)| subcommand.get_name() != "help")
+    else {
+        return Ok(args);
+    };
+    let name = subcommand.get_name().to_string();
+    let has_flag = |flag: &str| {
+        subcommand
+            .get_arguments()
+            .any(|arg| arg.get_long() == Some(flag))
+    };
+
+    let is_switch = |flag: &str| {
+        subcommand.get_arguments().any(|arg| {
+            arg.get_long() == Some(flag) && matches!(arg.get_action(), ArgAction::SetTrue)
+        })
+    };
+    let mut args = args;
+    let mut rest = args.split_off(position + 1);
+    let mut switched_off = Vec::new();
+    rest.retain(|arg| {
+        let flag = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix("--no-"))
+            .filter(|flag| !has_flag(&format!("no-{flag}")) && is_switch(flag));
+        switched_off.extend(flag.map(str::to_string));
+        flag.is_none()
+    });
+    let given = given_flags(subcommand, &rest);
+    let skip =
+        |flag: &str| in_env(flag) || given.iter().chain(&switched_off).any(|given| given == flag);
+
+    let mut defaults = Vec::new();
+    for path in files {
+        let document = fs::read_to_string(path)?
+            .parse::<DocumentMut>()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        for (key, item) in document.iter() {
+            let flag = key.replace('_', "-");
+            match item {
+                Item::Value(_) if skip(&flag) => {}
+                Item::Value(value) if has_flag(&flag) => defaults.extend(
+                    value_args(&flag, value).map_err(|e| format!("{}: {e}", path.display()))?,
+                ),
+                Item::Table(table) if key == name => {
+                    for (key, item) in table.iter() {
+                        let flag = key.replace('_', "-");
+                        let value =
+                            item.as_value().filter(|_| has_flag(&flag)).ok_or_else(|| {
+                                format!("{}: {name} has no option {key}", path.display())
+                            })?;
+                        if skip(&flag) {
+                            continue;
+                        }
+                        defaults.extend(
+                            value_args(&flag, value)
+                                .map_err(|e| format!("{}: {e}", path.display()))?,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    args.extend(defaults.into_iter().map(OsString::from));
+    args.extend(rest);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use clap::FromArgMatches;
+    use opencv_undistort::Interpolation;
+
+    use super::*;
+    use crate::Action;
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+
+    fn config_file(content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "opencv-undistort-config-{}-{}.toml",
+            process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn args(line: &str) -> Vec<OsString> {
+        line.split_whitespace().map(OsString::from).collect()
+    }
+
+    // interpolation and --recursive of `correct` with the config files layered in
+    fn correct(line: &str, files: &[&str], in_env: impl Fn(&str) -> bool) -> (Interpolation, bool) {
+        let files = files
+            .iter()
+            .map(|content| config_file(content))
+            .collect::<Vec<_>>();
+        let layered = layered(args(line), &files, in_env).unwrap();
+        files.iter().for_each(|file| fs::remove_file(file).unwrap());
+        let matches = Args::command().try_get_matches_from(layered).unwrap();
+        match Args::from_arg_matches(&matches).unwrap().action {
+            Action::Correct {
+                interpolation,
+                selection,
+                ..
+            } => (interpolation, selection.recursive),
+            _ => panic!("not a correct command"),
+        }
+    }
+
+    const CORRECT: &str = "opencv-undistort correct -c cal.json -d in -o out";
+
+    #[test]
+    fn bool_string_and_array_values() {
+        let document = "on = true\noff = false\nname = \"a b\"\ncount = 3\nscale = 0.5\n\
+                        list = [\"x\", \"y\"]"
+            .parse::<DocumentMut>()
+            .unwrap();
+        let value = |key: &str| value_args(key, document[key].as_value().unwrap()).unwrap();
+        assert_eq!(value("on"), ["--on"]);
+        assert!(value("off").is_empty());
+        assert_eq!(value("name"), ["--name=a b"]);
+        assert_eq!(value("count"), ["--count=3"]);
+        assert_eq!(value("scale"), ["--scale=0.5"]);
+        assert_eq!(value("list"), ["--list=x", "--list=y"]);
+    }
+
+    #[test]
+    fn project_file_overrides_user_file() {
+        let files = [
+            "interpolation = \"cubic\"",
+            "[correct]\ninterpolation = \"nearest\"",
+        ];
+        assert_eq!(
+            correct(CORRECT, &files, |_| false).0,
+            Interpolation::Nearest
+        );
+        assert_eq!(
+            correct(CORRECT, &files[..1], |_| false).0,
+            Interpolation::Cubic
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_files() {
+        let line = format!("{CORRECT} --interpolation lanczos");
+        let files = ["interpolation = \"cubic\""];
+        assert_eq!(correct(&line, &files, |_| false).0, Interpolation::Lanczos);
+    }
+
+    #[test]
+    fn environment_overrides_files() {
+        let files = ["interpolation = \"cubic\""];
+        let in_env = |flag: &str| flag == "interpolation";
+        // the value itself comes from the environment when clap parses
+        assert_eq!(correct(CORRECT, &files, in_env).0, Interpolation::Linear);
+    }
+
+    #[test]
+    fn no_flag_switches_off_a_config_flag() {
+        let files = ["recursive = true"];
+        assert!(correct(CORRECT, &files, |_| false).1);
+        let line = format!("{CORRECT} --no-recursive");
+        assert!(!correct(&line, &files, |_| false).1);
+    }
+
+    #[test]
+    fn unknown_subcommand_option_is_rejected() {
+        let file = config_file("[correct]\nno_such_option = 1");
+        let result = layered(args(CORRECT), &[file.clone()], |_| false);
+        fs::remove_file(file).unwrap();
+        assert!(result.is_err());
+    }
+}
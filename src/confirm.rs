@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// set from the global --yes flag
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+pub fn assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+/// every question is answered yes without asking
+pub fn assumes_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+// ask on stderr, without a terminal there is nobody to answer and it is a no
+fn ask(question: &str) -> Result<(), Box<dyn Error>> {
+    if assumes_yes() {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!("{question} pass --yes to confirm").into());
+    }
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err("aborted".into()),
+    }
+}
+
+/// confirm before replacing an existing file
+pub fn overwrite(path: &str) -> Result<(), Box<dyn Error>> {
+    if Path::new(path).exists() {
+        ask(&format!("{path} exists, overwrite?"))?;
+    }
+    Ok(())
+}
+
+/// confirm before writing into a directory that already has files, a missing directory is
+/// left for the caller to report
+pub fn output_dir(dir: &str) -> Result<(), Box<dyn Error>> {
+    let non_empty = Path::new(dir)
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some());
+    if non_empty {
+        ask(&format!("{dir} is not empty, write into it?"))?;
+    }
+    Ok(())
+}
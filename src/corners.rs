@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use opencv::core::{Point2f, Size, Vector};
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Board, Detector, Geometry};
+
+/// board corners detected in one image
+#[derive(Serialize, Deserialize)]
+pub struct Detection {
+    pub image: String,
+    /// image points in the order of the board's object points, None when no board was found
+    pub corners: Option<Vec<[f32; 2]>>,
+}
+
+/// corners of every calibration image, written by `calibrate --corners-file` so
+/// `recalibrate` can solve again with more images without detecting the earlier ones again.
+/// The object points of every view follow from the board
+#[derive(Serialize, Deserialize)]
+pub struct Cache {
+    pub board: Geometry,
+    #[serde(default)]
+    pub thermal: bool,
+    #[serde(default)]
+    pub inverted: bool,
+    #[serde(default)]
+    pub detector: Detector,
+    pub image_size: [i32; 2],
+    pub images: Vec<Detection>,
+}
+
+impl Cache {
+    pub fn new(board: &Board, size: Size) -> Self {
+        Cache {
+            board: board.geometry(),
+            thermal: board.thermal,
+            inverted: board.inverted,
+            detector: board.detector,
+            image_size: [size.width, size.height],
+            images: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let cache: Cache =
+            serde_json::from_slice(&fs::read(path)?).map_err(|e| format!("{path}: {e}"))?;
+        let count = (cache.board.width * cache.board.height) as usize;
+        if let Some(detection) = cache.images.iter().find(|detection| {
+            detection
+                .corners
+                .as_ref()
+                .is_some_and(|corners| corners.len() != count)
+        }) {
+            return Err(format!(
+                "{path}: {} doesn't have the {count} corners of a {}x{} board",
+                detection.image, cache.board.width, cache.board.height
+            )
+            .into());
+        }
+        Ok(cache)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        Ok(fs::write(path, serde_json::to_string(self)?)?)
+    }
+
+    /// the board the corners were detected on, new images are detected with it as well
+    pub fn board(&self) -> Board {
+        Board {
+            pattern: self.board.pattern,
+            width: self.board.width,
+            height: self.board.height,
+            square_size_mm: self.board.square_size_mm,
+            thermal: self.thermal,
+            inverted: self.inverted,
+            detector: self.detector,
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        Size::new(self.image_size[0], self.image_size[1])
+    }
+
+    pub fn contains(&self, image: &str) -> bool {
+        self.images.iter().any(|detection| detection.image == image)
+    }
+
+    pub fn push(&mut self, image: &str, corners: Option<&Vector<Point2f>>) {
+        self.images.push(Detection {
+            image: image.to_string(),
+            corners: corners.map(|corners| corners.iter().map(|p| [p.x, p.y]).collect()),
+        });
+    }
+
+    /// drop the images deleted since they were detected, returns how many
+    pub fn prune(&mut self) -> usize {
+        let before = self.images.len();
+        self.images
+            .retain(|detection| Path::new(&detection.image).exists());
+        before - self.images.len()
+    }
+
+    /// corners of the images with a board, in the order they were added
+    pub fn corners(&self) -> impl Iterator<Item = (&str, Vector<Point2f>)> {
+        self.images.iter().filter_map(|detection| {
+            let corners = detection.corners.as_ref()?;
+            Some((
+                detection.image.as_str(),
+                Vector::from_iter(corners.iter().map(|[x, y]| Point2f::new(*x, *y))),
+            ))
+        })
+    }
+}
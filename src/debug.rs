@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::info;
+use opencv::calib3d::draw_chessboard_corners;
+use opencv::core::{
+    CV_8U, CV_32F, NORM_MINMAX, Point, Point2f, Rect, Scalar, Size, Vector, no_array, normalize,
+};
+use opencv::imgcodecs;
+use opencv::imgproc::{self, FILLED, LINE_AA};
+use opencv::prelude::*;
+
+use crate::ViewError;
+use crate::board::Board;
+
+// side in pixels of the cells corners are counted in for the heatmap
+const HEATMAP_CELL: i32 = 16;
+/// cells of the coverage grid over the sensor, of the figure in the log and of the
+/// `calibrate-live` overlay
+pub const GRID_COLUMNS: i32 = 8;
+pub const GRID_ROWS: i32 = 6;
+
+/// index of the coverage grid cell of a point of an image of `size`
+pub fn grid_cell(size: Size, point: Point2f) -> Option<usize> {
+    let column = (point.x * GRID_COLUMNS as f32 / size.width as f32) as i32;
+    let row = (point.y * GRID_ROWS as f32 / size.height as f32) as i32;
+    ((0..GRID_COLUMNS).contains(&column) && (0..GRID_ROWS).contains(&row))
+        .then_some((row * GRID_COLUMNS + column) as usize)
+}
+
+fn label(img: &mut Mat, text: &str, color: Scalar) -> opencv::Result<()> {
+    let scale = (img.cols().max(img.rows()) as f64 / 1200.0).max(0.7);
+    let baseline = (36.0 * scale) as i32;
+    imgproc::put_text(
+        img,
+        text,
+        Point::new(baseline / 2, baseline),
+        imgproc::FONT_HERSHEY_SIMPLEX,
+        scale,
+        color,
+        (2.0 * scale) as i32,
+        LINE_AA,
+        false,
+    )
+}
+
+// the image with its corners drawn in and what the calibration made of it, images that
+// didn't contribute get a red frame
+fn annotate(
+    board: &Board,
+    image: &str,
+    corners: Option<&Vector<Point2f>>,
+    status: &str,
+    used: bool,
+) -> opencv::Result<Mat> {
+    let mut img = board.read_image(image)?;
+    if let Some(corners) = corners {
+        draw_chessboard_corners(&mut img, board.pattern(), corners, true)?;
+    }
+    let color = if used {
+        Scalar::new(0.0, 220.0, 0.0, 0.0)
+    } else {
+        Scalar::new(0.0, 0.0, 255.0, 0.0)
+    };
+    if !used {
+        let thickness = (img.cols().max(img.rows()) / 150).max(4);
+        imgproc::rectangle(
+            &mut img,
+            Rect::new(0, 0, img.cols(), img.rows()),
+            color,
+            thickness,
+            imgproc::LINE_8,
+            0,
+        )?;
+    }
+    label(&mut img, status, color)?;
+    Ok(img)
+}
+
+// corner density of the used views over the sensor, blue for few and red for many, with
+// every corner as a dot
+fn heatmap(size: Size, corners: &[&Vector<Point2f>]) -> opencv::Result<(Mat, f64)> {
+    let grid = Size::new(
+        (size.width + HEATMAP_CELL - 1) / HEATMAP_CELL,
+        (size.height + HEATMAP_CELL - 1) / HEATMAP_CELL,
+    );
+    let mut counts = Mat::zeros(grid.height, grid.width, CV_32F)?.to_mat()?;
+    let mut covered = vec![false; (GRID_COLUMNS * GRID_ROWS) as usize];
+    for point in corners.iter().flat_map(|corners| corners.iter()) {
+        let (column, row) = (point.x as i32 / HEATMAP_CELL, point.y as i32 / HEATMAP_CELL);
+        if (0..grid.width).contains(&column) && (0..grid.height).contains(&row) {
+            *counts.at_2d_mut::<f32>(row, column)? += 1.0;
+        }
+        if let Some(cell) = grid_cell(size, point) {
+            covered[cell] = true;
+        }
+    }
+    let mut blurred = Mat::default();
+    imgproc::gaussian_blur_def(&counts, &mut blurred, Size::new(0, 0), 2.0)?;
+    let mut density = Mat::default();
+    normalize(
+        &blurred,
+        &mut density,
+        0.0,
+        255.0,
+        NORM_MINMAX,
+        CV_8U,
+        &no_array(),
+    )?;
+    let mut full = Mat::default();
+    imgproc::resize(&density, &mut full, size, 0.0, 0.0, imgproc::INTER_LINEAR)?;
+    let mut heat = Mat::default();
+    imgproc::apply_color_map(&full, &mut heat, imgproc::COLORMAP_JET)?;
+    let radius = (size.width.max(size.height) / 800).max(1);
+    for point in corners.iter().flat_map(|corners| corners.iter()) {
+        imgproc::circle(
+            &mut heat,
+            Point::new(point.x.round() as i32, point.y.round() as i32),
+            radius,
+            Scalar::new(255.0, 255.0, 255.0, 0.0),
+            FILLED,
+            LINE_AA,
+            0,
+        )?;
+    }
+    let fraction = covered.iter().filter(|covered| **covered).count() as f64 / covered.len() as f64;
+    Ok((heat, fraction))
+}
+
+fn write(path: &Path, img: &Mat) -> Result<(), Box<dyn Error>> {
+    let path = path.to_string_lossy();
+    if !imgcodecs::imwrite_def(&path, img)? {
+        return Err(format!("could not write {path}").into());
+    }
+    Ok(())
+}
+
+/// write every calibration image with its detected corners and outcome, images without a
+/// board or rejected by the reprojection threshold in a red frame, and `coverage.png`, the
+/// density of the corners the calibration was solved from over the sensor. Images below
+/// `input_dir` keep their place under `debug_dir`
+pub fn write_images(
+    debug_dir: &str,
+    input_dir: Option<&str>,
+    board: &Board,
+    size: Size,
+    detections: &[(String, Option<Vector<Point2f>>)],
+    views: &[ViewError],
+    rejected: &[ViewError],
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(debug_dir)?;
+    let used = views
+        .iter()
+        .map(|view| (view.name.as_str(), view.rms))
+        .collect::<HashMap<&str, f64>>();
+    let rejected = rejected
+        .iter()
+        .map(|view| (view.name.as_str(), view.rms))
+        .collect::<HashMap<&str, f64>>();
+    for (image, corners) in detections {
+        let (status, is_used) = match (
+            corners,
+            used.get(image.as_str()),
+            rejected.get(image.as_str()),
+        ) {
+            (None, _, _) => ("board not found".to_string(), false),
+            (_, Some(rms), _) => (format!("rms {rms:.3}px"), true),
+            (_, _, Some(rms)) => (format!("rejected, rms {rms:.3}px"), false),
+            _ => ("not used".to_string(), false),
+        };
+        let img = annotate(board, image, corners.as_ref(), &status, is_used)?;
+        let name = input_dir
+            .and_then(|dir| Path::new(image).strip_prefix(dir).ok())
+            .or_else(|| Path::new(image).file_name().map(Path::new))
+            .unwrap_or(Path::new(image));
+        let path = Path::new(debug_dir).join(name).with_extension("jpg");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write(&path, &img)?;
+    }
+    let corners = detections
+        .iter()
+        .filter(|(image, _)| used.contains_key(image.as_str()))
+        .filter_map(|(_, corners)| corners.as_ref())
+        .collect::<Vec<&Vector<Point2f>>>();
+    let (coverage, fraction) = heatmap(size, &corners)?;
+    write(&Path::new(debug_dir).join("coverage.png"), &coverage)?;
+    info!(
+        "debug images in {debug_dir}, the corners cover {:.0}% of the sensor",
+        fraction * 100.0
+    );
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use log::{info, warn};
+use opencv::calib3d::{CALIB_CB_ADAPTIVE_THRESH, CALIB_CB_FAST_CHECK, find_chessboard_corners};
+use opencv::core::{CV_64F, Point2f, Size, Vector, mean_std_dev_def};
+use opencv::imgcodecs;
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::board::{Board, Pattern};
+
+// below these the image is too flat or too blurry for corner detection
+const MIN_CONTRAST: f64 = 20.0;
+const MIN_SHARPNESS: f64 = 50.0;
+// board sizes tried when the configured one is not found
+const MIN_CORNERS: i32 = 3;
+const MAX_CORNERS: i32 = 16;
+// longest side of the image searched for other board sizes
+const SEARCH_SIDE: i32 = 960;
+
+fn std_dev(image: &Mat) -> opencv::Result<f64> {
+    let mut mean = Vector::<f64>::new();
+    let mut std_dev = Vector::<f64>::new();
+    mean_std_dev_def(image, &mut mean, &mut std_dev)?;
+    std_dev.get(0)
+}
+
+// largest board the detector finds in the image, by interior corners
+fn find_pattern(gray: &Mat, skip: Size) -> opencv::Result<Option<Size>> {
+    let mut sizes = (MIN_CORNERS..=MAX_CORNERS)
+        .flat_map(|width| (MIN_CORNERS..=width).map(move |height| Size::new(width, height)))
+        .filter(|size| *size != skip && Size::new(size.height, size.width) != skip)
+        .collect::<Vec<Size>>();
+    sizes.sort_by_key(|size| -(size.width * size.height));
+    let mut corners = Vector::<Point2f>::new();
+    for size in sizes {
+        if find_chessboard_corners(
+            gray,
+            size,
+            &mut corners,
+            CALIB_CB_ADAPTIVE_THRESH | CALIB_CB_FAST_CHECK,
+        )? {
+            return Ok(Some(size));
+        }
+    }
+    Ok(None)
+}
+
+/// after no board was detected in any image, look at one of them and log likely causes and
+/// fixes
+pub fn no_boards(image: &str, board: &Board) -> opencv::Result<()> {
+    let img = imgcodecs::imread_def(image)?;
+    if img.empty() {
+        warn!("{image} could not be read, check that the directory has images");
+        return Ok(());
+    }
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(&img, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    info!("looking at {image} for why no board was found");
+
+    let contrast = std_dev(&gray)?;
+    if contrast < MIN_CONTRAST {
+        warn!("low contrast (std dev {contrast:.1}), improve the lighting or exposure");
+    }
+    let mut laplacian = Mat::default();
+    imgproc::laplacian_def(&gray, &mut laplacian, CV_64F)?;
+    let sharpness = std_dev(&laplacian)?.powi(2);
+    if sharpness < MIN_SHARPNESS {
+        warn!("image looks blurred (laplacian variance {sharpness:.1}), check focus and motion");
+    }
+
+    if board.pattern != Pattern::Chessboard {
+        warn!(
+            "no {}x{} circle grid found, check --pattern and the grid size and that the circles are dark on a white background",
+            board.width, board.height
+        );
+        return Ok(());
+    }
+    let pattern = board.pattern();
+    let scale = SEARCH_SIDE as f64 / gray.cols().max(gray.rows()) as f64;
+    let mut small = Mat::default();
+    if scale < 1.0 {
+        imgproc::resize(
+            &gray,
+            &mut small,
+            Size::default(),
+            scale,
+            scale,
+            imgproc::INTER_AREA,
+        )?;
+    } else {
+        small = gray;
+    }
+    match find_pattern(&small, pattern)? {
+        Some(found) => warn!(
+            "pattern size mismatch, detected ~{}x{} interior corners instead of {}x{}; try --board-width {} --board-height {}",
+            found.width, found.height, pattern.width, pattern.height, found.width, found.height
+        ),
+        None if contrast >= MIN_CONTRAST && sharpness >= MIN_SHARPNESS => warn!(
+            "no chessboard between {MIN_CORNERS}x{MIN_CORNERS} and {MAX_CORNERS}x{MAX_CORNERS} corners found, check that the whole board is in the frame and has a white border"
+        ),
+        None => {}
+    }
+    Ok(())
+}
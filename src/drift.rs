@@ -0,0 +1,223 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::Calibration;
+
+// size of one plot panel in the svg
+const PANEL_WIDTH: f64 = 640.0;
+const PANEL_HEIGHT: f64 = 120.0;
+const MARGIN: f64 = 60.0;
+
+/// one calibration session of a camera, a line of its history file
+#[derive(Serialize, Deserialize)]
+struct Session {
+    /// unix time the calibration was recorded
+    recorded: f64,
+    rms: f64,
+    calibration: Calibration,
+}
+
+// the id names a file in the history dir, it can't lead out of it
+fn history_file(history_dir: &str, camera_id: &str) -> Result<String, Box<dyn Error>> {
+    if camera_id.is_empty()
+        || camera_id.contains(['/', '\\'])
+        || camera_id.contains("..")
+        || Path::new(camera_id).is_absolute()
+    {
+        return Err(format!("camera id {camera_id:?} must not be empty or hold a path").into());
+    }
+    Ok(format!("{history_dir}/{camera_id}.jsonl"))
+}
+
+// text for an svg element
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// append a calibration to the history of a camera, earlier sessions are never rewritten
+pub fn record(
+    history_dir: &str,
+    camera_id: &str,
+    calibration: &Calibration,
+    rms: f64,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(history_dir)?;
+    let session = Session {
+        recorded: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64(),
+        rms,
+        calibration: calibration.clone(),
+    };
+    let path = history_file(history_dir, camera_id)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(&session)?)?;
+    file.sync_data()?;
+    info!("calibration added to the history {path}");
+    Ok(())
+}
+
+// yyyy-mm-dd of a unix time
+fn date(unix: f64) -> String {
+    // days to civil date, http://howardhinnant.github.io/date_algorithms.html
+    let z = (unix / 86400.0).floor() as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// tracked values of a session
+fn values(session: &Session) -> [f64; 7] {
+    let k = &session.calibration.camera_matrix;
+    let d = |i: usize| {
+        session
+            .calibration
+            .dist_coeffs
+            .get(i)
+            .copied()
+            .unwrap_or(0.0)
+    };
+    [k[0], k[4], k[2], k[5], d(0), d(1), session.rms]
+}
+
+const NAMES: [&str; 7] = ["fx", "fy", "cx", "cy", "k1", "k2", "rms"];
+
+// one panel per value over time, sessions as points on a line
+fn plot(camera_id: &str, sessions: &[Session]) -> String {
+    let times = sessions.iter().map(|s| s.recorded).collect::<Vec<f64>>();
+    let (first, last) = (times[0], times[times.len() - 1]);
+    let span = (last - first).max(1.0);
+    let mut svg = String::new();
+    let height = MARGIN + NAMES.len() as f64 * (PANEL_HEIGHT + MARGIN);
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{height}" font-family="sans-serif" font-size="12">"#,
+        PANEL_WIDTH + 2.0 * MARGIN
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{MARGIN}" y="{}" font-size="16">{} calibration drift, {} to {}</text>"#,
+        MARGIN / 2.0,
+        escape(camera_id),
+        date(first),
+        date(last)
+    );
+    for (panel, name) in NAMES.iter().enumerate() {
+        let series = sessions
+            .iter()
+            .map(|s| values(s)[panel])
+            .collect::<Vec<f64>>();
+        let (min, max) = series.iter().fold((f64::MAX, f64::MIN), |(min, max), v| {
+            (min.min(*v), max.max(*v))
+        });
+        let range = if max > min { max - min } else { 1.0 };
+        let top = MARGIN + panel as f64 * (PANEL_HEIGHT + MARGIN);
+        let points = times
+            .iter()
+            .zip(&series)
+            .map(|(t, v)| {
+                (
+                    MARGIN + (t - first) / span * PANEL_WIDTH,
+                    top + PANEL_HEIGHT - (v - min) / range * PANEL_HEIGHT,
+                )
+            })
+            .collect::<Vec<(f64, f64)>>();
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{MARGIN}" y="{top}" width="{PANEL_WIDTH}" height="{PANEL_HEIGHT}" fill="none" stroke="#ccc"/>"##
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">{name}</text><text x="{}" y="{}" text-anchor="end">{max:.5}</text><text x="{}" y="{}" text-anchor="end">{min:.5}</text>"#,
+            MARGIN,
+            top - 6.0,
+            MARGIN - 4.0,
+            top + 12.0,
+            MARGIN - 4.0,
+            top + PANEL_HEIGHT
+        );
+        let line = points
+            .iter()
+            .map(|(x, y)| format!("{x:.1},{y:.1}"))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let _ = writeln!(
+            svg,
+            r##"<polyline points="{line}" fill="none" stroke="#1f77b4"/>"##
+        );
+        for (x, y) in points {
+            let _ = writeln!(
+                svg,
+                r##"<circle cx="{x:.1}" cy="{y:.1}" r="3" fill="#1f77b4"/>"##
+            );
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// table of the sessions of a camera and the change from the first to the last one, with an
+/// optional svg plot of every value over time
+pub fn report(
+    history_dir: &str,
+    camera_id: &str,
+    plot_file: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let path = history_file(history_dir, camera_id)?;
+    if !Path::new(&path).exists() {
+        return Err(format!("no history for camera {camera_id} in {history_dir}").into());
+    }
+    let mut sessions = fs::read_to_string(&path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str::<Session>)
+        .collect::<Result<Vec<Session>, _>>()?;
+    sessions.sort_by(|a, b| a.recorded.total_cmp(&b.recorded));
+    let (Some(first), Some(last)) = (sessions.first(), sessions.last()) else {
+        return Err(format!("{path} has no sessions").into());
+    };
+
+    println!(
+        "{:<10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+        "date", NAMES[0], NAMES[1], NAMES[2], NAMES[3], NAMES[4], NAMES[5], NAMES[6]
+    );
+    for session in &sessions {
+        let [fx, fy, cx, cy, k1, k2, rms] = values(session);
+        println!(
+            "{:<10} {fx:>10.2} {fy:>10.2} {cx:>10.2} {cy:>10.2} {k1:>10.5} {k2:>10.5} {rms:>8.4}",
+            date(session.recorded)
+        );
+    }
+    let change = values(first)
+        .iter()
+        .zip(values(last))
+        .map(|(a, b)| b - a)
+        .collect::<Vec<f64>>();
+    println!(
+        "{:<10} {:>+10.2} {:>+10.2} {:>+10.2} {:>+10.2} {:>+10.5} {:>+10.5} {:>+8.4}",
+        "change", change[0], change[1], change[2], change[3], change[4], change[5], change[6]
+    );
+
+    if let Some(plot_file) = plot_file {
+        fs::write(plot_file, plot(camera_id, &sessions))?;
+        info!("drift plot {plot_file}");
+    }
+    Ok(())
+}
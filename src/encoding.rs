@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use clap::{Args, ValueEnum};
+use log::debug;
+use opencv::core::{CV_8U, CV_16U, Vector};
+use opencv::imgcodecs;
+use opencv::prelude::*;
+
+use crate::exif::Metadata;
+
+/// image format of the corrected images
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Jpg,
+    Png,
+    Tiff,
+    Webp,
+    Bmp,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::Tiff => "tif",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Bmp => "bmp",
+        }
+    }
+
+    // formats without 16 bit samples
+    fn eight_bit(extension: &str) -> bool {
+        matches!(extension, "jpg" | "jpeg" | "webp" | "bmp")
+    }
+}
+
+/// how the corrected images are encoded
+#[derive(Args, Clone, Debug, Default)]
+pub struct Encoding {
+    /// transcode the corrected images, by default each keeps the format of its input
+    #[arg(long, value_enum)]
+    pub output_format: Option<OutputFormat>,
+    /// jpeg and webp quality, 1 to 100
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..=100))]
+    pub jpeg_quality: Option<i32>,
+    /// png zlib compression level, 0 to 9
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..=9))]
+    pub png_compression: Option<i32>,
+}
+
+impl Encoding {
+    /// file extension of the corrected image of `input`
+    pub fn extension(&self, input: &Path) -> String {
+        match self.output_format {
+            Some(format) => format.extension().to_string(),
+            None => input.extension().map_or("jpg".to_string(), |ext| {
+                ext.to_string_lossy().to_lowercase()
+            }),
+        }
+    }
+
+    fn params(&self, extension: &str) -> Vector<i32> {
+        let mut params = Vector::new();
+        if let Some(quality) = self.jpeg_quality {
+            match extension {
+                "jpg" | "jpeg" => params.extend([imgcodecs::IMWRITE_JPEG_QUALITY, quality]),
+                "webp" => params.extend([imgcodecs::IMWRITE_WEBP_QUALITY, quality]),
+                _ => {}
+            }
+        }
+        if let Some(compression) = self.png_compression.filter(|_| extension == "png") {
+            params.extend([imgcodecs::IMWRITE_PNG_COMPRESSION, compression]);
+        }
+        params
+    }
+
+    /// the image in the format of `extension`, with the exif and xmp of the source carried
+    /// into jpeg and png outputs
+    pub fn encode(
+        &self,
+        img: &Mat,
+        extension: &str,
+        metadata: &Metadata,
+    ) -> opencv::Result<Vec<u8>> {
+        let mut eight_bit = Mat::default();
+        let img = if img.depth() == CV_16U && OutputFormat::eight_bit(extension) {
+            img.convert_to(&mut eight_bit, CV_8U, 1.0 / 257.0, 0.0)?;
+            &eight_bit
+        } else {
+            img
+        };
+        let mut encoded = Vector::<u8>::new();
+        imgcodecs::imencode(
+            &format!(".{extension}"),
+            img,
+            &mut encoded,
+            &self.params(extension),
+        )?;
+        let mut encoded = encoded.to_vec();
+        match extension {
+            "jpg" | "jpeg" => metadata.write_jpeg(&mut encoded),
+            "png" => metadata.write_png(&mut encoded),
+            _ if !metadata.is_empty() => debug!("no exif or xmp is written to {extension} images"),
+            _ => {}
+        }
+        Ok(encoded)
+    }
+}
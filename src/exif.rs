@@ -0,0 +1,327 @@
+use std::fs;
+
+use log::warn;
+
+// tiff tags of the exif sub-ifd pointer and the lens focal length
+const EXIF_IFD: u16 = 0x8769;
+const FOCAL_LENGTH: u16 = 0x920a;
+const ORIENTATION: u16 = 0x0112;
+const XMP_TAG: u16 = 0x02bc;
+
+// headers of the exif and xmp app1 segments
+const EXIF: &[u8] = b"Exif\0\0";
+const XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+// keyword of the xmp iTXt chunk of a png
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// tiff structure inside the exif app1 segment
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    // value or value offset of a tag in the ifd at `ifd`
+    fn entry(&self, ifd: usize, tag: u16) -> Option<u32> {
+        let count = self.u16(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|entry| self.u16(*entry) == Some(tag))
+            .and_then(|entry| self.u32(entry + 8))
+    }
+}
+
+// marker and payload of the segments of a jpeg before the start of scan
+fn segments(jpeg: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return segments;
+    }
+    let mut offset = 2;
+    // image data follows the start of scan
+    while let (Some(0xff), Some(&marker)) = (jpeg.get(offset), jpeg.get(offset + 1)) {
+        let Some(length) = jpeg
+            .get(offset + 2..offset + 4)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+        else {
+            break;
+        };
+        let Some(segment) = jpeg.get(offset + 4..offset + 2 + length) else {
+            break;
+        };
+        if marker == 0xda {
+            break;
+        }
+        segments.push((marker, segment));
+        offset += 2 + length;
+    }
+    segments
+}
+
+// the tiff data of the first exif app1 segment of a jpeg
+fn exif_segment(jpeg: &[u8]) -> Option<&[u8]> {
+    segments(jpeg)
+        .into_iter()
+        .find(|(marker, segment)| *marker == 0xe1 && segment.starts_with(EXIF))
+        .map(|(_, segment)| &segment[EXIF.len()..])
+}
+
+// xmp packet of the first xmp app1 segment of a jpeg
+fn xmp_segment(jpeg: &[u8]) -> Option<&[u8]> {
+    segments(jpeg)
+        .into_iter()
+        .find(|(marker, segment)| *marker == 0xe1 && segment.starts_with(XMP))
+        .map(|(_, segment)| &segment[XMP.len()..])
+}
+
+// type and data of the chunks of a png
+fn png_chunks(png: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    if !png.starts_with(PNG) {
+        return chunks;
+    }
+    let mut offset = PNG.len();
+    // length, type, data and crc
+    while let Some(length) = png.get(offset..offset + 4) {
+        let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+        let (Some(kind), Some(data)) = (
+            png.get(offset + 4..offset + 8),
+            png.get(offset + 8..offset + 8 + length),
+        ) else {
+            break;
+        };
+        chunks.push((kind, data));
+        offset += 12 + length;
+    }
+    chunks
+}
+
+// the xmp packet of an uncompressed iTXt chunk
+fn png_xmp(text: &[u8]) -> Option<&[u8]> {
+    let text = text.strip_prefix(XMP_KEYWORD)?;
+    // compression flag and method, then the language and translated keyword ended by nul
+    let (&[0, _], text) = text.split_at_checked(2)? else {
+        return None;
+    };
+    let language = text.iter().position(|byte| *byte == 0)?;
+    let text = &text[language + 1..];
+    let keyword = text.iter().position(|byte| *byte == 0)?;
+    Some(&text[keyword + 1..])
+}
+
+// fourcc and data of the chunks of a webp
+fn riff_chunks(webp: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    if !(webp.starts_with(b"RIFF") && webp.get(8..12) == Some(b"WEBP")) {
+        return chunks;
+    }
+    let mut offset = 12;
+    while let Some(length) = webp.get(offset + 4..offset + 8) {
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let Some(data) = webp.get(offset + 8..offset + 8 + length) else {
+            break;
+        };
+        chunks.push((&webp[offset..offset + 4], data));
+        // chunks are padded to an even length
+        offset += 8 + length + length % 2;
+    }
+    chunks
+}
+
+// a tiff image with exif or xmp in its first ifd
+fn tiff_has_metadata(tiff: &[u8]) -> bool {
+    let little_endian = tiff.starts_with(b"II*\0");
+    if !little_endian && !tiff.starts_with(b"MM\0*") {
+        return false;
+    }
+    let tiff = Tiff {
+        data: tiff,
+        little_endian,
+    };
+    tiff.u32(4).is_some_and(|ifd0| {
+        [EXIF_IFD, XMP_TAG]
+            .iter()
+            .any(|tag| tiff.entry(ifd0 as usize, *tag).is_some())
+    })
+}
+
+/// exif and xmp of a jpeg, png or webp, the exif orientation reset to upright since opencv
+/// rotates the pixels on reading
+pub struct Metadata {
+    /// tiff data of the exif segment
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// metadata of the encoded image read from `path`, a warning when it has some that isn't
+    /// carried over
+    pub fn read(path: &str, image: &[u8]) -> Self {
+        let (exif, xmp) = if image.starts_with(PNG) {
+            let chunks = png_chunks(image);
+            (
+                chunks
+                    .iter()
+                    .find(|(kind, _)| *kind == b"eXIf")
+                    .map(|(_, data)| *data),
+                chunks
+                    .iter()
+                    .filter(|(kind, _)| *kind == b"iTXt")
+                    .find_map(|(_, text)| png_xmp(text)),
+            )
+        } else if image.starts_with(b"RIFF") {
+            let chunks = riff_chunks(image);
+            (
+                chunks
+                    .iter()
+                    .find(|(fourcc, _)| *fourcc == b"EXIF")
+                    .map(|(_, data)| data.strip_prefix(EXIF).unwrap_or(*data)),
+                chunks
+                    .iter()
+                    .find(|(fourcc, _)| *fourcc == b"XMP ")
+                    .map(|(_, data)| *data),
+            )
+        } else {
+            if tiff_has_metadata(image) {
+                warn!("{path}: the exif and xmp of tiff images are not carried over");
+            }
+            (exif_segment(image), xmp_segment(image))
+        };
+        let exif = exif.map(|data| {
+            let mut data = data.to_vec();
+            upright(&mut data);
+            data
+        });
+        Metadata {
+            exif,
+            xmp: xmp.map(<[u8]>::to_vec),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none()
+    }
+
+    /// app1 segments after the SOI and a JFIF app0 of an encoded jpeg
+    pub fn write_jpeg(&self, jpeg: &mut Vec<u8>) {
+        if !jpeg.starts_with(&[0xff, 0xd8]) {
+            return;
+        }
+        let mut inserted = Vec::new();
+        let payloads = [(EXIF, &self.exif), (XMP, &self.xmp)];
+        for (header, data) in payloads {
+            let Some(data) = data else { continue };
+            // the segment length counts its own two bytes and can't exceed 64k
+            let length = header.len() + data.len() + 2;
+            if length > u16::MAX as usize {
+                continue;
+            }
+            inserted.extend([0xff, 0xe1]);
+            inserted.extend((length as u16).to_be_bytes());
+            inserted.extend(header);
+            inserted.extend(data);
+        }
+        let at = match segments(jpeg).first() {
+            Some((0xe0, app0)) => 4 + app0.len() + 2,
+            _ => 2,
+        };
+        jpeg.splice(at..at, inserted);
+    }
+
+    /// eXIf and xmp iTXt chunks after the IHDR chunk of an encoded png
+    pub fn write_png(&self, png: &mut Vec<u8>) {
+        // signature, then the IHDR chunk of 13 bytes with its length, type and crc
+        let at = 8 + 4 + 4 + 13 + 4;
+        if png.len() < at || &png[12..16] != b"IHDR" {
+            return;
+        }
+        let mut inserted = Vec::new();
+        if let Some(exif) = &self.exif {
+            inserted.extend(png_chunk(b"eXIf", exif));
+        }
+        if let Some(xmp) = &self.xmp {
+            // keyword, no compression, no language and translated keyword
+            let mut text = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+            text.extend(xmp);
+            inserted.extend(png_chunk(b"iTXt", &text));
+        }
+        png.splice(at..at, inserted);
+    }
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(kind);
+    chunk.extend(data);
+    let crc = crc32fast::hash(&chunk[4..]);
+    chunk.extend(crc.to_be_bytes());
+    chunk
+}
+
+// set the orientation tag of ifd0 to 1, pixels are stored upright
+fn upright(data: &mut [u8]) {
+    let tiff = Tiff {
+        data,
+        little_endian: data.starts_with(b"II"),
+    };
+    let Some(ifd0) = tiff.u32(4).map(|ifd0| ifd0 as usize) else {
+        return;
+    };
+    let Some(count) = tiff.u16(ifd0) else {
+        return;
+    };
+    let little_endian = tiff.little_endian;
+    let entry = (0..count as usize)
+        .map(|i| ifd0 + 2 + i * 12)
+        .find(|entry| tiff.u16(*entry) == Some(ORIENTATION));
+    if let Some(value) = entry.map(|entry| entry + 8)
+        && let Some(bytes) = data.get_mut(value..value + 2)
+    {
+        bytes.copy_from_slice(&if little_endian {
+            1u16.to_le_bytes()
+        } else {
+            1u16.to_be_bytes()
+        });
+    }
+}
+
+/// lens focal length in mm recorded by the camera, none without exif or the tag
+pub fn focal_length(path: &str) -> std::io::Result<Option<f64>> {
+    let jpeg = fs::read(path)?;
+    let Some(data) = exif_segment(&jpeg) else {
+        return Ok(None);
+    };
+    let tiff = Tiff {
+        data,
+        little_endian: data.starts_with(b"II"),
+    };
+    let focal_length = tiff
+        .u32(4)
+        .and_then(|ifd0| tiff.entry(ifd0 as usize, EXIF_IFD))
+        .and_then(|exif| tiff.entry(exif as usize, FOCAL_LENGTH))
+        .and_then(|rational| {
+            let numerator = tiff.u32(rational as usize)?;
+            let denominator = tiff.u32(rational as usize + 4)?;
+            (denominator != 0).then(|| numerator as f64 / denominator as f64)
+        });
+    Ok(focal_length)
+}
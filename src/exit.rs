@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt;
+
+/// outcomes the command line exits with a code of its own for, besides 0 for success, 1 for
+/// any other error and 2 for invalid arguments or configuration
+#[derive(Debug, Clone, Copy)]
+pub enum Code {
+    /// no calibration board detected in any image
+    NoBoards = 3,
+    /// some files could not be processed, the others were written
+    PartialFailure = 4,
+    /// calibration written but its rms error is above `--max-rms`
+    QualityBelowThreshold = 5,
+    /// a file or directory could not be read or written
+    Io = 6,
+    /// not a single input could be processed
+    AllFailed = 7,
+}
+
+/// error carrying the exit code of the outcome
+#[derive(Debug)]
+pub struct Failure {
+    code: Code,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Failure {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> Code {
+        self.code
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failure {}
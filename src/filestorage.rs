@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::ValueEnum;
+use opencv::core::{CV_64F, FileStorage, FileStorage_Mode};
+use opencv::prelude::*;
+
+use crate::{Calibration, CameraModel, SCHEMA_VERSION, mat_to_vec, omnidir};
+
+/// file format of a calibration
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// json of this tool, the only one keeping the board and the provenance
+    Json,
+    /// opencv FileStorage yaml, as read by ros camera_calibration, kalibr and most tools
+    Yaml,
+    /// opencv FileStorage xml
+    Xml,
+}
+
+impl Format {
+    /// format by the file extension, .yaml, .yml and .xml are FileStorage, anything else json
+    pub fn of(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("xml") => Format::Xml,
+            _ => Format::Json,
+        }
+    }
+
+    /// format by the start of the content, `None` when it doesn't tell
+    pub fn sniff(content: &[u8]) -> Option<Self> {
+        let content = content.trim_ascii_start();
+        if content.starts_with(b"%YAML") {
+            Some(Format::Yaml)
+        } else if content.starts_with(b"<?xml") {
+            Some(Format::Xml)
+        } else if content.starts_with(b"{") {
+            Some(Format::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// calibration from the `camera_matrix`, `distortion_coefficients` and `image_width`,
+/// `image_height` nodes of a FileStorage file, pinhole unless a `camera_model` node says
+/// otherwise
+pub fn read(path: &str) -> Result<Calibration, Box<dyn Error>> {
+    let fs = FileStorage::new(path, FileStorage_Mode::READ as i32, "")?;
+    if !fs.is_opened()? {
+        return Err(format!("could not open {path}").into());
+    }
+    let matrix = |name: &str| -> Result<Vec<f64>, Box<dyn Error>> {
+        let node = fs.get(name)?;
+        if node.is_none()? {
+            return Err(format!("{path} has no {name}").into());
+        }
+        let mut mat = Mat::default();
+        node.mat()?.convert_to_def(&mut mat, CV_64F)?;
+        Ok(mat_to_vec(&mat)?)
+    };
+    let optional_matrix = |name: &str| -> Result<Option<Vec<f64>>, Box<dyn Error>> {
+        if fs.get(name)?.is_none()? {
+            return Ok(None);
+        }
+        matrix(name).map(Some)
+    };
+    let number = |name: &str| -> opencv::Result<Option<f64>> {
+        let node = fs.get(name)?;
+        Ok(if node.is_none()? {
+            None
+        } else {
+            Some(node.to_f64()?)
+        })
+    };
+
+    let model = match fs.get("camera_model")? {
+        node if node.is_string()? => {
+            let name = node.to_string()?;
+            CameraModel::from_str(&name, true)
+                .map_err(|_| format!("{path}: unknown camera_model {name}"))?
+        }
+        _ => CameraModel::Pinhole,
+    };
+    let camera_matrix = matrix("camera_matrix")?;
+    if camera_matrix.len() != 9 {
+        return Err(format!("{path}: camera_matrix is not 3x3").into());
+    }
+    let image_size = match (number("image_width")?, number("image_height")?) {
+        (Some(width), Some(height)) => Some([width as i32, height as i32]),
+        _ => None,
+    };
+    let omnidir = match (model, number("xi")?) {
+        (CameraModel::Omnidir, Some(xi)) => Some(omnidir::Omnidir {
+            xi,
+            rectification: omnidir::Rectification::default(),
+        }),
+        (CameraModel::Omnidir, None) => return Err(format!("{path} has no xi").into()),
+        _ => None,
+    };
+    Ok(Calibration {
+        // files of other tools are read as the current layout
+        version: number("schema_version")?.map_or(SCHEMA_VERSION, |version| version as u32),
+        model,
+        camera_matrix,
+        dist_coeffs: matrix("distortion_coefficients")?,
+        image_size,
+        board: None,
+        omnidir,
+        rms: number("avg_reprojection_error")?,
+        new_camera_matrix: optional_matrix("new_camera_matrix")?,
+        roi: optional_matrix("roi")?.and_then(|roi| match roi[..] {
+            [x, y, width, height] => Some([x as i32, y as i32, width as i32, height as i32]),
+            _ => None,
+        }),
+        provenance: None,
+    })
+}
+
+/// write the nodes of the opencv calibration sample, the board and the provenance are
+/// dropped
+pub fn write(path: &str, calibration: &Calibration, format: Format) -> Result<(), Box<dyn Error>> {
+    let flags = FileStorage_Mode::WRITE as i32
+        | match format {
+            Format::Xml => FileStorage_Mode::FORMAT_XML,
+            _ => FileStorage_Mode::FORMAT_YAML,
+        } as i32;
+    let mut fs = FileStorage::new(path, flags, "")?;
+    if !fs.is_opened()? {
+        return Err(format!("could not write {path}").into());
+    }
+    fs.write("schema_version", calibration.version as i64)?;
+    if let Some([width, height]) = calibration.image_size {
+        fs.write("image_width", width as i64)?;
+        fs.write("image_height", height as i64)?;
+    }
+    if let Some(model) = calibration.model.to_possible_value() {
+        fs.write_str("camera_model", model.get_name())?;
+    }
+    let (mtx, dist) = calibration.matrices()?;
+    fs.write_mat("camera_matrix", &mtx)?;
+    fs.write_mat("distortion_coefficients", &dist)?;
+    if let Some(new_camera_matrix) = &calibration.new_camera_matrix {
+        fs.write_mat(
+            "new_camera_matrix",
+            &Mat::new_rows_cols_with_data(3, 3, new_camera_matrix)?,
+        )?;
+    }
+    if let Some(roi) = &calibration.roi {
+        fs.write_mat("roi", &Mat::new_rows_cols_with_data(1, 4, roi)?)?;
+    }
+    if let Some(omnidir) = calibration.omnidir {
+        fs.write_f64("xi", omnidir.xi)?;
+    }
+    if let Some(rms) = calibration.rms {
+        fs.write_f64("avg_reprojection_error", rms)?;
+    }
+    fs.release()?;
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use opencv::calib3d::{
+    Fisheye_CALIB_FIX_SKEW, Fisheye_CALIB_RECOMPUTE_EXTRINSIC, calibrate,
+    fisheye_project_points_vec_def, fisheye_undistort_points,
+};
+use opencv::core::{
+    Point2f, Point3f, Size, TermCriteria, TermCriteria_COUNT, TermCriteria_EPS, ToInputArray,
+    ToOutputArray, Vector, no_array,
+};
+use opencv::prelude::*;
+
+use crate::view_rms;
+
+/// single camera fisheye calibration with all four distortion terms, rms error, camera matrix,
+/// k1..k4 and the rms reprojection error of every view
+pub fn calibrate_camera(
+    objpoints: &Vector<Vector<Point3f>>,
+    imgpoints: &Vector<Vector<Point2f>>,
+    size: Size,
+) -> opencv::Result<(f64, Mat, Mat, Vec<f64>)> {
+    let mut mtx = Mat::default();
+    let mut dist = Mat::default();
+    let mut rvecs = Vector::<Mat>::new();
+    let mut tvecs = Vector::<Mat>::new();
+    let rms = calibrate(
+        objpoints,
+        imgpoints,
+        size,
+        &mut mtx,
+        &mut dist,
+        &mut rvecs,
+        &mut tvecs,
+        Fisheye_CALIB_RECOMPUTE_EXTRINSIC | Fisheye_CALIB_FIX_SKEW,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 100, 1e-6)?,
+    )?;
+    let view_errors = (0..objpoints.len())
+        .map(|i| {
+            let mut projected = Vector::<Point2f>::new();
+            fisheye_project_points_vec_def(
+                &objpoints.get(i)?,
+                &mut projected,
+                &rvecs.get(i)?,
+                &tvecs.get(i)?,
+                &mtx,
+                &dist,
+            )?;
+            Ok(view_rms(&projected, &imgpoints.get(i)?))
+        })
+        .collect::<opencv::Result<Vec<f64>>>()?;
+    Ok((rms, mtx, dist, view_errors))
+}
+
+/// pixel positions in the undistorted image, the new camera matrix is the calibrated one
+pub fn undistort_points(
+    distorted: &impl ToInputArray,
+    undistorted: &mut impl ToOutputArray,
+    mtx: &Mat,
+    dist: &Mat,
+) -> opencv::Result<()> {
+    fisheye_undistort_points(
+        distorted,
+        undistorted,
+        mtx,
+        dist,
+        &no_array(),
+        mtx,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 10, 1e-8)?,
+    )
+}
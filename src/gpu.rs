@@ -0,0 +1,34 @@
+use clap::ValueEnum;
+use log::{info, warn};
+
+/// device the remap runs on instead of the cpu
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Gpu {
+    /// transparent OpenCL through UMat, any OpenCL capable gpu
+    Opencl,
+    /// opencv's cuda warping module, nvidia gpus, no lanczos interpolation
+    #[cfg(feature = "cuda")]
+    Cuda,
+}
+
+/// the gpu when opencv can use it, None with a warning when the remap stays on the cpu
+pub fn available(gpu: Gpu) -> opencv::Result<Option<Gpu>> {
+    match gpu {
+        Gpu::Opencl => {
+            if !opencv::core::have_opencl()? {
+                warn!("opencv has no OpenCL device, remapping on the cpu");
+                return Ok(None);
+            }
+            opencv::core::set_use_opencl(true)?;
+        }
+        #[cfg(feature = "cuda")]
+        Gpu::Cuda => {
+            if opencv::core::get_cuda_enabled_device_count()? == 0 {
+                warn!("opencv has no cuda device, remapping on the cpu");
+                return Ok(None);
+            }
+        }
+    }
+    info!("remapping with {gpu:?}");
+    Ok(Some(gpu))
+}
@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use log::info;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::service::Undistorters;
+use crate::threads;
+
+pub mod pb {
+    tonic::include_proto!("undistort");
+}
+
+use pb::Frame;
+use pb::undistorter_server::{Undistorter, UndistorterServer};
+
+struct UndistortService {
+    undistorters: Arc<Undistorters>,
+}
+
+#[tonic::async_trait]
+impl Undistorter for UndistortService {
+    type UndistortStream = ReceiverStream<Result<Frame, Status>>;
+
+    async fn undistort(
+        &self,
+        request: Request<Streaming<Frame>>,
+    ) -> Result<Response<Self::UndistortStream>, Status> {
+        let mut frames = request.into_inner();
+        let undistorters = self.undistorters.clone();
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let frame = match frames.message().await {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                let undistorters = undistorters.clone();
+                // opencv work is blocking, keep it off the io threads
+                let corrected = tokio::task::spawn_blocking(move || {
+                    undistorters
+                        .undistort_encoded(&frame.calibration_id, &frame.data, &frame.format)
+                        .map(|data| Frame { data, ..frame })
+                        .map_err(|e| Status::invalid_argument(e.to_string()))
+                })
+                .await
+                .unwrap_or_else(|e| Err(Status::internal(e.to_string())));
+                if tx.send(corrected).await.is_err() {
+                    // client went away
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+pub fn serve(calibration_dir: &str, port: u16) -> Result<(), Box<dyn Error>> {
+    let undistorters = Undistorters::load_dir(calibration_dir)?;
+    info!("calibrations: {}", undistorters.ids().join(", "));
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let service = UndistortService {
+        undistorters: Arc::new(undistorters),
+    };
+    let runtime = threads::runtime()?;
+    runtime.block_on(async {
+        info!("grpc undistort service listening on {address}");
+        Server::builder()
+            .add_service(UndistorterServer::new(service))
+            .serve(address)
+            .await
+    })?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::time::Instant;
+
+use indicatif::HumanDuration;
+use log::info;
+use opencv::imgproc;
+use opencv::prelude::*;
+use opencv::videoio::{CAP_GSTREAMER, CAP_PROP_FPS, VideoCapture, VideoWriter};
+
+use crate::{Calibration, progress, undistort_maps};
+
+pub fn run(
+    calibration_file: &str,
+    input_pipeline: &str,
+    output_pipeline: &str,
+    fps: f64,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+
+    let mut capture = VideoCapture::from_file(input_pipeline, CAP_GSTREAMER)?;
+    if !capture.is_opened()? {
+        return Err(format!("could not open input pipeline {input_pipeline}").into());
+    }
+    // frames and maps are allocated once and reused for the whole stream
+    let mut frame = Mat::default();
+    let mut corrected = Mat::default();
+    if !capture.read(&mut frame)? {
+        return Err("input pipeline produced no frames".into());
+    }
+    let size = frame.size()?;
+    let (mapx, mapy) = undistort_maps(&calibration, size)?;
+    let fps = match capture.get(CAP_PROP_FPS)? {
+        reported if reported > 0.0 => reported,
+        _ => fps,
+    };
+    // fourcc 0 hands raw frames to appsrc
+    let mut writer = VideoWriter::new_with_backend(
+        output_pipeline,
+        CAP_GSTREAMER,
+        0,
+        fps,
+        size,
+        frame.channels() == 3,
+    )?;
+    if !writer.is_opened()? {
+        return Err(format!("could not open output pipeline {output_pipeline}").into());
+    }
+
+    let pb = progress::spinner();
+    let started = Instant::now();
+    let mut frames = 0u64;
+    loop {
+        imgproc::remap_def(&frame, &mut corrected, &mapx, &mapy, imgproc::INTER_LINEAR)?;
+        writer.write(&corrected)?;
+        frames += 1;
+        pb.set_message(format!(
+            "{frames} frames in {}",
+            HumanDuration(started.elapsed())
+        ));
+        pb.tick();
+        if !capture.read(&mut frame)? || frame.empty() {
+            break;
+        }
+    }
+    writer.release()?;
+    pb.finish_and_clear();
+    info!(
+        "{frames} frames done in {}",
+        HumanDuration(started.elapsed())
+    );
+    Ok(())
+}
@@ -0,0 +1,359 @@
+//! Small http server undistorting uploaded images.
+//!
+//! `POST /undistort` takes the image as the raw request body (`Content-Type: image/jpeg`,
+//! `image/png`, ...) or as the first file of a `multipart/form-data` upload and answers with
+//! the corrected image in the same format, `?format=png` picks another one. `GET /health`
+//! answers `ok` once the maps are built. Errors come back as `text/plain` with status 400 for
+//! malformed requests and 422 for images that could not be decoded or corrected.
+
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use opencv::core::Size;
+use opencv_undistort::WorkerPool;
+
+use crate::service::Undistorters;
+
+// refuse larger uploads, the body is read as it arrives so a wrong content length allocates
+// no more than was sent
+const MAX_BODY: usize = 256 * 1024 * 1024;
+// request line and headers together
+const MAX_HEADER: u64 = 64 * 1024;
+// a client sending or reading nothing for this long loses its connection
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type: "text/plain".to_string(),
+            body: format!("{}\n", body.into()).into_bytes(),
+        }
+    }
+}
+
+fn read_request(stream: impl Read) -> Result<Request, Response> {
+    let bad = |e: io::Error| Response::text("400 Bad Request", e.to_string());
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEADER);
+    // a line without its end ran into the limit
+    let mut read_line = |line: &mut String| match head.read_line(line) {
+        Ok(_) if line.ends_with('\n') => Ok(()),
+        Ok(_) => Err(Response::text(
+            "431 Request Header Fields Too Large",
+            format!("request line and headers are limited to {MAX_HEADER} bytes"),
+        )),
+        Err(e) => Err(bad(e)),
+    };
+    let mut request_line = String::new();
+    read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut content_type = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-type" => content_type = value.to_string(),
+            "content-length" => {
+                content_length = value
+                    .parse()
+                    .map_err(|_| Response::text("400 Bad Request", "invalid Content-Length"))?
+            }
+            "transfer-encoding" if !value.eq_ignore_ascii_case("identity") => {
+                return Err(Response::text(
+                    "411 Length Required",
+                    "chunked uploads are not supported, send a Content-Length",
+                ));
+            }
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(Response::text(
+            "413 Payload Too Large",
+            format!("uploads are limited to {MAX_BODY} bytes"),
+        ));
+    }
+    let mut body = Vec::new();
+    reader
+        .take(content_length as u64)
+        .read_to_end(&mut body)
+        .map_err(bad)?;
+    if body.len() < content_length {
+        return Err(Response::text(
+            "400 Bad Request",
+            format!("the body ended after {} bytes", body.len()),
+        ));
+    }
+    Ok(Request {
+        method,
+        path,
+        query,
+        content_type,
+        body,
+    })
+}
+
+// image extension of a mime type, None for anything that isn't an image
+fn extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    Some(match mime.to_ascii_lowercase().as_str() {
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/png" => "png",
+        "image/tiff" => "tif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        _ => return None,
+    })
+}
+
+fn mime(extension: &str) -> &'static str {
+    match extension {
+        "png" => "image/png",
+        "tif" | "tiff" => "image/tiff",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => "image/jpeg",
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// the first part of a multipart body that carries a file, its content type and bytes
+fn multipart_file<'a>(body: &'a [u8], content_type: &str) -> Option<(String, &'a [u8])> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|parameter| parameter.trim().strip_prefix("boundary="))?
+        .trim_matches('"');
+    let delimiter = format!("--{boundary}");
+    let mut rest = &body[find(body, delimiter.as_bytes())? + delimiter.len()..];
+    loop {
+        let end = find(rest, delimiter.as_bytes())?;
+        let part = rest[..end].strip_prefix(b"\r\n").unwrap_or(&rest[..end]);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        if let Some(split) = find(part, b"\r\n\r\n") {
+            let headers = String::from_utf8_lossy(&part[..split]).to_ascii_lowercase();
+            let data = &part[split + 4..];
+            if headers.contains("filename=") || headers.contains("content-type: image/") {
+                let part_type = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-type:"))
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                return Some((part_type, data));
+            }
+        }
+        rest = &rest[end + delimiter.len()..];
+    }
+}
+
+fn undistort(undistorters: &Undistorters, id: &str, request: &Request) -> Response {
+    let (content_type, data) = if request.content_type.starts_with("multipart/form-data") {
+        match multipart_file(&request.body, &request.content_type) {
+            Some((content_type, data)) => (content_type, data),
+            None => return Response::text("400 Bad Request", "no file in the multipart upload"),
+        }
+    } else {
+        (request.content_type.clone(), &request.body[..])
+    };
+    if data.is_empty() {
+        return Response::text("400 Bad Request", "empty upload");
+    }
+    let requested = request
+        .query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("format="));
+    let format = requested
+        .or_else(|| extension(&content_type))
+        .unwrap_or("jpg");
+    match undistorters.undistort_encoded(id, data, format) {
+        Ok(corrected) => Response {
+            status: "200 OK",
+            content_type: mime(format).to_string(),
+            body: corrected,
+        },
+        Err(e) => Response::text("422 Unprocessable Entity", e.to_string()),
+    }
+}
+
+fn respond(mut stream: TcpStream, undistorters: &Undistorters, id: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let response = match read_request(&stream) {
+        Ok(request) => match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/undistort") => undistort(undistorters, id, &request),
+            ("GET", "/health") => Response::text("200 OK", "ok"),
+            (_, "/undistort" | "/health") => {
+                Response::text("405 Method Not Allowed", "method not allowed")
+            }
+            _ => Response::text("404 Not Found", "not found"),
+        },
+        Err(response) => response,
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)
+}
+
+// answer the connections of the queue until it closes
+fn work(connections: &Mutex<Receiver<TcpStream>>, undistorters: &Undistorters, id: &str) {
+    loop {
+        let Ok(stream) = connections.lock().unwrap().recv() else {
+            return;
+        };
+        if let Err(e) = respond(stream, undistorters, id) {
+            warn!("request failed: {e}");
+        }
+    }
+}
+
+/// serve the calibration on `port` until the process is stopped, one request per connection
+/// on a pool of `--threads` workers, connections beyond wait in the listen backlog
+pub fn serve(calibration_file: &str, port: u16) -> Result<(), Box<dyn Error>> {
+    let undistorters = Arc::new(Undistorters::load_file(calibration_file)?);
+    let id = undistorters.ids()[0].to_string();
+    // the maps of the calibrated size are ready before the first upload, other sizes are
+    // built on their first image
+    if let Some([width, height]) = undistorters.image_size(&id) {
+        undistorters.prepare(&id, Size::new(width, height))?;
+        info!("maps built for {width}x{height}");
+    }
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(address)?;
+    info!("undistorting uploads on http://{address}/undistort");
+    let pool = WorkerPool::new(None)?;
+    let workers = pool.current_num_threads();
+    // accepting blocks while every worker is busy and the queue is full
+    let (sender, receiver) = mpsc::sync_channel(workers);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..workers {
+        let (receiver, undistorters, id) = (receiver.clone(), undistorters.clone(), id.clone());
+        pool.spawn(move || work(&receiver, &undistorters, &id));
+    }
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("accepting a connection failed: {e}");
+                continue;
+            }
+        };
+        if sender.send(stream).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORM: &str = "multipart/form-data; boundary=\"frontier\"";
+
+    fn status(request: &[u8]) -> &'static str {
+        match read_request(request) {
+            Ok(_) => "200 OK",
+            Err(response) => response.status,
+        }
+    }
+
+    #[test]
+    fn request() {
+        let bytes = b"POST /undistort?format=png HTTP/1.1\r\nContent-Type: image/jpeg\r\n\
+                      Content-Length: 4\r\n\r\njpeg";
+        let request = read_request(&bytes[..]).ok().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/undistort");
+        assert_eq!(request.query, "format=png");
+        assert_eq!(request.content_type, "image/jpeg");
+        assert_eq!(request.body, b"jpeg");
+    }
+
+    #[test]
+    fn multipart() {
+        let body = b"preamble\r\n--frontier\r\nContent-Disposition: form-data; name=\"id\"\r\n\r\n\
+                     left\r\n--frontier\r\nContent-Disposition: form-data; name=\"image\"; \
+                     filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\npng\r\n--\r\n\
+                     --frontier--\r\n";
+        let (content_type, data) = multipart_file(body, FORM).unwrap();
+        assert_eq!(content_type, "image/png");
+        // a line break or a dash inside the file isn't a boundary
+        assert_eq!(data, b"png\r\n--");
+    }
+
+    #[test]
+    fn multipart_without_file() {
+        let body = b"--frontier\r\nContent-Disposition: form-data; name=\"id\"\r\n\r\n\
+                     left\r\n--frontier--\r\n";
+        assert!(multipart_file(body, FORM).is_none());
+        assert!(multipart_file(b"", FORM).is_none());
+        assert!(multipart_file(body, "multipart/form-data").is_none());
+    }
+
+    #[test]
+    fn body_limit() {
+        let request = format!(
+            "POST /undistort HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(status(request.as_bytes()), "413 Payload Too Large");
+    }
+
+    #[test]
+    fn header_limit() {
+        let mut request = b"POST /undistort HTTP/1.1\r\nX-Padding: ".to_vec();
+        request.resize(MAX_HEADER as usize + 1, b'a');
+        request.extend(b"\r\n\r\n");
+        assert_eq!(status(&request), "431 Request Header Fields Too Large");
+    }
+
+    #[test]
+    fn truncated_body() {
+        let request = b"POST /undistort HTTP/1.1\r\nContent-Length: 8\r\n\r\njpeg";
+        assert_eq!(status(request), "400 Bad Request");
+    }
+}
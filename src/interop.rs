@@ -0,0 +1,76 @@
+use opencv::core::{DataType, StsBadArg};
+use opencv::prelude::*;
+
+#[cfg(feature = "kornia")]
+use kornia_image::{Image, ImageSize};
+#[cfg(feature = "ndarray")]
+use ndarray::{Array3, ArrayView3};
+
+fn bad_arg(message: impl Into<String>) -> opencv::Error {
+    opencv::Error::new(StsBadArg, message)
+}
+
+// interleaved pixel data of a mat, copied when the mat is a non continuous roi
+fn pixels<T: DataType>(mat: &Mat) -> opencv::Result<Vec<T>> {
+    let copy;
+    let mat = if mat.is_continuous() {
+        mat
+    } else {
+        copy = mat.try_clone()?;
+        &copy
+    };
+    Ok(mat.reshape(1, 0)?.data_typed::<T>()?.to_vec())
+}
+
+// mat of `channels` interleaved channels over a rows x cols x channels buffer
+fn from_pixels<T: DataType>(
+    rows: usize,
+    cols: usize,
+    channels: usize,
+    data: &[T],
+) -> opencv::Result<Mat> {
+    Mat::new_rows_cols_with_data(rows as i32, (cols * channels) as i32, data)?
+        .reshape(channels as i32, rows as i32)?
+        .try_clone()
+}
+
+/// height x width x channels array of a `Mat`, use `u8` for 8 bit and `f32` for float images
+#[cfg(feature = "ndarray")]
+pub fn mat_to_array3<T: DataType>(mat: &Mat) -> opencv::Result<Array3<T>> {
+    let shape = (
+        mat.rows() as usize,
+        mat.cols() as usize,
+        mat.channels() as usize,
+    );
+    Array3::from_shape_vec(shape, pixels(mat)?).map_err(|e| bad_arg(e.to_string()))
+}
+
+/// `Mat` owning a copy of a height x width x channels array in any memory layout
+#[cfg(feature = "ndarray")]
+pub fn array3_to_mat<T: DataType>(array: ArrayView3<T>) -> opencv::Result<Mat> {
+    let (rows, cols, channels) = array.dim();
+    let array = array.as_standard_layout();
+    from_pixels(rows, cols, channels, array.as_slice().unwrap())
+}
+
+/// kornia-rs image with `C` channels, the mat must have the same channel count
+#[cfg(feature = "kornia")]
+pub fn mat_to_image<T: DataType, const C: usize>(mat: &Mat) -> opencv::Result<Image<T, C>> {
+    if mat.channels() as usize != C {
+        return Err(bad_arg(format!(
+            "mat has {} channels, expected {C}",
+            mat.channels()
+        )));
+    }
+    let size = ImageSize {
+        width: mat.cols() as usize,
+        height: mat.rows() as usize,
+    };
+    Image::new(size, pixels(mat)?).map_err(|e| bad_arg(e.to_string()))
+}
+
+/// `Mat` owning a copy of a kornia-rs image
+#[cfg(feature = "kornia")]
+pub fn image_to_mat<T: DataType, const C: usize>(image: &Image<T, C>) -> opencv::Result<Mat> {
+    from_pixels(image.height(), image.width(), C, image.as_slice())
+}
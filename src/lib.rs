@@ -0,0 +1,1108 @@
+//! Library side of opencv-undistort for embedding in other rust pipelines.
+//!
+//! [`Calibrator`] collects board detections and solves for a [`Calibration`], an
+//! [`Undistorter`] corrects images with it. The `opencv-undistort` binary is a command line
+//! wrapper around the same modules.
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use log::{info, warn};
+use opencv::calib3d::{
+    fisheye_distort_points_def, fisheye_init_undistort_rectify_map, get_optimal_new_camera_matrix,
+    init_undistort_rectify_map, project_points_def, undistort_points,
+};
+#[cfg(feature = "cuda")]
+use opencv::core::{_InputArray, _OutputArray, GpuMat};
+use opencv::core::{
+    AccessFlag, Point2f, Point3f, Rect, Size, TermCriteria, TermCriteria_EPS,
+    TermCriteria_MAX_ITER, UMat, Vector, no_array,
+};
+use opencv::prelude::*;
+use opencv::{imgproc, not_opencv_branch_5, opencv_branch_5};
+use serde::{Deserialize, Serialize};
+
+opencv_branch_5! {
+    use opencv::calib::{
+        CALIB_CB_ACCURACY, CALIB_CB_ADAPTIVE_THRESH, CALIB_CB_EXHAUSTIVE, CALIB_CB_FAST_CHECK,
+        CALIB_CB_NORMALIZE_IMAGE, find_chessboard_corners, find_chessboard_corners_def,
+        find_chessboard_corners_sb,
+    };
+    use opencv::mod_3d::init_undistort_rectify_map;
+}
+
+not_opencv_branch_5! {
+    use opencv::calib3d::{
+        CALIB_CB_ACCURACY, CALIB_CB_ADAPTIVE_THRESH, CALIB_CB_EXHAUSTIVE, CALIB_CB_FAST_CHECK,
+        CALIB_CB_NORMALIZE_IMAGE, find_chessboard_corners, find_chessboard_corners_def,
+        find_chessboard_corners_sb,
+    };
+}
+
+pub mod affine;
+pub mod blender;
+pub mod board;
+pub mod corners;
+pub mod debug;
+pub mod diagnose;
+pub mod drift;
+pub mod encoding;
+pub mod exif;
+pub mod exit;
+pub mod filestorage;
+pub mod fisheye;
+pub mod gpu;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod gstreamer;
+#[cfg(any(feature = "ndarray", feature = "kornia"))]
+pub mod interop;
+pub mod manifest;
+pub mod mapcache;
+pub mod mapfile;
+pub mod marker;
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod nvr;
+pub mod omnidir;
+pub mod opensfm;
+pub mod order;
+pub mod panotools;
+pub mod pinhole;
+pub mod pipe;
+pub mod plumbline;
+pub mod points;
+pub mod pose;
+pub mod presets;
+pub mod progress;
+pub mod projector;
+pub mod provenance;
+pub mod rectify;
+pub mod resume;
+pub mod rig;
+pub mod ros;
+#[cfg(feature = "ros2")]
+pub mod ros2;
+pub mod select;
+pub mod service;
+pub mod stereo;
+pub mod stmap;
+#[cfg(feature = "s3")]
+pub mod storage;
+pub mod thermal;
+mod threads;
+pub mod underwater;
+pub mod validate;
+pub mod video;
+pub mod zoom;
+
+pub use threads::WorkerPool;
+
+/// lens model the distortion coefficients belong to
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraModel {
+    /// opencv's standard model, k1, k2, p1, p2, k3
+    #[default]
+    Pinhole,
+    /// equidistant fisheye model, k1..k4
+    Fisheye,
+    /// orthographic projection of telecentric lenses, radial k1, k2 around the image center
+    Affine,
+    /// unified model of ultra wide and catadioptric cameras, xi and k1, k2, p1, p2
+    Omnidir,
+}
+
+/// version of the calibration file layout written by this build
+pub const SCHEMA_VERSION: u32 = 2;
+
+// files written before the layout was versioned
+fn legacy_version() -> u32 {
+    1
+}
+
+// aspect ratios closer than this are the same sensor read out at another resolution
+const ASPECT_TOLERANCE: f64 = 0.01;
+
+/// intrinsics of one camera, the calibration json file
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Calibration {
+    /// layout of the file, see `SCHEMA_VERSION`
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+    /// files written before the model was recorded are pinhole
+    #[serde(default)]
+    pub model: CameraModel,
+    pub camera_matrix: Vec<f64>,
+    pub dist_coeffs: Vec<f64>,
+    /// width and height of the calibration images, older files don't have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_size: Option<[i32; 2]>,
+    /// board the calibration was computed from, the units of the extrinsics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board: Option<board::Geometry>,
+    /// xi and the rectification of the omnidir model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omnidir: Option<omnidir::Omnidir>,
+    /// rms reprojection error in pixels of the views the calibration was solved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rms: Option<f64>,
+    /// camera matrix of the undistorted image for the `--alpha` it was computed with, the
+    /// camera matrix when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_camera_matrix: Option<Vec<f64>>,
+    /// x, y, width and height of the part of the undistorted image with only valid pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roi: Option<[i32; 4]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<provenance::Provenance>,
+}
+
+impl Calibration {
+    /// json or a FileStorage yaml/xml file, by the content or else the extension, a file
+    /// saved with a `--format` that doesn't match its extension loads as well
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read(path)?;
+        let format =
+            filestorage::Format::sniff(&content).unwrap_or_else(|| filestorage::Format::of(path));
+        let calibration: Calibration = match format {
+            filestorage::Format::Json => serde_json::from_slice(&content)?,
+            _ => filestorage::read(path)?,
+        };
+        calibration.validate().map_err(|e| format!("{path}: {e}"))?;
+        Ok(calibration)
+    }
+
+    /// a schema version this build reads with a camera matrix, distortion coefficients and
+    /// image size that fit the model
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version == 0 || self.version > SCHEMA_VERSION {
+            return Err(format!(
+                "calibration schema version {} is not supported, this build reads versions 1 to \
+                 {SCHEMA_VERSION}, recalibrate or update opencv-undistort",
+                self.version
+            ));
+        }
+        if self.camera_matrix.len() != 9 {
+            return Err(format!(
+                "the camera matrix has {} values instead of 9",
+                self.camera_matrix.len()
+            ));
+        }
+        if !(self.camera_matrix[0] > 0.0 && self.camera_matrix[4] > 0.0) {
+            return Err("the focal lengths of the camera matrix are not positive".into());
+        }
+        let counts: &[usize] = match self.model {
+            CameraModel::Pinhole => &[4, 5, 8, 12, 14],
+            CameraModel::Fisheye | CameraModel::Omnidir => &[4],
+            CameraModel::Affine => &[2],
+        };
+        if !counts.contains(&self.dist_coeffs.len()) {
+            return Err(format!(
+                "{} distortion coefficients don't fit the {:?} model, it has {counts:?}",
+                self.dist_coeffs.len(),
+                self.model
+            ));
+        }
+        if let Some([width, height]) = self.image_size
+            && (width <= 0 || height <= 0)
+        {
+            return Err(format!("invalid image size {width}x{height}"));
+        }
+        Ok(())
+    }
+
+    /// write in the given format, or the one of the file extension
+    pub fn save(
+        &self,
+        path: &str,
+        format: Option<filestorage::Format>,
+    ) -> Result<(), Box<dyn Error>> {
+        match format.unwrap_or_else(|| filestorage::Format::of(path)) {
+            filestorage::Format::Json => Ok(fs::write(path, serde_json::to_string(self)?)?),
+            format => filestorage::write(path, self, format),
+        }
+    }
+
+    /// a calibration file or a built-in preset
+    pub fn resolve(path: Option<&str>, preset: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        match (path, preset) {
+            (_, Some(preset)) => presets::find(preset),
+            (Some(path), None) => Self::load(path),
+            (None, None) => Err("a calibration file or a preset is required".into()),
+        }
+    }
+
+    /// camera matrix for images of another resolution of the same sensor, distortion
+    /// coefficients are resolution independent
+    pub fn scaled(&self, size: Size) -> Self {
+        let Some([width, height]) = self.image_size else {
+            return self.clone();
+        };
+        if [width, height] == [size.width, size.height] {
+            return self.clone();
+        }
+        let (sx, sy) = (
+            size.width as f64 / width as f64,
+            size.height as f64 / height as f64,
+        );
+        let scale = |k: &[f64]| {
+            vec![
+                k[0] * sx,
+                k[1] * sx,
+                k[2] * sx,
+                k[3],
+                k[4] * sy,
+                k[5] * sy,
+                k[6],
+                k[7],
+                k[8],
+            ]
+        };
+        Calibration {
+            version: self.version,
+            model: self.model,
+            camera_matrix: scale(&self.camera_matrix),
+            dist_coeffs: self.dist_coeffs.clone(),
+            image_size: Some([size.width, size.height]),
+            board: self.board,
+            omnidir: self.omnidir,
+            rms: self.rms,
+            new_camera_matrix: self.new_camera_matrix.as_deref().map(scale),
+            roi: self.roi.map(|[x, y, width, height]| {
+                [
+                    (x as f64 * sx) as i32,
+                    (y as f64 * sy) as i32,
+                    (width as f64 * sx) as i32,
+                    (height as f64 * sy) as i32,
+                ]
+            }),
+            provenance: self.provenance.clone(),
+        }
+    }
+
+    /// the calibration under another model, the coefficients have to fit it
+    pub fn with_model(self, model: Option<CameraModel>) -> Result<Self, Box<dyn Error>> {
+        let Some(model) = model.filter(|model| *model != self.model) else {
+            return Ok(self);
+        };
+        if model == CameraModel::Fisheye && self.dist_coeffs.len() != 4 {
+            return Err(format!(
+                "the fisheye model has 4 distortion coefficients, the calibration has {}",
+                self.dist_coeffs.len()
+            )
+            .into());
+        }
+        warn!(
+            "correcting a {:?} calibration with the {model:?} model",
+            self.model
+        );
+        Ok(Calibration { model, ..self })
+    }
+
+    /// the omnidir calibration rendered in another view
+    pub fn with_rectification(
+        self,
+        rectification: Option<omnidir::Rectification>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let Some(rectification) = rectification else {
+            return Ok(self);
+        };
+        let Some(omnidir) = self.omnidir.filter(|_| self.model == CameraModel::Omnidir) else {
+            return Err("--rectification needs an omnidir calibration".into());
+        };
+        Ok(Calibration {
+            omnidir: Some(omnidir::Omnidir {
+                rectification,
+                ..omnidir
+            }),
+            ..self
+        })
+    }
+
+    /// the undistorted view for a free scaling `alpha`, 0 keeps only valid pixels and 1
+    /// keeps all pixels of the distorted image, pinhole only
+    pub fn with_alpha(self, alpha: Option<f64>) -> Result<Self, Box<dyn Error>> {
+        let Some(alpha) = alpha else {
+            return Ok(self);
+        };
+        if self.model != CameraModel::Pinhole {
+            return Err(
+                format!("--alpha needs a pinhole calibration, not {:?}", self.model).into(),
+            );
+        }
+        let [width, height] = self
+            .image_size
+            .ok_or("--alpha needs the image size, the calibration doesn't record it")?;
+        let size = Size::new(width, height);
+        let (mtx, dist) = self.matrices()?;
+        let mut roi = Rect::default();
+        let new_mtx =
+            get_optimal_new_camera_matrix(&mtx, &dist, size, alpha, size, Some(&mut roi), true)?;
+        Ok(Calibration {
+            new_camera_matrix: Some(mat_to_vec(&new_mtx)?),
+            roi: Some([roi.x, roi.y, roi.width, roi.height]),
+            ..self
+        })
+    }
+
+    /// positions in the undistorted image of pixels of a distorted image of the given size
+    pub fn undistort_points(
+        &self,
+        distorted: &Vector<Point2f>,
+        undistorted: &mut Vector<Point2f>,
+        size: Size,
+    ) -> opencv::Result<()> {
+        let scaled = self.scaled(size);
+        let (mtx, dist) = scaled.matrices()?;
+        match self.model {
+            CameraModel::Pinhole => undistort_points(
+                distorted,
+                undistorted,
+                &mtx,
+                &dist,
+                &no_array(),
+                &scaled.new_matrix()?,
+            ),
+            CameraModel::Fisheye => fisheye::undistort_points(distorted, undistorted, &mtx, &dist),
+            CameraModel::Affine => {
+                *undistorted = Vector::from_iter(distorted.iter().map(|point| {
+                    let point = affine::undistort(
+                        glam::DVec2::new(point.x as f64, point.y as f64),
+                        &scaled.camera_matrix,
+                        &scaled.dist_coeffs,
+                    );
+                    Point2f::new(point.x as f32, point.y as f32)
+                }));
+                Ok(())
+            }
+            CameraModel::Omnidir => {
+                omnidir::undistort_pixels(&scaled, distorted, undistorted, size)
+            }
+        }
+    }
+
+    /// positions in a distorted image of the given size of pixels of the undistorted image,
+    /// the inverse of `undistort_points`
+    pub fn distort_points(
+        &self,
+        undistorted: &Vector<Point2f>,
+        distorted: &mut Vector<Point2f>,
+        size: Size,
+    ) -> opencv::Result<()> {
+        let scaled = self.scaled(size);
+        let (mtx, dist) = scaled.matrices()?;
+        // normalized image coordinates of the undistorted pixels
+        let normalize = |k: &[f64]| {
+            Vector::<Point2f>::from_iter(undistorted.iter().map(|point| {
+                Point2f::new(
+                    ((point.x as f64 - k[2]) / k[0]) as f32,
+                    ((point.y as f64 - k[5]) / k[4]) as f32,
+                )
+            }))
+        };
+        match self.model {
+            CameraModel::Pinhole => {
+                let k = scaled
+                    .new_camera_matrix
+                    .as_ref()
+                    .unwrap_or(&scaled.camera_matrix);
+                let rays = Vector::<Point3f>::from_iter(
+                    normalize(k)
+                        .iter()
+                        .map(|point| Point3f::new(point.x, point.y, 1.0)),
+                );
+                let zero = Vector::<f64>::from_slice(&[0.0, 0.0, 0.0]);
+                project_points_def(&rays, &zero, &zero, &mtx, &dist, distorted)
+            }
+            CameraModel::Fisheye => fisheye_distort_points_def(
+                &normalize(&scaled.camera_matrix),
+                distorted,
+                &mtx,
+                &dist,
+            ),
+            CameraModel::Affine => {
+                *distorted = Vector::from_iter(undistorted.iter().map(|point| {
+                    let point = affine::distort(
+                        glam::DVec2::new(point.x as f64, point.y as f64),
+                        &scaled.camera_matrix,
+                        &scaled.dist_coeffs,
+                    );
+                    Point2f::new(point.x as f32, point.y as f32)
+                }));
+                Ok(())
+            }
+            CameraModel::Omnidir => Err(opencv::Error::new(
+                opencv::core::StsNotImplemented,
+                "distorting points is not supported for omnidir calibrations",
+            )),
+        }
+    }
+
+    /// camera matrix of the undistorted image
+    pub fn new_matrix(&self) -> opencv::Result<Mat> {
+        let k = self
+            .new_camera_matrix
+            .as_ref()
+            .unwrap_or(&self.camera_matrix);
+        Mat::new_rows_cols_with_data(3, 3, k)?.try_clone()
+    }
+
+    /// camera matrix and distortion coefficients as opencv matrices
+    pub fn matrices(&self) -> opencv::Result<(Mat, Mat)> {
+        Ok((
+            Mat::new_rows_cols_with_data(3, 3, &self.camera_matrix)?.try_clone()?,
+            Mat::new_rows_cols_with_data(1, self.dist_coeffs.len() as i32, &self.dist_coeffs)?
+                .try_clone()?,
+        ))
+    }
+}
+
+/// chessboard interior corners
+pub const BOARD_WIDTH: i32 = 11;
+pub const BOARD_HEIGHT: i32 = 8;
+
+/// termination criteria for corner refinement
+pub fn corner_criteria() -> TermCriteria {
+    TermCriteria {
+        typ: TermCriteria_EPS + TermCriteria_MAX_ITER,
+        max_count: 30,
+        epsilon: 0.001,
+    }
+}
+
+/// prepare object points, like (0,0,0), (1,0,0), (2,0,0) ....,(6,5,0)
+pub fn object_points(width_dim: i32, height_dim: i32, square_size: f32) -> Vector<Point3f> {
+    Vector::from_iter((0..width_dim * height_dim).map(|i| {
+        Point3f::new(
+            (i % width_dim) as f32 * square_size,
+            (i / width_dim) as f32 * square_size,
+            0.,
+        )
+    }))
+}
+
+/// image formats opencv reads, matched in any case
+pub const IMAGE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "tif", "tiff", "bmp", "webp"];
+
+/// images in a directory in `--sort` order
+pub fn list_images(dir: &str) -> std::io::Result<Vec<String>> {
+    list_files(dir, &IMAGE_EXTENSIONS)
+}
+
+/// files with one of the extensions in a directory in `--sort` order
+pub fn list_files(dir: &str, extensions: &[&str]) -> std::io::Result<Vec<String>> {
+    select::Selection::default().files(dir, extensions)
+}
+
+/// images of a directory or the NUL separated paths of a file, `-` reads stdin
+pub fn input_images(
+    dir: Option<&str>,
+    files_from: Option<&str>,
+    extensions: &[&str],
+    selection: &select::Selection,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let Some(files_from) = files_from else {
+        return Ok(selection.files(
+            dir.ok_or("an image directory or --files-from is required")?,
+            extensions,
+        )?);
+    };
+    let content = if files_from == "-" {
+        let mut content = Vec::new();
+        std::io::stdin().read_to_end(&mut content)?;
+        content
+    } else {
+        fs::read(files_from)?
+    };
+    let mut images = Vec::new();
+    for path in content
+        .split(|byte| *byte == 0)
+        .filter(|path| !path.is_empty())
+    {
+        let path = OsStr::from_bytes(path);
+        // opencv opens images by utf-8 paths only
+        match path.to_str() {
+            Some(path) => images.push(path.to_string()),
+            None => warn!("{} is not a utf-8 path, skipped", Path::new(path).display()),
+        }
+    }
+    order::sort(&mut images);
+    Ok(images)
+}
+
+/// detect and refine chessboard corners, None when the board is not visible
+pub fn detect_corners(img: &Mat, pattern: Size) -> opencv::Result<Option<Vector<Point2f>>> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(img, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    let mut corners = Vector::<Point2f>::default();
+    if !find_chessboard_corners_def(&gray, pattern, &mut corners)? {
+        return Ok(None);
+    }
+    imgproc::corner_sub_pix(
+        &gray,
+        &mut corners,
+        Size::new(11, 11),
+        Size::new(-1, -1),
+        corner_criteria(),
+    )?;
+    Ok(Some(corners))
+}
+
+/// chessboard corners with opencv's sector based detector, subpixel accurate without
+/// cornerSubPix and tolerant of markers in the squares, None when the board is not visible
+pub fn detect_corners_sb(img: &Mat, pattern: Size) -> opencv::Result<Option<Vector<Point2f>>> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(img, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    let mut corners = Vector::<Point2f>::default();
+    let flags = CALIB_CB_NORMALIZE_IMAGE | CALIB_CB_EXHAUSTIVE | CALIB_CB_ACCURACY;
+    if !find_chessboard_corners_sb(&gray, pattern, &mut corners, flags)? {
+        return Ok(None);
+    }
+    Ok(Some(corners))
+}
+
+/// quick check for a chessboard of the pattern, the corners are not refined
+pub fn chessboard_visible(img: &Mat, pattern: Size) -> opencv::Result<bool> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(img, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    let mut corners = Vector::<Point2f>::default();
+    find_chessboard_corners(
+        &gray,
+        pattern,
+        &mut corners,
+        CALIB_CB_ADAPTIVE_THRESH | CALIB_CB_NORMALIZE_IMAGE | CALIB_CB_FAST_CHECK,
+    )
+}
+
+/// quick check for a chessboard of the pattern with the sector based detector, without its
+/// exhaustive search and subpixel accuracy
+pub fn chessboard_visible_sb(img: &Mat, pattern: Size) -> opencv::Result<bool> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(img, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    let mut corners = Vector::<Point2f>::default();
+    find_chessboard_corners_sb(&gray, pattern, &mut corners, CALIB_CB_NORMALIZE_IMAGE)
+}
+
+/// remap tables undistorting images of the given size with the model of the calibration
+pub fn undistort_maps(calibration: &Calibration, size: Size) -> opencv::Result<(Mat, Mat)> {
+    // another resolution of the calibrated sensor is rescaled, a crop of it or another camera
+    // can't be
+    if let Some([width, height]) = calibration.image_size {
+        let calibrated = width as f64 / height as f64;
+        let aspect = size.width as f64 / size.height as f64;
+        if (aspect / calibrated - 1.0).abs() > ASPECT_TOLERANCE {
+            return Err(opencv::Error::new(
+                opencv::core::StsBadSize,
+                format!(
+                    "{}x{} images don't have the aspect ratio of the {width}x{height} calibration \
+                     images",
+                    size.width, size.height
+                ),
+            ));
+        }
+    }
+    let scaled = calibration.scaled(size);
+    let (mtx, dist) = scaled.matrices()?;
+    let mut mapx = Mat::default();
+    let mut mapy = Mat::default();
+    match calibration.model {
+        CameraModel::Pinhole => init_undistort_rectify_map(
+            &mtx,
+            &dist,
+            &no_array(),
+            &scaled.new_matrix()?,
+            size,
+            f32::opencv_type(),
+            &mut mapx,
+            &mut mapy,
+        )?,
+        CameraModel::Fisheye => fisheye_init_undistort_rectify_map(
+            &mtx,
+            &dist,
+            &no_array(),
+            &mtx,
+            size,
+            f32::opencv_type(),
+            &mut mapx,
+            &mut mapy,
+        )?,
+        CameraModel::Affine => {
+            return affine::maps(&scaled.camera_matrix, &scaled.dist_coeffs, size);
+        }
+        CameraModel::Omnidir => return omnidir::maps(&scaled, size),
+    }
+    Ok((mapx, mapy))
+}
+
+/// row-major copy of a f64 matrix
+pub fn mat_to_vec(mat: &Mat) -> opencv::Result<Vec<f64>> {
+    Ok(mat
+        .to_vec_2d::<f64>()?
+        .iter()
+        .flat_map(|row| row.iter())
+        .cloned()
+        .collect::<Vec<f64>>())
+}
+
+/// rms distance between projected and detected board points of one view
+fn view_rms(projected: &Vector<Point2f>, detected: &Vector<Point2f>) -> f64 {
+    let squared = projected
+        .iter()
+        .zip(detected.iter())
+        .map(|(p, d)| ((p.x - d.x) as f64).powi(2) + ((p.y - d.y) as f64).powi(2))
+        .sum::<f64>();
+    (squared / detected.len().max(1) as f64).sqrt()
+}
+
+// views kept however bad they are, fewer give no meaningful calibration
+const MIN_VIEWS: usize = 3;
+
+/// board detections of a calibration session and the lens model to solve for
+pub struct Calibrator {
+    board: board::Board,
+    model: CameraModel,
+    objp: Vector<Point3f>,
+    /// image or frame each detection came from
+    names: Vec<String>,
+    /// 3d points of the board in real world space
+    objpoints: Vector<Vector<Point3f>>,
+    /// 2d points in the image plane
+    imgpoints: Vector<Vector<Point2f>>,
+    alpha: f64,
+    pinhole: pinhole::Options,
+}
+
+/// rms reprojection error of one view of the board
+#[derive(Serialize, Clone, Debug)]
+pub struct ViewError {
+    pub name: String,
+    pub rms: f64,
+}
+
+/// a solved calibration with its rms reprojection error in pixels
+pub struct CalibrationResult {
+    pub calibration: Calibration,
+    pub rms: f64,
+    /// images the calibration was solved from
+    pub used: usize,
+    /// errors of the used views, worst first
+    pub views: Vec<ViewError>,
+    /// views dropped above the rejection threshold, in the order they were dropped
+    pub rejected: Vec<ViewError>,
+}
+
+// camera of one solve, with the error of every view it was solved from
+struct Solve {
+    rms: f64,
+    mtx: Mat,
+    dist: Mat,
+    xi: Option<f64>,
+    view_errors: Vec<f64>,
+}
+
+impl Calibrator {
+    pub fn new(board: board::Board, model: CameraModel) -> Self {
+        Calibrator {
+            board,
+            model,
+            objp: board.object_points(),
+            names: Vec::new(),
+            objpoints: Vector::new(),
+            imgpoints: Vector::new(),
+            alpha: 1.0,
+            pinhole: pinhole::Options::default(),
+        }
+    }
+
+    /// free scaling of the undistorted view of pinhole calibrations, 1 by default
+    pub fn with_alpha(self, alpha: f64) -> Self {
+        Calibrator { alpha, ..self }
+    }
+
+    /// calibrateCamera flags and initial camera matrix of pinhole calibrations
+    pub fn with_pinhole(self, pinhole: pinhole::Options) -> Self {
+        Calibrator { pinhole, ..self }
+    }
+
+    /// another board, before any corners were added
+    pub fn with_board(self, board: board::Board) -> Self {
+        Calibrator {
+            board,
+            objp: board.object_points(),
+            ..self
+        }
+    }
+
+    pub fn board(&self) -> &board::Board {
+        &self.board
+    }
+
+    /// board detections added so far
+    pub fn len(&self) -> usize {
+        self.imgpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.imgpoints.is_empty()
+    }
+
+    /// detected board points of one image, in the order of `board::Board::object_points`
+    pub fn add_corners(&mut self, name: &str, corners: Vector<Point2f>) {
+        self.names.push(name.to_string());
+        self.objpoints.push(self.objp.clone());
+        self.imgpoints.push(corners);
+    }
+
+    /// detect the board in an image and add it, false when the board is not visible
+    pub fn add_image(&mut self, name: &str, img: &Mat) -> opencv::Result<bool> {
+        let Some(corners) = self.board.detect(img)? else {
+            return Ok(false);
+        };
+        self.add_corners(name, corners);
+        Ok(true)
+    }
+
+    fn solve(&self, views: &[usize], size: Size) -> opencv::Result<Solve> {
+        let objpoints = Vector::<Vector<Point3f>>::from_iter(
+            views
+                .iter()
+                .map(|i| self.objpoints.get(*i))
+                .collect::<opencv::Result<Vec<_>>>()?,
+        );
+        let imgpoints = Vector::<Vector<Point2f>>::from_iter(
+            views
+                .iter()
+                .map(|i| self.imgpoints.get(*i))
+                .collect::<opencv::Result<Vec<_>>>()?,
+        );
+        let (objpoints, imgpoints) = (&objpoints, &imgpoints);
+        Ok(match self.model {
+            CameraModel::Fisheye => {
+                let (rms, mtx, dist, view_errors) =
+                    fisheye::calibrate_camera(objpoints, imgpoints, size)?;
+                Solve {
+                    rms,
+                    mtx,
+                    dist,
+                    xi: None,
+                    view_errors,
+                }
+            }
+            CameraModel::Omnidir => {
+                let (rms, mtx, dist, xi, view_errors) =
+                    omnidir::calibrate_camera(objpoints, imgpoints, size)?;
+                Solve {
+                    rms,
+                    mtx,
+                    dist,
+                    xi: Some(xi),
+                    view_errors,
+                }
+            }
+            CameraModel::Affine => {
+                let (rms, camera_matrix, dist_coeffs, view_errors) =
+                    affine::calibrate_camera(objpoints, imgpoints, size)?;
+                Solve {
+                    rms,
+                    mtx: Mat::new_rows_cols_with_data(3, 3, &camera_matrix)?.try_clone()?,
+                    dist: Mat::new_rows_cols_with_data(1, 2, &dist_coeffs)?.try_clone()?,
+                    xi: None,
+                    view_errors,
+                }
+            }
+            CameraModel::Pinhole => {
+                let (rms, mtx, dist, view_errors) =
+                    pinhole::calibrate_camera(objpoints, imgpoints, size, &self.pinhole)?;
+                Solve {
+                    rms,
+                    mtx,
+                    dist,
+                    xi: None,
+                    view_errors,
+                }
+            }
+        })
+    }
+
+    /// solve for the camera of images of the given size. With a `reject_threshold` the worst
+    /// view above it is dropped and the camera solved again until all views are below it
+    pub fn calibrate(
+        &self,
+        size: Size,
+        reject_threshold: Option<f64>,
+    ) -> Result<CalibrationResult, Box<dyn Error>> {
+        if self.is_empty() {
+            return Err("no board detections to calibrate from".into());
+        }
+        let mut views = (0..self.len()).collect::<Vec<usize>>();
+        let mut rejected = Vec::new();
+        let solve = loop {
+            let solve = self.solve(&views, size)?;
+            let worst = solve
+                .view_errors
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(i, rms)| (i, *rms));
+            match (reject_threshold, worst) {
+                (Some(threshold), Some((i, rms))) if rms > threshold && views.len() > MIN_VIEWS => {
+                    let view = ViewError {
+                        name: self.names[views[i]].clone(),
+                        rms,
+                    };
+                    warn!(
+                        "{}: rms {:.3}px above {threshold}px, dropped",
+                        view.name, view.rms
+                    );
+                    rejected.push(view);
+                    views.remove(i);
+                }
+                _ => break solve,
+            }
+        };
+        if let Some((threshold, worst)) =
+            reject_threshold.zip(solve.view_errors.iter().copied().reduce(f64::max))
+            && worst > threshold
+        {
+            warn!("{MIN_VIEWS} views are kept although the worst is {worst:.3}px");
+        }
+        if let Some(xi) = solve.xi {
+            info!("omnidir xi {xi:.4}");
+        }
+        if self.model == CameraModel::Affine {
+            info!(
+                "affine magnification {:.3} pixels per board unit",
+                solve.mtx.at_2d::<f64>(0, 0)?
+            );
+        }
+        let mut errors = views
+            .iter()
+            .zip(&solve.view_errors)
+            .map(|(view, rms)| ViewError {
+                name: self.names[*view].clone(),
+                rms: *rms,
+            })
+            .collect::<Vec<ViewError>>();
+        errors.sort_by(|a, b| b.rms.total_cmp(&a.rms));
+        Ok(CalibrationResult {
+            calibration: Calibration {
+                version: SCHEMA_VERSION,
+                model: self.model,
+                camera_matrix: mat_to_vec(&solve.mtx)?,
+                dist_coeffs: mat_to_vec(&solve.dist)?,
+                image_size: Some([size.width, size.height]),
+                board: Some(self.board.geometry()),
+                omnidir: solve.xi.map(|xi| omnidir::Omnidir {
+                    xi,
+                    rectification: omnidir::Rectification::default(),
+                }),
+                rms: Some(solve.rms),
+                new_camera_matrix: None,
+                roi: None,
+                provenance: Some(provenance::Provenance::new()),
+            }
+            .with_alpha((self.model == CameraModel::Pinhole).then_some(self.alpha))?,
+            rms: solve.rms,
+            used: views.len(),
+            views: errors,
+            rejected,
+        })
+    }
+}
+
+/// pixel interpolation of the remap
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    Nearest,
+    #[default]
+    Linear,
+    Cubic,
+    Lanczos,
+}
+
+impl Interpolation {
+    pub fn flag(self) -> i32 {
+        match self {
+            Interpolation::Nearest => imgproc::INTER_NEAREST,
+            Interpolation::Linear => imgproc::INTER_LINEAR,
+            Interpolation::Cubic => imgproc::INTER_CUBIC,
+            Interpolation::Lanczos => imgproc::INTER_LANCZOS4,
+        }
+    }
+}
+
+// roi of the calibration for images of the given size, clipped to the image
+fn valid_roi(calibration: &Calibration, size: Size) -> Option<Rect> {
+    let [x, y, width, height] = calibration.scaled(size).roi?;
+    Some(Rect::new(x, y, width, height) & Rect::new(0, 0, size.width, size.height))
+}
+
+// remap tables on the device the remap runs on, gpu memory is used by one worker at a time
+enum Maps {
+    Cpu(Mat, Mat),
+    Opencl(Mutex<(UMat, UMat)>),
+    #[cfg(feature = "cuda")]
+    Cuda(Mutex<(GpuMat, GpuMat)>),
+}
+
+/// undistortion of images of one size, the remap tables are built once
+pub struct Undistorter {
+    size: Size,
+    maps: Maps,
+    interpolation: Interpolation,
+    /// valid pixels of the undistorted image, the output when cropping
+    roi: Option<Rect>,
+    crop: bool,
+}
+
+impl Undistorter {
+    pub fn new(calibration: &Calibration, size: Size) -> opencv::Result<Self> {
+        let (mapx, mapy) = undistort_maps(calibration, size)?;
+        Ok(Undistorter {
+            size,
+            maps: Maps::Cpu(mapx, mapy),
+            interpolation: Interpolation::default(),
+            roi: valid_roi(calibration, size),
+            crop: false,
+        })
+    }
+
+    /// the remap tables are read from `cache_dir` when an earlier run stored them there
+    pub fn cached(
+        calibration: &Calibration,
+        size: Size,
+        cache_dir: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (mapx, mapy) = mapcache::maps(cache_dir, calibration, size)?;
+        Ok(Undistorter {
+            size,
+            maps: Maps::Cpu(mapx, mapy),
+            interpolation: Interpolation::default(),
+            roi: valid_roi(calibration, size),
+            crop: false,
+        })
+    }
+
+    /// remap tables built elsewhere, e.g. read with `mapfile::read`, for images of their size.
+    /// Nothing is known about the valid pixels, cropping leaves the images as they are
+    pub fn from_maps(mapx: Mat, mapy: Mat) -> opencv::Result<Self> {
+        let size = mapx.size()?;
+        if mapy.size()? != size
+            || mapx.typ() != f32::opencv_type()
+            || mapy.typ() != f32::opencv_type()
+        {
+            return Err(opencv::Error::new(
+                opencv::core::StsBadArg,
+                "the maps must be single channel float tables of the same size",
+            ));
+        }
+        Ok(Undistorter {
+            size,
+            maps: Maps::Cpu(mapx, mapy),
+            interpolation: Interpolation::default(),
+            roi: None,
+            crop: false,
+        })
+    }
+
+    pub fn with_interpolation(self, interpolation: Interpolation) -> Self {
+        Undistorter {
+            interpolation,
+            ..self
+        }
+    }
+
+    /// cut the undistorted images down to the valid pixels, calibrations without a roi are
+    /// left uncropped
+    pub fn with_crop(self, crop: bool) -> Self {
+        Undistorter { crop, ..self }
+    }
+
+    /// move the remap tables to the gpu, a gpu from `gpu::available`
+    pub fn on_gpu(self, gpu: Option<gpu::Gpu>) -> opencv::Result<Self> {
+        let Maps::Cpu(mapx, mapy) = &self.maps else {
+            return Ok(self);
+        };
+        let maps = match gpu {
+            None => return Ok(self),
+            Some(gpu::Gpu::Opencl) => {
+                // copies of their own, a UMat of get_umat borrows the buffer of the Mat, which
+                // is dropped with self
+                let mut umapx = UMat::new_def();
+                let mut umapy = UMat::new_def();
+                mapx.copy_to(&mut umapx)?;
+                mapy.copy_to(&mut umapy)?;
+                Maps::Opencl(Mutex::new((umapx, umapy)))
+            }
+            #[cfg(feature = "cuda")]
+            Some(gpu::Gpu::Cuda) => {
+                let mut gpu_mapx = GpuMat::new_def()?;
+                let mut gpu_mapy = GpuMat::new_def()?;
+                gpu_mapx.upload(mapx)?;
+                gpu_mapy.upload(mapy)?;
+                Maps::Cuda(Mutex::new((gpu_mapx, gpu_mapy)))
+            }
+        };
+        Ok(Undistorter { maps, ..self })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn undistort(&self, img: &Mat) -> opencv::Result<Mat> {
+        if img.size()? != self.size {
+            return Err(opencv::Error::new(
+                opencv::core::StsBadSize,
+                format!(
+                    "image is {}x{}, the undistorter is for {}x{}",
+                    img.cols(),
+                    img.rows(),
+                    self.size.width,
+                    self.size.height
+                ),
+            ));
+        }
+        let interpolation = self.interpolation.flag();
+        let mut dst = Mat::default();
+        match &self.maps {
+            Maps::Cpu(mapx, mapy) => imgproc::remap_def(img, &mut dst, mapx, mapy, interpolation)?,
+            Maps::Opencl(maps) => {
+                let (mapx, mapy) = &*maps.lock().unwrap();
+                let src = img.get_umat_def(AccessFlag::ACCESS_READ)?;
+                let mut remapped = UMat::new_def();
+                imgproc::remap_def(&src, &mut remapped, mapx, mapy, interpolation)?;
+                remapped.copy_to(&mut dst)?;
+            }
+            #[cfg(feature = "cuda")]
+            Maps::Cuda(maps) => {
+                let (mapx, mapy) = &*maps.lock().unwrap();
+                let mut src = GpuMat::new_def()?;
+                src.upload(img)?;
+                let mut remapped = GpuMat::new_def()?;
+                opencv::cudawarping::remap(
+                    &_InputArray::from_gpumat(&src)?,
+                    &mut _OutputArray::from_gpumat_mut(&mut remapped)?,
+                    &_InputArray::from_gpumat(mapx)?,
+                    &_InputArray::from_gpumat(mapy)?,
+                    interpolation,
+                    opencv::core::BORDER_CONSTANT,
+                    opencv::core::Scalar::default(),
+                    &mut opencv::core::Stream::null()?,
+                )?;
+                remapped.download(&mut dst)?;
+            }
+        }
+        match self.roi.filter(|roi| self.crop && roi.area() > 0) {
+            Some(roi) => Mat::roi(&dst, roi)?.try_clone(),
+            None => Ok(dst),
+        }
+    }
+}
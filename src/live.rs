@@ -0,0 +1,231 @@
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use opencv::calib3d::draw_chessboard_corners;
+use opencv::core::{Point, Point2f, Rect, Scalar, Size, Vector, add_weighted_def};
+use opencv::prelude::*;
+use opencv::videoio::{CAP_ANY, VideoCapture};
+use opencv::{highgui, imgcodecs, imgproc};
+use opencv_undistort::debug::{self, GRID_COLUMNS, GRID_ROWS};
+
+use crate::board::Board;
+use crate::{CalibrationResult, Calibrator, CameraModel};
+
+const WINDOW: &str = "calibrate-live";
+// the board is held still when its points move less than this on average between frames,
+// for at least STABLE_FOR
+const STABLE_PIXELS: f32 = 1.5;
+const STABLE_FOR: Duration = Duration::from_millis(700);
+// least time between two automatic captures
+const AUTO_INTERVAL: Duration = Duration::from_secs(2);
+// captures before `c` calibrates
+const MIN_CAPTURES: usize = 10;
+
+const KEY_ESCAPE: i32 = 27;
+const KEY_ENTER: i32 = 13;
+
+// parts of the sensor the accepted boards have covered
+struct Coverage {
+    size: Size,
+    cells: Vec<bool>,
+}
+
+impl Coverage {
+    fn new(size: Size) -> Self {
+        Coverage {
+            size,
+            cells: vec![false; (GRID_COLUMNS * GRID_ROWS) as usize],
+        }
+    }
+
+    fn add(&mut self, corners: &Vector<Point2f>) {
+        for point in corners {
+            if let Some(cell) = debug::grid_cell(self.size, point) {
+                self.cells[cell] = true;
+            }
+        }
+    }
+
+    fn fraction(&self) -> f64 {
+        self.cells.iter().filter(|covered| **covered).count() as f64 / self.cells.len() as f64
+    }
+
+    // covered cells shaded green over the preview
+    fn draw(&self, frame: &mut Mat) -> opencv::Result<()> {
+        let mut overlay = frame.try_clone()?;
+        let (w, h) = (self.size.width / GRID_COLUMNS, self.size.height / GRID_ROWS);
+        for (i, covered) in self.cells.iter().enumerate() {
+            let (column, row) = (i as i32 % GRID_COLUMNS, i as i32 / GRID_COLUMNS);
+            let cell = Rect::new(column * w, row * h, w, h);
+            if *covered {
+                imgproc::rectangle(
+                    &mut overlay,
+                    cell,
+                    Scalar::new(0.0, 200.0, 0.0, 0.0),
+                    imgproc::FILLED,
+                    imgproc::LINE_8,
+                    0,
+                )?;
+            }
+            imgproc::rectangle(
+                &mut overlay,
+                cell,
+                Scalar::new(128.0, 128.0, 128.0, 0.0),
+                1,
+                imgproc::LINE_8,
+                0,
+            )?;
+        }
+        let mut blended = Mat::default();
+        add_weighted_def(&overlay, 0.3, &*frame, 0.7, 0.0, &mut blended)?;
+        *frame = blended;
+        Ok(())
+    }
+}
+
+// mean distance the board points moved between two frames
+fn motion(a: &Vector<Point2f>, b: &Vector<Point2f>) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return f32::MAX;
+    }
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a.x - b.x).hypot(a.y - b.y))
+        .sum::<f32>()
+        / a.len() as f32
+}
+
+fn status(frame: &mut Mat, line: i32, text: &str) -> opencv::Result<()> {
+    imgproc::put_text(
+        frame,
+        text,
+        Point::new(10, 30 + 28 * line),
+        imgproc::FONT_HERSHEY_SIMPLEX,
+        0.7,
+        Scalar::new(0.0, 255.0, 255.0, 0.0),
+        2,
+        imgproc::LINE_AA,
+        false,
+    )
+}
+
+/// calibrate from a live camera: the preview shows the detected board and the covered part
+/// of the sensor, space captures a frame (or `auto` captures when the board is held still),
+/// `c` or enter calibrates and writes the calibration, `q` or escape quits without it
+pub fn calibrate(
+    device: i32,
+    calibration_file: &str,
+    board: Board,
+    model: CameraModel,
+    auto: bool,
+    save_dir: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let mut capture = VideoCapture::new(device, CAP_ANY)?;
+    if !capture.is_opened()? {
+        return Err(format!("could not open camera {device}").into());
+    }
+    if let Some(save_dir) = save_dir {
+        fs::create_dir_all(save_dir)?;
+    }
+    highgui::named_window_def(WINDOW)?;
+    info!("space captures, c calibrates, q quits");
+
+    let mut calibrator = Calibrator::new(board, model);
+    let mut coverage = None;
+    let mut previous: Option<Vector<Point2f>> = None;
+    let mut stable_since = None;
+    let mut last_capture = Instant::now() - AUTO_INTERVAL;
+    let mut frame = Mat::default();
+    let result: Result<Option<CalibrationResult>, Box<dyn Error>> = loop {
+        if !capture.read(&mut frame)? || frame.empty() {
+            break Err("the camera stopped delivering frames".into());
+        }
+        let size = frame.size()?;
+        let coverage = coverage.get_or_insert_with(|| Coverage::new(size));
+        let corners = board.detect(&frame)?;
+
+        // the board is still when it barely moved since the last frame
+        let still = match (&corners, &previous) {
+            (Some(corners), Some(previous)) => motion(corners, previous) < STABLE_PIXELS,
+            _ => false,
+        };
+        stable_since = if still {
+            stable_since.or(Some(Instant::now()))
+        } else {
+            None
+        };
+        previous = corners.clone();
+
+        let mut preview = frame.try_clone()?;
+        coverage.draw(&mut preview)?;
+        if let Some(corners) = &corners {
+            draw_chessboard_corners(&mut preview, board.pattern(), corners, true)?;
+        }
+        status(
+            &mut preview,
+            0,
+            &format!(
+                "{} captures, {:.0}% of the sensor covered",
+                calibrator.len(),
+                coverage.fraction() * 100.0
+            ),
+        )?;
+        if corners.is_none() {
+            status(&mut preview, 1, "no board")?;
+        }
+        highgui::imshow(WINDOW, &preview)?;
+
+        let key = highgui::wait_key(1)?;
+        let held = auto
+            && last_capture.elapsed() >= AUTO_INTERVAL
+            && stable_since.is_some_and(|since| since.elapsed() >= STABLE_FOR);
+        if let (Some(corners), true) = (&corners, key == ' ' as i32 || held) {
+            coverage.add(corners);
+            calibrator.add_corners(
+                &format!("capture {}", calibrator.len() + 1),
+                corners.clone(),
+            );
+            last_capture = Instant::now();
+            stable_since = None;
+            if let Some(save_dir) = save_dir {
+                let path = format!("{save_dir}/frame_{:04}.jpg", calibrator.len());
+                imgcodecs::imwrite_def(&path, &frame)?;
+            }
+            info!(
+                "capture {}, {:.0}% covered",
+                calibrator.len(),
+                coverage.fraction() * 100.0
+            );
+        }
+        match key {
+            k if k == 'q' as i32 || k == KEY_ESCAPE => break Ok(None),
+            k if k == 'c' as i32 || k == KEY_ENTER => {
+                if calibrator.len() >= MIN_CAPTURES {
+                    break Ok(Some(calibrator.calibrate(size, None)?));
+                }
+                warn!(
+                    "{} captures, at least {MIN_CAPTURES} are needed",
+                    calibrator.len()
+                );
+            }
+            _ => {}
+        }
+    };
+    highgui::destroy_window(WINDOW)?;
+
+    let Some(CalibrationResult {
+        calibration, rms, ..
+    }) = result?
+    else {
+        info!("quit without calibrating");
+        return Ok(());
+    };
+    calibration.save(calibration_file, None)?;
+    info!(
+        "rms {rms:.3}px from {} captures, store to file {calibration_file}",
+        calibrator.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use opencv_undistort::{progress, provenance};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    /// one json object per line on stderr
+    Json,
+}
+
+struct Logger {
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = match self.format {
+            LogFormat::Plain => match record.level() {
+                Level::Error | Level::Warn => format!("[!] {}", record.args()),
+                Level::Info => record.args().to_string(),
+                Level::Debug | Level::Trace => format!("[{}] {}", record.level(), record.args()),
+            },
+            LogFormat::Json => serde_json::json!({
+                "timestamp": SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+                "level": record.level().as_str().to_lowercase(),
+                "run_id": provenance::run_id(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        };
+        progress::suspend(|| eprintln!("{line}"));
+    }
+
+    fn flush(&self) {}
+}
+
+/// route all diagnostics through the logger: errors only with `quiet`, info by default and
+/// debug/trace with each `-v`. Progress bars are only drawn for plain output
+pub fn init(quiet: bool, verbose: u8, format: LogFormat) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    if quiet || format == LogFormat::Json {
+        progress::hide();
+    }
+    log::set_boxed_logger(Box::new(Logger { format })).expect("logger is set once");
+    log::set_max_level(level);
+}
@@ -1,33 +1,54 @@
 //#![cfg(ocvrs_has_module_imgproc)]
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
-use std::time::Instant;
-
-use clap::{Parser, Subcommand, arg};
-use indicatif::{HumanDuration, ProgressBar};
-use opencv::calib3d::{
-    RANSAC, SOLVEPNP_ITERATIVE, get_optimal_new_camera_matrix, init_undistort_rectify_map,
-    solve_pnp, solve_pnp_def,
-};
-use opencv::core::{
-    Point2f, Point3f, Size, TermCriteria, TermCriteria_EPS, TermCriteria_MAX_ITER, Vector, no_array,
-};
-use opencv::imgcodecs::imwrite_def;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::{FromArgMatches, Parser, Subcommand};
+use indicatif::HumanDuration;
+use log::{error, info, warn};
+use opencv::core::{Point2f, Size, Vector};
+use opencv::imgcodecs;
 use opencv::prelude::*;
-use opencv::{imgcodecs, imgproc, not_opencv_branch_5, opencv_branch_5};
-use serde::{Deserialize, Serialize};
+use opencv_undistort::manifest::{Entry, Manifest};
+use opencv_undistort::*;
+use rayon::prelude::*;
+use serde::Serialize;
 
-opencv_branch_5! {
-    use opencv::calib::{find_chessboard_corners_def, draw_chessboard_corners, calibrate_camera_def};
-    use opencv::mod_3d::{undistort_def, init_undistort_rectify_map};
-}
+mod config;
+mod confirm;
+mod http;
+mod live;
+mod logging;
+mod pipeline;
+mod selftest;
+mod watch;
 
-not_opencv_branch_5! {
-    use opencv::calib3d::{find_chessboard_corners_def,  calibrate_camera_def, undistort_def};
-}
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+// an option of the configuration file is repeated on the command line, the last one wins
+#[command(version, about, long_about = None, args_override_self = true)]
 struct Args {
+    /// only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// more detail, -vv for everything
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    #[arg(long, global = true, value_enum, default_value_t = logging::LogFormat::Plain)]
+    log_format: logging::LogFormat,
+    /// overwrite existing files and write into non-empty directories without asking
+    #[arg(short, long, visible_alias = "force", global = true)]
+    yes: bool,
+    /// threads for opencv and the worker pools, defaults to all cores
+    #[arg(long, global = true)]
+    threads: Option<NonZeroUsize>,
+    /// order of the images read from a directory
+    #[arg(long, global = true, value_enum, default_value_t = order::SortOrder::Natural)]
+    sort: order::SortOrder,
     #[command(subcommand)]
     action: Action,
 }
@@ -35,316 +56,2152 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Action {
     Calibrate {
+        #[arg(short, long, required_unless_present_any = ["files_from", "video"])]
+        calibration_dir: Option<String>,
+        /// NUL separated image paths (find -print0) instead of a directory, `-` for stdin
+        #[arg(long, conflicts_with = "calibration_dir")]
+        files_from: Option<String>,
+        #[command(flatten)]
+        selection: select::Selection,
         #[arg(short, long)]
-        calibration_dir: String,
+        calibration_file: String,
+        #[command(flatten)]
+        board: board::BoardArgs,
+        /// print a json summary of the result to stdout
+        #[arg(long)]
+        json: bool,
+        /// exit with code 5 when the rms reprojection error is above this, the calibration
+        /// is still written
+        #[arg(long)]
+        max_rms: Option<f64>,
+        /// free scaling of the undistorted view of pinhole calibrations, 0 keeps only valid
+        /// pixels, 1 keeps all pixels with black borders
+        #[arg(long, default_value_t = 1.0, value_parser = unit_interval)]
+        alpha: f64,
+        /// drop the image with the largest reprojection error and calibrate again while it is
+        /// above this many pixels, at least 3 images are kept
+        #[arg(long, value_name = "PX")]
+        reject_threshold: Option<f64>,
+        /// keep detection progress in this file and resume from it after an interruption
+        #[arg(long)]
+        state: Option<String>,
+        /// dome-port housing: fisheye model with all four distortion terms and refraction
+        /// warnings
+        #[arg(long)]
+        underwater: bool,
+        /// in-water images of the board to check the underwater calibration against
+        #[arg(long, requires = "underwater")]
+        validate_dir: Option<String>,
+        /// lens model to fit, fisheye for gopro style wide angle lenses, affine for telecentric
+        /// lenses and microscopes
+        #[arg(long, value_enum, default_value_t = CameraModel::Pinhole, conflicts_with = "underwater")]
+        model: CameraModel,
+        #[command(flatten)]
+        pinhole: pinhole::PinholeArgs,
+        /// append the calibration to the history of `--camera-id` in this directory, see `drift`
+        #[arg(long, requires = "camera_id")]
+        history: Option<String>,
+        #[arg(long, requires = "history")]
+        camera_id: Option<String>,
+        /// images processed at the same time, defaults to `--threads`
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+        /// sample the board from a video instead of images, e.g. a phone video slowly
+        /// moving around the board
+        #[arg(long, conflicts_with_all = ["calibration_dir", "files_from", "state", "thermal"])]
+        video: Option<String>,
+        /// the sharpest frame of every this many frames of `--video` is a candidate
+        #[arg(long, default_value_t = 15, requires = "video")]
+        frame_interval: usize,
+        /// stop sampling `--video` after this many boards
+        #[arg(long, default_value_t = 60, requires = "video")]
+        max_frames: usize,
+        /// format of the calibration file, by default from its extension
+        #[arg(long, value_enum)]
+        format: Option<filestorage::Format>,
+        /// write every image with its detected corners and outcome and a heatmap of the
+        /// covered sensor into this directory
+        #[arg(long, conflicts_with = "video")]
+        debug_dir: Option<String>,
+        /// keep the detected corners of every image in this file, `recalibrate` adds images
+        /// to them without detecting these again
+        #[arg(long, conflicts_with = "video")]
+        corners_file: Option<String>,
+        /// find the number of interior corners of the chessboard in the first images instead
+        /// of `--board-width` and `--board-height`
+        #[arg(long, conflicts_with_all = ["board_width", "board_height", "video"])]
+        auto_board_size: bool,
+    },
+    /// solve a calibration again from the corners of `calibrate --corners-file` and the
+    /// boards of new images, only the new images are detected
+    Recalibrate {
+        /// corners of the earlier images, the new images are added to it
+        #[arg(long)]
+        corners_file: String,
+        /// directory with the new images, images already in the corners file are skipped
+        #[arg(short = 'd', long)]
+        calibration_dir: Option<String>,
+        /// NUL separated image paths (find -print0) instead of a directory, `-` for stdin
+        #[arg(long, conflicts_with = "calibration_dir")]
+        files_from: Option<String>,
+        #[command(flatten)]
+        selection: select::Selection,
         #[arg(short, long)]
         calibration_file: String,
+        /// forget the images of the corners file that were deleted since
+        #[arg(long)]
+        prune: bool,
+        #[arg(long, value_enum, default_value_t = CameraModel::Pinhole)]
+        model: CameraModel,
+        #[command(flatten)]
+        pinhole: pinhole::PinholeArgs,
+        /// free scaling of the undistorted view of pinhole calibrations, see `calibrate`
+        #[arg(long, default_value_t = 1.0, value_parser = unit_interval)]
+        alpha: f64,
+        /// drop the image with the largest reprojection error and calibrate again while it is
+        /// above this many pixels, at least 3 images are kept
+        #[arg(long, value_name = "PX")]
+        reject_threshold: Option<f64>,
+        /// images processed at the same time, defaults to `--threads`
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+        /// format of the calibration file, by default from its extension
+        #[arg(long, value_enum)]
+        format: Option<filestorage::Format>,
     },
     Correct {
+        #[arg(short, long, required_unless_present_any = ["preset", "zoom_profiles", "maps"])]
+        calibration_file: Option<String>,
+        /// built-in lens profile instead of a calibration file, see `presets`
+        #[arg(long)]
+        preset: Option<String>,
+        /// zoom lens profiles from `zoom-add`, picked by the exif focal length of each image
+        #[arg(long, conflicts_with_all = ["calibration_file", "preset"])]
+        zoom_profiles: Option<String>,
+        /// lens model of a calibration file that does not record it, e.g. written by another
+        /// tool, files from `calibrate` pick the right model on their own
+        #[arg(long, value_enum, conflicts_with = "zoom_profiles")]
+        model: Option<CameraModel>,
+        /// view rendered from omnidir calibrations, perspective by default
+        #[arg(long, value_enum)]
+        rectification: Option<omnidir::Rectification>,
+        /// free scaling of the undistorted view instead of the one stored in the calibration,
+        /// 0 keeps only valid pixels, 1 keeps all pixels with black borders
+        #[arg(long, conflicts_with = "zoom_profiles", value_parser = unit_interval)]
+        alpha: Option<f64>,
+        /// crop the corrected images to the part with only valid pixels
+        #[arg(long, overrides_with = "no_crop")]
+        crop: bool,
+        #[arg(long)]
+        no_crop: bool,
+        /// directory or s3://bucket/prefix with the `s3` feature
+        #[arg(short = 'd', long, required_unless_present = "files_from")]
+        correction_dir: Option<String>,
+        /// NUL separated image paths (find -print0) instead of a directory, `-` for stdin
+        #[arg(long, conflicts_with = "correction_dir")]
+        files_from: Option<String>,
+        #[command(flatten)]
+        selection: select::Selection,
+        /// directory or s3://bucket/prefix with the `s3` feature
+        #[arg(short, long)]
+        output_dir: String,
+        /// write a json manifest of every input, its outcome, output and checksum
+        #[arg(long)]
+        manifest: Option<String>,
+        /// keep progress in this file and skip the images it lists as corrected, local
+        /// directories only
+        #[arg(long)]
+        state: Option<String>,
+        /// images processed at the same time, defaults to `--threads`
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+        /// pixel interpolation of the remap, lanczos is the sharpest and slowest
+        #[arg(long, value_enum, default_value_t = Interpolation::Linear)]
+        interpolation: Interpolation,
+        /// keep the remap tables of each calibration and image size in this directory and
+        /// reuse them in later runs
+        #[arg(long)]
+        map_cache: Option<String>,
+        /// remap with the tables of this file from `make-maps` or another tool instead of a
+        /// calibration, only images of their size are corrected
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "calibration_file",
+                "preset",
+                "zoom_profiles",
+                "model",
+                "rectification",
+                "alpha",
+                "map_cache",
+                "crop",
+            ]
+        )]
+        maps: Option<String>,
+        /// remap on the gpu, `--gpu` alone is OpenCL, `--gpu cuda` needs the `cuda` feature
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "opencl")]
+        gpu: Option<gpu::Gpu>,
+        #[command(flatten)]
+        encoding: encoding::Encoding,
+        /// keep running and correct every image arriving in the correction directory, images
+        /// with an up to date output are skipped, also after a restart
+        #[arg(long, conflicts_with_all = ["files_from", "manifest", "state"])]
+        watch: bool,
+        /// a new image is corrected once its size stayed the same this long
+        #[arg(long, default_value_t = 1000, requires = "watch")]
+        settle_ms: u64,
+        /// serve prometheus metrics of the watcher on this port
+        #[arg(long, requires = "watch")]
+        metrics_port: Option<u16>,
+        /// custom s3 endpoint, e.g. a minio server
+        #[cfg(feature = "s3")]
+        #[arg(long)]
+        s3_endpoint: Option<String>,
+        /// objects transferred and corrected at the same time
+        #[cfg(feature = "s3")]
+        #[arg(long, default_value_t = 8)]
+        s3_concurrency: usize,
+    },
+    /// calibrate from a live camera with a preview of the detected board and the covered
+    /// part of the sensor
+    CalibrateLive {
+        /// camera index, 0 is the first webcam
+        #[arg(long, default_value_t = 0)]
+        device: i32,
         #[arg(short, long)]
         calibration_file: String,
+        #[command(flatten)]
+        board: board::BoardArgs,
+        #[arg(long, value_enum, default_value_t = CameraModel::Pinhole)]
+        model: CameraModel,
+        /// capture on its own when the board is held still
+        #[arg(long)]
+        auto: bool,
+        /// also write the captured frames to calibrate from them again later
+        #[arg(long)]
+        save_dir: Option<String>,
+    },
+    /// how the calibrations in the history of a camera changed over time
+    Drift {
+        #[arg(long)]
+        history: String,
+        #[arg(long)]
+        camera_id: String,
+        /// svg plot of every value over time
+        #[arg(long)]
+        plot: Option<String>,
+    },
+    /// approximate calibration from straight edges in ordinary photos when no board was
+    /// captured, only radial distortion is estimated
+    PlumbLine {
+        /// photos of one camera with buildings, door frames, shelves or horizons
+        #[arg(short, long)]
+        image_dir: String,
         #[arg(short, long)]
-        correction_dir: String,
+        calibration_file: String,
+        /// focal length in pixels if known, otherwise a 60 degree field of view is assumed
+        #[arg(long)]
+        focal_px: Option<f64>,
+    },
+    /// write a calibration in another format, json or opencv FileStorage yaml/xml
+    Convert {
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long)]
+        output: String,
+        /// format of the output, by default from its extension
+        #[arg(long, value_enum)]
+        format: Option<filestorage::Format>,
+    },
+    /// list the built-in lens profiles
+    Presets,
+    /// add a calibration at one focal length to the zoom profiles of a lens
+    ZoomAdd {
+        #[arg(long)]
+        zoom_profiles: String,
+        #[arg(short, long)]
+        calibration_file: String,
+        /// focal length the calibration images were taken at
+        #[arg(long)]
+        focal_length_mm: f64,
+    },
+    /// calibrate and correct a synthetic chessboard dataset and compare the result with the
+    /// known camera, checks that opencv works
+    SelfTest,
+    /// calibrate and correct the cameras of a rig as declared in a pipeline file
+    Run {
+        /// toml file with a [[camera]] table per camera, see the readme
+        #[arg(long)]
+        config: String,
+    },
+    /// pose of the calibration board in each image, one json line per image on stdout
+    #[command(alias = "solve")]
+    Pose {
+        #[arg(short, long, required_unless_present = "preset")]
+        calibration_file: Option<String>,
+        /// built-in lens profile instead of a calibration file, see `presets`
+        #[arg(long)]
+        preset: Option<String>,
+        #[arg(short, long)]
+        image_dir: String,
+        #[command(flatten)]
+        selection: select::Selection,
+        #[command(flatten)]
+        board: board::BoardArgs,
+        /// the images were corrected with the same calibration, without cropping
+        #[arg(long)]
+        corrected: bool,
+        /// write each image with the detected and reprojected corners and the board axes
+        #[arg(long)]
+        overlay_dir: Option<String>,
+    },
+    /// reprojection errors of a calibration on board images it was not solved from, e.g. to
+    /// check a camera after remounting
+    Validate {
+        #[arg(short, long)]
+        calibration_file: String,
+        /// held-out images of the board
+        #[arg(short, long)]
+        test_dir: String,
+        #[command(flatten)]
+        selection: select::Selection,
+        // by default the board stored in the calibration file
+        #[command(flatten)]
+        board: board::BoardArgs,
+        /// exit with code 5 when `--statistic` of all corners is above this many pixels
+        #[arg(long, value_name = "PX")]
+        max_error: Option<f64>,
+        #[arg(long, value_enum, default_value_t = validate::Statistic::P95, requires = "max_error")]
+        statistic: validate::Statistic,
+        /// print the errors of every image and overall as json to stdout
+        #[arg(long)]
+        json: bool,
+        /// images processed at the same time, defaults to `--threads`
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+    },
+    /// undistort images of a plane and warp it to a fronto-parallel view at a fixed scale,
+    /// spanned by the calibration board or four marked points
+    Rectify {
+        #[arg(short, long, required_unless_present = "preset")]
+        calibration_file: Option<String>,
+        /// built-in lens profile instead of a calibration file, see `presets`
+        #[arg(long)]
+        preset: Option<String>,
+        #[arg(short, long)]
+        image_dir: String,
+        #[command(flatten)]
+        selection: select::Selection,
         #[arg(short, long)]
         output_dir: String,
+        // by default the board stored in the calibration file
+        #[command(flatten)]
+        board: board::BoardArgs,
+        /// image points x,y of a rectangle on the plane, clockwise from the top left, instead
+        /// of the board
+        #[arg(long, num_args = 4, value_name = "X,Y", value_parser = point,
+            requires_all = ["points_width_mm", "points_height_mm"])]
+        points: Option<Vec<Point2f>>,
+        /// width of the rectangle of `--points`
+        #[arg(long, requires = "points")]
+        points_width_mm: Option<f32>,
+        #[arg(long, requires = "points")]
+        points_height_mm: Option<f32>,
+        /// scale of the output, by default the reference keeps about its size in pixels
+        #[arg(long)]
+        mm_per_pixel: Option<f64>,
+        /// size of the output centered on the reference, by default it covers the whole image
+        #[arg(long, requires = "output_height")]
+        output_width: Option<i32>,
+        #[arg(long, requires = "output_width")]
+        output_height: Option<i32>,
+        #[arg(long, value_enum, default_value_t = Interpolation::Linear)]
+        interpolation: Interpolation,
     },
-    Solve {
+    /// calibrate a stereo rig from left/right image pairs
+    StereoCalibrate {
+        #[arg(short, long)]
+        left_dir: String,
+        #[arg(short, long)]
+        right_dir: String,
         #[arg(short, long)]
         calibration_file: String,
+        #[command(flatten)]
+        pairing: stereo::PairingArgs,
+        #[command(flatten)]
+        board: board::BoardArgs,
+        /// lens model of both cameras, pinhole or fisheye
+        #[arg(long, value_enum, default_value_t = CameraModel::Fisheye)]
+        model: CameraModel,
+        /// write camera frusta and a few board poses as a ply wireframe
+        #[arg(long)]
+        rig_ply: Option<String>,
+    },
+    /// camera pose and image scale from single aruco markers of known size, one json line
+    /// per marker on stdout
+    MarkerPose {
+        #[arg(short, long, required_unless_present = "preset")]
+        calibration_file: Option<String>,
+        /// built-in lens profile instead of a calibration file, see `presets`
+        #[arg(long)]
+        preset: Option<String>,
+        /// images corrected with the same calibration
         #[arg(short, long)]
         image_dir: String,
+        #[arg(long, value_enum, default_value_t = marker::Dictionary::Dict4x4_50)]
+        dictionary: marker::Dictionary,
+        /// printed side length of the marker's black border
+        #[arg(long)]
+        marker_size_mm: f32,
+        /// only report the marker with this id
+        #[arg(long)]
+        marker_id: Option<i32>,
+        /// the images are not corrected, undistort the marker corners instead
+        #[arg(long)]
+        distorted: bool,
+    },
+    /// write the structured light patterns for `projector-calibrate` as png images
+    ProjectorPatterns {
+        #[command(flatten)]
+        projector: projector::ProjectorArgs,
+        #[arg(short, long)]
+        output_dir: String,
+    },
+    /// calibrate a projector as an inverse camera together with the camera that captured
+    /// the projected patterns on a board, one subdirectory of captures per board pose
+    ProjectorCalibrate {
+        #[arg(long)]
+        captures_dir: String,
+        #[arg(short, long)]
+        calibration_file: String,
+        #[command(flatten)]
+        projector: projector::ProjectorArgs,
+        #[command(flatten)]
+        board: board::BoardArgs,
+    },
+    /// write rectified left/right image pairs using a stereo calibration file
+    StereoCorrect {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(short, long)]
+        left_dir: String,
+        #[arg(short, long)]
+        right_dir: String,
+        #[arg(short, long)]
+        output_dir: String,
+        #[command(flatten)]
+        pairing: stereo::PairingArgs,
+    },
+    /// undistort a video file
+    CorrectVideo {
+        #[arg(short, long, required_unless_present = "preset")]
+        calibration_file: Option<String>,
+        /// built-in lens profile instead of a calibration file, see `presets`
+        #[arg(long)]
+        preset: Option<String>,
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long)]
+        output: String,
+        /// fourcc for opencv or a codec name for ffmpeg (prores, av1, h264, hevc, ...)
+        #[arg(long, default_value = "mp4v")]
+        codec: String,
+        #[arg(long, value_enum, default_value_t = video::Encoder::Auto)]
+        encoder: video::Encoder,
+        /// lens model of a calibration file that does not record it
+        #[arg(long, value_enum)]
+        model: Option<CameraModel>,
+        /// view rendered from omnidir calibrations, perspective by default
+        #[arg(long, value_enum)]
+        rectification: Option<omnidir::Rectification>,
+    },
+    /// undistort frames between two gstreamer pipelines, the input ending in appsink and the
+    /// output starting with appsrc
+    Gstreamer {
+        #[arg(short, long)]
+        calibration_file: String,
+        /// e.g. "v4l2src ! videoconvert ! video/x-raw,format=BGR ! appsink"
+        #[arg(short, long)]
+        input_pipeline: String,
+        /// e.g. "appsrc ! videoconvert ! x264enc ! mp4mux ! filesink location=out.mp4"
+        #[arg(short, long)]
+        output_pipeline: String,
+        /// used when the input pipeline does not report a frame rate
+        #[arg(long, default_value_t = 30.0)]
+        fps: f64,
+    },
+    /// undistort length-prefixed frames from stdin to stdout, see src/pipe.rs for the format
+    Pipe {
+        #[arg(short, long)]
+        calibration_file: String,
+        /// serve prometheus metrics on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+    },
+    /// http server answering image uploads with the undistorted image, see src/http.rs
+    Serve {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(short, long, default_value_t = 8080)]
+        port: u16,
+        /// serve prometheus metrics on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+    },
+    /// grpc server with a streaming Undistort rpc, see proto/undistort.proto
+    #[cfg(feature = "grpc")]
+    Grpc {
+        /// directory of calibration json files, the calibration id is the file stem
+        #[arg(short, long)]
+        calibration_dir: String,
+        #[arg(short, long, default_value_t = 50051)]
+        port: u16,
+        /// serve prometheus metrics on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+    },
+    /// edge mode, correct the file of every capture-complete message and publish a reply
+    #[cfg(feature = "mqtt")]
+    Mqtt {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(short, long)]
+        output_dir: String,
+        #[arg(long, default_value = "localhost")]
+        host: String,
+        #[arg(long, default_value_t = 1883)]
+        port: u16,
+        #[arg(long, default_value = "opencv-undistort")]
+        client_id: String,
+        /// messages are {"path": "...", "id": ...} or a bare file path
+        #[arg(long, default_value = "capture/complete")]
+        capture_topic: String,
+        #[arg(long, default_value = "capture/corrected")]
+        done_topic: String,
+        /// serve prometheus metrics on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+    },
+    /// nvr pre-processing: pull an rtsp camera, undistort and re-publish it over rtsp
+    /// (go2rtc, mediamtx) and/or as an mjpeg http stream for frigate and similar
+    Nvr {
+        #[arg(short, long)]
+        calibration_file: String,
+        /// rtsp url or anything else ffmpeg can open
+        #[arg(short, long)]
+        input: String,
+        /// e.g. rtsp://go2rtc:8554/driveway_rect
+        #[arg(long)]
+        rtsp_output: Option<String>,
+        #[arg(long)]
+        mjpeg_port: Option<u16>,
+        #[arg(long, default_value_t = 80)]
+        jpeg_quality: i32,
+        /// serve prometheus metrics on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+    },
+    /// ros 2 node publishing undistorted images and camera info
+    #[cfg(feature = "ros2")]
+    Ros2 {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(long, default_value = "image_raw")]
+        image_topic: String,
+        #[arg(long, default_value = "image_rect")]
+        output_topic: String,
+        #[arg(long, default_value = "camera_info")]
+        camera_info_topic: String,
+    },
+    /// add the calibration to an OpenSfM camera_models.json or ODM cameras.json
+    ExportOpensfm {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(short, long)]
+        output: String,
+        /// width of the calibrated images
+        #[arg(long)]
+        image_width: i32,
+        #[arg(long)]
+        image_height: i32,
+        /// camera id to match the dataset exif, e.g. "v2 dji fc6310 5472 3648 brown 0.6666"
+        #[arg(long)]
+        camera_id: Option<String>,
+    },
+    /// blender python snippet (or .json) setting the camera intrinsics
+    ExportBlender {
+        #[arg(short, long)]
+        calibration_file: String,
+        /// .py to run in blender's text editor or .json
+        #[arg(short, long)]
+        output: String,
+        #[arg(long)]
+        image_width: i32,
+        #[arg(long)]
+        image_height: i32,
+        /// sensor width in mm, only scales the focal length
+        #[arg(long, default_value_t = 36.0)]
+        sensor_width: f64,
+    },
+    /// lensfun xml profile for darktable and rawtherapee and/or a hugin pto project of a
+    /// pinhole calibration
+    Export {
+        #[arg(short, long)]
+        calibration_file: String,
+        /// lensfun database file to write, e.g. ~/.local/share/lensfun/my-lens.xml
+        #[arg(long, required_unless_present = "pto")]
+        lensfun: Option<String>,
+        /// hugin project to write
+        #[arg(long)]
+        pto: Option<String>,
+        /// size of the images the profile is for, by default the calibrated size
+        #[arg(long, requires = "image_height")]
+        image_width: Option<i32>,
+        #[arg(long, requires = "image_width")]
+        image_height: Option<i32>,
+        #[command(flatten)]
+        lens: panotools::LensArgs,
+    },
+    /// write undistort_stmap.exr and distort_stmap.exr for nuke/after effects
+    ExportStmap {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(long)]
+        image_width: i32,
+        #[arg(long)]
+        image_height: i32,
+        #[arg(short, long)]
+        output_dir: String,
+    },
+    /// write the remap tables of a calibration as exr, pfm or raw float maps for a gpu or fpga
+    /// stage applying them itself, see `correct --maps`
+    MakeMaps {
+        #[arg(short, long, required_unless_present = "preset")]
+        calibration_file: Option<String>,
+        /// built-in lens profile instead of a calibration file, see `presets`
+        #[arg(long)]
+        preset: Option<String>,
+        /// size of the images the maps are for, by default the calibrated size
+        #[arg(long, requires = "image_height")]
+        image_width: Option<i32>,
+        #[arg(long, requires = "image_width")]
+        image_height: Option<i32>,
+        /// free scaling of the undistorted view instead of the one stored in the calibration
+        #[arg(long, value_parser = unit_interval)]
+        alpha: Option<f64>,
+        #[arg(short, long)]
+        output: String,
+        /// format of the maps, by default from the extension, .exr, .pfm or raw for any other
+        #[arg(long, value_enum)]
+        format: Option<mapfile::Format>,
+    },
+    /// undistorted coordinates of pixel positions in a csv or json file, e.g. feature tracks
+    /// or detection boxes, without touching the images
+    UndistortPoints {
+        #[arg(short, long, required_unless_present = "preset")]
+        calibration_file: Option<String>,
+        #[arg(long)]
+        preset: Option<String>,
+        /// csv with x and y columns, or a json array of [x, y] or {"x", "y"}, `-` for stdin
+        #[arg(short, long)]
+        input: String,
+        /// written in the format of the input, stdout by default
+        #[arg(short, long)]
+        output: Option<String>,
+        /// size of the images the points belong to, the calibration size by default
+        #[arg(long, requires = "image_height")]
+        image_width: Option<i32>,
+        #[arg(long, requires = "image_width")]
+        image_height: Option<i32>,
+        /// distort undistorted points instead, back into the original images
+        #[arg(long)]
+        inverse: bool,
+        /// normalized image coordinates on the undistorted side instead of pixels
+        #[arg(long)]
+        normalized: bool,
+    },
+    /// write left.yaml/right.yaml ros camera_info files for stereo_image_proc
+    StereoExportRos {
+        #[arg(short, long)]
+        calibration_file: String,
+        #[arg(short, long)]
+        output_dir: String,
     },
 }
 
-#[derive(Serialize, Deserialize)]
-struct Calibration {
-    camera_matrix: Vec<f64>,
-    dist_coeffs: Vec<f64>,
+/// result of `calibrate --json`, printed to stdout for wrapper scripts
+#[derive(Serialize)]
+struct CalibrationSummary<'a> {
+    calibration_file: &'a str,
+    rms: f64,
+    /// images found and images the calibration was solved from
+    images: usize,
+    used: usize,
+    image_size: [i32; 2],
+    /// reprojection error of each used image, worst first
+    views: Vec<ViewError>,
+    /// images dropped by `--reject-threshold`
+    rejected: Vec<ViewError>,
+    /// images that could not be read
+    failed: Vec<FailedImage>,
+    warnings: Vec<String>,
+    stages: StageTimings,
 }
 
-// https://docs.opencv.org/4.x/dc/dbb/tutorial_py_calibration.html
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
+/// an image skipped because it could not be read or processed
+#[derive(Serialize)]
+struct FailedImage {
+    image: String,
+    reason: String,
+}
+
+/// seconds spent in each calibration stage
+#[derive(Serialize, Default)]
+struct StageTimings {
+    detect: f64,
+    solve: f64,
+    write: f64,
+}
+
+// prometheus endpoint of the long running modes
+fn serve_metrics(port: Option<u16>) -> std::io::Result<()> {
+    match port {
+        Some(port) => metrics::serve(port),
+        None => Ok(()),
+    }
+}
+
+fn main() -> ExitCode {
+    // SAFETY: first thing in main, before opencv or any other thread starts
+    unsafe { stmap::enable_exr() };
+    let args = match config::with_defaults(std::env::args_os().collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("[!] {e}");
+            return ExitCode::from(USAGE);
+        }
+    };
+    let matches = config::command().get_matches_from(args);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logging::init(args.quiet, args.verbose, args.log_format);
+    confirm::assume_yes(args.yes);
+    info!(
+        "run {} version {}",
+        provenance::run_id(),
+        provenance::TOOL_VERSION
+    );
+    order::init(args.sort);
+    let workers = batch_workers(&args.action);
+    if let Err(e) = init_threads(args.threads.map(NonZeroUsize::get), workers) {
+        error!("{e}");
+        return ExitCode::FAILURE;
+    }
+
+    match run(args.action) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            exit_code(e.as_ref())
+        }
+    }
+}
+
+// exit code of invalid arguments or configuration
+const USAGE: u8 = 2;
+
+// exit code of an error returned by a subcommand, io errors anywhere in the source chain
+// map to `Code::Io`
+fn exit_code(error: &(dyn Error + 'static)) -> ExitCode {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(failure) = error.downcast_ref::<exit::Failure>() {
+            return ExitCode::from(failure.code() as u8);
+        }
+        if error.is::<std::io::Error>() {
+            return ExitCode::from(exit::Code::Io as u8);
+        }
+        source = error.source();
+    }
+    ExitCode::FAILURE
+}
+
+// size rayon's global pool and bound opencv's internal parallelism. The workers of a batch
+// pool each run opencv, they get their share of the threads once here and not per pool, pools
+// of parallel pipeline cameras or concurrent requests live at the same time
+fn init_threads(
+    threads: Option<usize>,
+    workers: Option<Option<usize>>,
+) -> Result<(), Box<dyn Error>> {
+    let threads = match threads {
+        Some(threads) => {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()?;
+            threads
+        }
+        None => rayon::current_num_threads(),
+    };
+    let share = match workers {
+        Some(workers) => threads / workers.unwrap_or(threads),
+        None => threads,
+    };
+    opencv::core::set_num_threads(share.max(1) as i32)?;
+    Ok(())
+}
+
+// workers of the batch pool the subcommand runs, as many as the global pool without `--jobs`.
+// `None` when it works on one image at a time and opencv gets all the threads
+fn batch_workers(action: &Action) -> Option<Option<usize>> {
+    match action {
+        Action::Calibrate { jobs, .. }
+        | Action::Recalibrate { jobs, .. }
+        | Action::Correct { jobs, .. }
+        | Action::Validate { jobs, .. } => Some(jobs.map(NonZeroUsize::get)),
+        // the pipeline splits the pool among its cameras, the servers run a request per worker
+        Action::Run { .. } | Action::Serve { .. } => Some(None),
+        #[cfg(feature = "grpc")]
+        Action::Grpc { .. } => Some(None),
+        _ => None,
+    }
+}
+
+// --alpha between 0 and 1
+fn unit_interval(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(alpha) if (0.0..=1.0).contains(&alpha) => Ok(alpha),
+        Ok(_) => Err("must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn point(value: &str) -> Result<Point2f, String> {
+    let (x, y) = value.split_once(',').ok_or("expected x,y")?;
+    match (x.trim().parse::<f32>(), y.trim().parse::<f32>()) {
+        (Ok(x), Ok(y)) => Ok(Point2f::new(x, y)),
+        (Err(e), _) | (_, Err(e)) => Err(e.to_string()),
+    }
+}
+
+// errors of the workers of an image batch
+type WorkerError = Box<dyn Error + Send + Sync>;
+
+// calibrations of a correct run, the remap tables of a fixed lens are built once per image
+// size and shared by the workers. Without a lens the tables of `--maps` correct every image
+struct Correction {
+    lens: Option<zoom::Lens>,
+    interpolation: Interpolation,
+    map_cache: Option<String>,
+    gpu: Option<gpu::Gpu>,
+    encoding: encoding::Encoding,
+    crop: bool,
+    // tables of `--maps`, used instead of a calibration for images of their size
+    maps: Option<(Mat, Mat)>,
+    undistorters: Mutex<HashMap<(i32, i32), Arc<Undistorter>>>,
+}
+
+impl Correction {
+    fn new(
+        lens: Option<zoom::Lens>,
+        interpolation: Interpolation,
+        map_cache: Option<String>,
+        gpu: Option<gpu::Gpu>,
+        encoding: encoding::Encoding,
+        crop: bool,
+    ) -> Self {
+        Correction {
+            lens,
+            interpolation,
+            map_cache,
+            gpu,
+            encoding,
+            crop,
+            maps: None,
+            undistorters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn with_maps(self, maps: Option<(Mat, Mat)>) -> Self {
+        Correction { maps, ..self }
+    }
+
+    fn build(
+        &self,
+        calibration: Option<&Calibration>,
+        size: Size,
+    ) -> Result<Arc<Undistorter>, Box<dyn Error>> {
+        let undistorter = match (&self.maps, calibration, &self.map_cache) {
+            (Some((mapx, mapy)), _, _) => {
+                let maps_size = mapx.size()?;
+                if maps_size != size {
+                    return Err(format!(
+                        "image is {}x{}, the maps are for {}x{}",
+                        size.width, size.height, maps_size.width, maps_size.height
+                    )
+                    .into());
+                }
+                Undistorter::from_maps(mapx.try_clone()?, mapy.try_clone()?)?
+            }
+            (None, Some(calibration), Some(dir)) => Undistorter::cached(calibration, size, dir)?,
+            (None, Some(calibration), None) => Undistorter::new(calibration, size)?,
+            (None, None, _) => return Err("no calibration or maps to correct with".into()),
+        };
+        Ok(Arc::new(
+            undistorter
+                .with_interpolation(self.interpolation)
+                .with_crop(self.crop)
+                .on_gpu(self.gpu)?,
+        ))
+    }
+
+    fn undistorter(
+        &self,
+        calibration: Option<&Calibration>,
+        size: Size,
+    ) -> Result<Arc<Undistorter>, Box<dyn Error>> {
+        // zoom profiles give every image its own calibration
+        if let Some(zoom::Lens::Zoom(_)) = self.lens {
+            return self.build(calibration, size);
+        }
+        let mut undistorters = self.undistorters.lock().unwrap();
+        if let Some(undistorter) = undistorters.get(&(size.width, size.height)) {
+            return Ok(undistorter.clone());
+        }
+        let undistorter = self.build(calibration, size)?;
+        undistorters.insert((size.width, size.height), undistorter.clone());
+        Ok(undistorter)
+    }
+}
+
+// path of an input below the correction directory, images of subdirectories keep their place
+// under the output directory
+fn relative_name<'a>(correction_dir: Option<&str>, image: &'a str) -> &'a Path {
+    correction_dir
+        .and_then(|dir| Path::new(image).strip_prefix(dir).ok())
+        .or_else(|| Path::new(image).file_name().map(Path::new))
+        .unwrap_or(Path::new(image))
+}
 
-    match args.action {
+// directory and file name of the corrected image of an input
+fn output_path(correction: &Correction, name: &Path, output_dir: &str) -> (String, String) {
+    let dir = match name
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        Some(parent) => format!("{output_dir}/{}", parent.display()),
+        None => output_dir.to_string(),
+    };
+    let extension = correction.encoding.extension(name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    (dir, format!("u_{stem}.{extension}"))
+}
+
+fn output_file(correction: &Correction, name: &Path, output_dir: &str) -> String {
+    let (dir, new_image) = output_path(correction, name, output_dir);
+    format!("{dir}/{new_image}")
+}
+
+// undistort one image of a local directory, `name` is its path below the input directory,
+// the output path and its encoded bytes
+fn correct_image(
+    correction: &Correction,
+    path: &str,
+    name: &Path,
+    output_dir: &str,
+) -> Result<(String, Vec<u8>), Box<dyn Error>> {
+    let calibration = correction
+        .lens
+        .as_ref()
+        .map(|lens| lens.for_image(path))
+        .transpose()?;
+    let source = fs::read(path)?;
+    // 16 bit and grayscale images stay as they are
+    let img = imgcodecs::imdecode(
+        &Vector::<u8>::from_slice(&source),
+        imgcodecs::IMREAD_ANYDEPTH | imgcodecs::IMREAD_ANYCOLOR,
+    )?;
+    if img.empty() {
+        return Err(format!("could not read {path}").into());
+    }
+    let (dir, new_image) = output_path(correction, name, output_dir);
+    fs::create_dir_all(&dir)?;
+    let extension = correction.encoding.extension(name);
+    info!("save new image {new_image}");
+
+    let dst_undistort = correction
+        .undistorter(calibration.as_ref(), img.size()?)?
+        .undistort(&img)?;
+
+    let output = format!("{dir}/{new_image}");
+    let mut encoded = correction.encoding.encode(
+        &dst_undistort,
+        &extension,
+        &exif::Metadata::read(path, &source),
+    )?;
+    if let Some(calibration) = &calibration {
+        provenance::tag_jpeg(&mut encoded, calibration);
+    }
+    write_aside(&output, &encoded)?;
+    write_aside(&format!("{dir}/u1_{new_image}"), &encoded)?;
+    Ok((output, encoded))
+}
+
+// written next to `path` and renamed, an interrupted run leaves no truncated image behind
+// that looks newer than its input
+fn write_aside(path: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let partial = format!("{path}.{}.partial", std::process::id());
+    fs::write(&partial, bytes)?;
+    fs::rename(partial, path)
+}
+
+// https://docs.opencv.org/4.x/dc/dbb/tutorial_py_calibration.html
+fn run(action: Action) -> Result<(), Box<dyn Error>> {
+    match action {
         Action::Calibrate {
             calibration_dir,
+            files_from,
+            selection,
             calibration_file,
+            board,
+            json,
+            max_rms,
+            alpha,
+            reject_threshold,
+            state,
+            underwater,
+            validate_dir,
+            model,
+            pinhole,
+            history,
+            camera_id,
+            jobs,
+            video,
+            frame_interval,
+            max_frames,
+            format,
+            debug_dir,
+            corners_file,
+            auto_board_size,
         } => {
-            // termination criteria
-            let criteria = TermCriteria {
-                typ: TermCriteria_EPS + TermCriteria_MAX_ITER,
-                max_count: 30,
-                epsilon: 0.001,
+            confirm::overwrite(&calibration_file)?;
+            if let Some(corners_file) = &corners_file {
+                confirm::overwrite(corners_file)?;
+            }
+            if let Some(debug_dir) = &debug_dir {
+                confirm::output_dir(debug_dir)?;
+            }
+            let mut board = board.board()?;
+            let mut warnings = Vec::new();
+            // images that could not be read, the calibration goes ahead without them
+            let mut failed = Vec::new();
+            // corners of every image for `--debug-dir` and `--corners-file`
+            let mut image_corners = Vec::new();
+            let model = if underwater {
+                underwater::warn_refraction();
+                CameraModel::Fisheye
+            } else {
+                model
             };
-
-            // prepare object points, like (0,0,0), (1,0,0), (2,0,0) ....,(6,5,0)
-            let width_dim = 11;
-            let height_dim = 8;
-            let objp_len = width_dim * height_dim;
-            let objp = Vector::from_iter(
-                (0..objp_len)
-                    .map(|i| Point3f::new((i % width_dim) as f32, (i / height_dim) as f32, 0.)),
-            );
-
-            let mut objpoints = Vector::<Vector<Point3f>>::new(); // 3d point in real world space
-            let mut imgpoints = Vector::<Vector<Point2f>>::new(); // 2d points in image plane.
-            let count = fs::read_dir(&calibration_dir)?
-                .flatten()
-                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
-                .count();
-            let pb = ProgressBar::new(count as u64);
-            pb.println("[1/3] process images");
+            let pinhole = pinhole.options()?;
+            if model != CameraModel::Pinhole && !pinhole.is_default() {
+                return Err(format!(
+                    "the calibration flags and --intrinsic-guess are for the pinhole model, not {model:?}"
+                )
+                .into());
+            }
+            let mut calibrator = Calibrator::new(board, model)
+                .with_alpha(alpha)
+                .with_pinhole(pinhole);
             let started = Instant::now();
-            fs::read_dir(&calibration_dir)?
-                .flatten()
-                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
-                .map(|entry| entry.path().to_string_lossy().to_string())
-                .for_each(|image| {
-                    // Arrays to store object points and image points from all the images.
-                    pb.inc(1);
-                    let img = imgcodecs::imread_def(&image).unwrap();
-                    let mut gray = Mat::default();
-                    imgproc::cvt_color_def(&img, &mut gray, imgproc::COLOR_BGR2GRAY).unwrap();
-
-                    let mut corners = Vector::<Point2f>::default();
-                    if let Ok(_) = find_chessboard_corners_def(
-                        &gray,
-                        Size::new(width_dim, height_dim),
-                        &mut corners,
-                    ) {
-                        imgproc::corner_sub_pix(
-                            &gray,
-                            &mut corners,
-                            Size::new(11, 11),
-                            Size::new(-1, -1),
-                            criteria,
+            let mut stages = StageTimings::default();
+            let (images, size, state) = match &video {
+                Some(video) => {
+                    info!("[1/3] sample boards from {video}");
+                    let samples = video::sample_boards(video, &board, frame_interval, max_frames)?;
+                    for (frame, corners) in samples.corners {
+                        calibrator.add_corners(&format!("frame {frame}"), corners);
+                    }
+                    if calibrator.is_empty() {
+                        return Err(exit::Failure::new(
+                            exit::Code::NoBoards,
+                            format!("no board found in {video}"),
                         )
-                        .unwrap();
-                        // Draw and display corners
-                        // draw_chessboard_corners(&mut img, Size::new(width_dim, height_dim), &corners, ret)?;
-                        objpoints.push(objp.clone());
-                        imgpoints.push(corners.clone());
-                        // println!("image {image}");
-                        // objp.iter()
-                        //     .zip(corners)
-                        //     .for_each(|item| println!("pair {item:?}"));
-
-                        pb.set_message(format!(
-                            "{image} processed. in progress for {}",
-                            HumanDuration(started.elapsed())
-                        ));
-                    } else {
-                        pb.println(format!("[!] chessboard not found for image {image}"));
+                        .into());
                     }
-                });
-
-            pb.println("[2/3] compute calibration");
-            let first_image = fs::read_dir(&calibration_dir)?
-                .flatten()
-                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
-                .map(|entry| entry.path().to_string_lossy().to_string())
-                .next()
-                .unwrap();
-
-            let img = imgcodecs::imread_def(&first_image)?;
-            let mut mtx = Mat::default();
-            let mut dist = Mat::default();
-            let mut rvecs = Vector::<Mat>::new();
-            let mut tvecs = Vector::<Mat>::new();
-            calibrate_camera_def(
-                &objpoints,
-                &imgpoints,
-                img.size()?,
-                &mut mtx,
-                &mut dist,
-                &mut rvecs, // rotation
-                &mut tvecs, // translation
-            )?;
-            //use the calibration
-            let width = img.cols();
-            let height = img.rows();
-            //println!("image dimensions : {} {}", width, height);
-            let mtx = get_optimal_new_camera_matrix(
-                &mtx,
-                &dist,
-                Size::new(width, height),
-                1.0,
-                Size::new(width, height),
-                None,
-                true,
-            )?;
+                    (samples.frames, samples.size, None)
+                }
+                None => {
+                    let source = calibration_dir
+                        .as_deref()
+                        .or(files_from.as_deref())
+                        .unwrap_or_default();
+                    let images = input_images(
+                        calibration_dir.as_deref(),
+                        files_from.as_deref(),
+                        board.extensions(),
+                        &selection,
+                    )?;
+                    if images.is_empty() {
+                        return Err(format!("no images in {source}").into());
+                    }
+                    if auto_board_size {
+                        board = board.with_detected_size(&images)?;
+                        calibrator = calibrator.with_board(board);
+                    }
+                    let pool = WorkerPool::new(jobs.map(NonZeroUsize::get))?;
+                    let pb = progress::progress_bar(images.len() as u64);
+                    let workers = progress::WorkerLines::new(pool.current_num_threads());
+                    info!(
+                        "[1/3] detect boards in {} images with {} workers",
+                        images.len(),
+                        pool.current_num_threads()
+                    );
+                    // corners of each image, None when no board was found
+                    let state = state
+                        .as_deref()
+                        .map(|path| {
+                            resume::State::<Option<Vec<[f32; 2]>>>::open(
+                                path,
+                                &match board.pattern {
+                                    // the detectors find different corners
+                                    board::Pattern::Chessboard => format!(
+                                        "calibrate {}x{} {:?}",
+                                        board.width, board.height, board.detector
+                                    ),
+                                    pattern => {
+                                        format!(
+                                            "calibrate {pattern:?} {}x{}",
+                                            board.width, board.height
+                                        )
+                                    }
+                                },
+                            )
+                        })
+                        .transpose()?
+                        .map(Mutex::new);
+                    // detections in image order, whatever order the workers finish in
+                    let detections = pool.install(|| {
+                        images
+                            .par_iter()
+                            .map(|image| {
+                                let recorded = state
+                                    .as_ref()
+                                    .and_then(|state| state.lock().unwrap().get(image).cloned());
+                                let corners = match recorded {
+                                    Some(corners) => corners.map(|corners| {
+                                        Vector::from_iter(
+                                            corners.iter().map(|[x, y]| Point2f::new(*x, *y)),
+                                        )
+                                    }),
+                                    None => {
+                                        workers.set(format!("detect {image}"));
+                                        // an unreadable image is skipped, not recorded, so
+                                        // a resumed run tries it again
+                                        let detected = board
+                                            .read_image(image)
+                                            .and_then(|img| board.detect(&img));
+                                        let corners = match detected {
+                                            Ok(corners) => corners,
+                                            Err(e) => {
+                                                pb.inc(1);
+                                                return Ok(Err(e.to_string()));
+                                            }
+                                        };
+                                        if let Some(state) = &state {
+                                            let points = corners.as_ref().map(|corners| {
+                                                corners.iter().map(|p| [p.x, p.y]).collect()
+                                            });
+                                            state
+                                                .lock()
+                                                .unwrap()
+                                                .record(image, points)
+                                                .map_err(|e| e.to_string())?;
+                                        }
+                                        corners
+                                    }
+                                };
+                                pb.inc(1);
+                                pb.set_message(format!(
+                                    "in progress for {}",
+                                    HumanDuration(started.elapsed())
+                                ));
+                                Ok::<_, WorkerError>(Ok(corners))
+                            })
+                            .collect::<Result<Vec<_>, WorkerError>>()
+                    });
+                    workers.finish();
+                    let mut first_board = None;
+                    for (image, detection) in images
+                        .iter()
+                        .zip(detections.map_err(|e| e as Box<dyn Error>)?)
+                    {
+                        if let Ok(corners) = &detection
+                            && (debug_dir.is_some() || corners_file.is_some())
+                        {
+                            image_corners.push((image.clone(), corners.clone()));
+                        }
+                        match detection {
+                            Ok(Some(corners)) => {
+                                first_board.get_or_insert(image);
+                                calibrator.add_corners(image, corners);
+                            }
+                            Ok(None) => {
+                                let warning = format!("board not found for image {image}");
+                                warn!("{warning}");
+                                warnings.push(warning);
+                            }
+                            Err(reason) => {
+                                warn!("{image}: {reason}");
+                                failed.push(FailedImage {
+                                    image: image.clone(),
+                                    reason,
+                                });
+                            }
+                        }
+                    }
+                    pb.finish_and_clear();
+                    if failed.len() == images.len() {
+                        return Err(exit::Failure::new(
+                            exit::Code::AllFailed,
+                            format!(
+                                "none of the {} images in {source} could be read",
+                                images.len()
+                            ),
+                        )
+                        .into());
+                    }
+                    let Some(first_board) = first_board else {
+                        diagnose::no_boards(&images[images.len() / 2], &board)?;
+                        return Err(exit::Failure::new(
+                            exit::Code::NoBoards,
+                            format!("no board found in {source}"),
+                        )
+                        .into());
+                    };
+                    let size = board.read_image(first_board)?.size()?;
+                    if let Some(corners_file) = &corners_file {
+                        let mut cache = corners::Cache::new(&board, size);
+                        for (image, corners) in &image_corners {
+                            cache.push(image, corners.as_ref());
+                        }
+                        cache.save(corners_file)?;
+                        info!(
+                            "corners of {} images written to {corners_file}",
+                            image_corners.len()
+                        );
+                    }
+                    (images.len(), size, state)
+                }
+            };
+            stages.detect = started.elapsed().as_secs_f64();
 
-            // let mean_error = 0.0;
-            // let mut imgpoints2 = Mat::default();
-            // project_points_def(&objpoints, &rvecs, &tvecs, &mtx, &dist, &mut imgpoints2).unwrap();
-            // mean_error +=
-            //     norm(&imgpoints, &imgpoints2, NORM_L2).unwrap() / (imgpoints2.size() as f64);
-            // println!("total error: {}", mean_error / objpoints.size());
-
-            let calibration = Calibration {
-                camera_matrix: mtx
-                    .to_vec_2d()
-                    .unwrap()
-                    .iter()
-                    .flat_map(|row| row.iter())
-                    .cloned()
-                    .collect::<Vec<f64>>(),
-                dist_coeffs: dist
-                    .to_vec_2d()
-                    .unwrap()
-                    .iter()
-                    .flat_map(|row| row.iter())
-                    .cloned()
-                    .collect::<Vec<f64>>(),
+            info!("[2/3] compute calibration");
+            let solve_started = Instant::now();
+            let (width, height) = (size.width, size.height);
+            let CalibrationResult {
+                calibration,
+                rms,
+                used,
+                views,
+                rejected,
+            } = calibrator.calibrate(size, reject_threshold)?;
+            stages.solve = solve_started.elapsed().as_secs_f64();
+            let name_width = views.iter().map(|view| view.name.len()).max().unwrap_or(0);
+            info!("reprojection error per image, worst first");
+            for view in &views {
+                info!("  {:<name_width$}  {:.3}px", view.name, view.rms);
+            }
+            info!("rms {rms:.3}px over {used} images");
+            if !failed.is_empty() {
+                warn!("{} of {images} images could not be read:", failed.len());
+                for FailedImage { image, reason } in &failed {
+                    warn!("  {image}: {reason}");
+                }
+            }
+            info!("[3/3] store to file {calibration_file}");
+            let write_started = Instant::now();
+            calibration.save(&calibration_file, format)?;
+            stages.write = write_started.elapsed().as_secs_f64();
+            // after saving, a debug image that can't be written doesn't cost the calibration
+            if let Some(debug_dir) = &debug_dir {
+                debug::write_images(
+                    debug_dir,
+                    calibration_dir.as_deref(),
+                    &board,
+                    size,
+                    &image_corners,
+                    &views,
+                    &rejected,
+                )?;
+            }
+            if let Some(state) = state {
+                state.into_inner().unwrap().finish()?;
+            }
+            if let Some(validate_dir) = validate_dir {
+                underwater::validate(&calibration, &validate_dir, &board)?;
+            }
+            if let (Some(history), Some(camera_id)) = (history, camera_id) {
+                drift::record(&history, &camera_id, &calibration, rms)?;
+            }
+            info!(
+                "done in {} (detect {:.2}s, solve {:.2}s, write {:.2}s)",
+                HumanDuration(started.elapsed()),
+                stages.detect,
+                stages.solve,
+                stages.write
+            );
+            if json {
+                let summary = CalibrationSummary {
+                    calibration_file: &calibration_file,
+                    rms,
+                    images,
+                    used,
+                    image_size: [width, height],
+                    views,
+                    rejected,
+                    failed,
+                    warnings,
+                    stages,
+                };
+                println!("{}", serde_json::to_string(&summary)?);
+            }
+            if let Some(max_rms) = max_rms.filter(|max_rms| rms > *max_rms) {
+                return Err(exit::Failure::new(
+                    exit::Code::QualityBelowThreshold,
+                    format!("rms error {rms:.3} is above --max-rms {max_rms}"),
+                )
+                .into());
+            }
+        }
+        Action::Recalibrate {
+            corners_file,
+            calibration_dir,
+            files_from,
+            selection,
+            calibration_file,
+            prune,
+            model,
+            pinhole,
+            alpha,
+            reject_threshold,
+            jobs,
+            format,
+        } => {
+            confirm::overwrite(&calibration_file)?;
+            let mut cache = corners::Cache::load(&corners_file)?;
+            let board = cache.board();
+            if prune {
+                info!("{} images of {corners_file} no longer exist", cache.prune());
+            }
+            let pinhole = pinhole.options()?;
+            if model != CameraModel::Pinhole && !pinhole.is_default() {
+                return Err(format!(
+                    "the calibration flags and --intrinsic-guess are for the pinhole model, not {model:?}"
+                )
+                .into());
+            }
+            let images = if calibration_dir.is_some() || files_from.is_some() {
+                input_images(
+                    calibration_dir.as_deref(),
+                    files_from.as_deref(),
+                    board.extensions(),
+                    &selection,
+                )?
+            } else {
+                Vec::new()
             };
-            pb.println(format!("[3/3] strore to file {calibration_file}"));
-            fs::write(
-                calibration_file,
-                serde_json::to_string(&calibration).unwrap(),
-            )
-            .unwrap();
-            pb.println(format!("done in {}", HumanDuration(started.elapsed())));
+            let new_images = images
+                .into_iter()
+                .filter(|image| !cache.contains(image))
+                .collect::<Vec<String>>();
+            let pool = WorkerPool::new(jobs.map(NonZeroUsize::get))?;
+            info!(
+                "[1/3] detect boards in {} new images, {} images in {corners_file}",
+                new_images.len(),
+                cache.images.len()
+            );
+            let pb = progress::progress_bar(new_images.len() as u64);
+            let detections = pool.install(|| {
+                new_images
+                    .par_iter()
+                    .map(|image| {
+                        let detection = board
+                            .read_image(image)
+                            .and_then(|img| Ok((img.size()?, board.detect(&img)?)));
+                        pb.inc(1);
+                        detection
+                    })
+                    .collect::<Vec<_>>()
+            });
             pb.finish_and_clear();
+            let size = cache.size();
+            for (image, detection) in new_images.iter().zip(detections) {
+                match detection {
+                    Ok((image_size, _)) if image_size != size => warn!(
+                        "{image} is {}x{}, not {}x{} like the earlier images, skipped",
+                        image_size.width, image_size.height, size.width, size.height
+                    ),
+                    Ok((_, corners)) => {
+                        if corners.is_none() {
+                            warn!("board not found for image {image}");
+                        }
+                        cache.push(image, corners.as_ref());
+                    }
+                    // not recorded, the next run tries it again
+                    Err(e) => warn!("{image}: {e}"),
+                }
+            }
+            cache.save(&corners_file)?;
+
+            info!("[2/3] compute calibration");
+            let mut calibrator = Calibrator::new(board, model)
+                .with_alpha(alpha)
+                .with_pinhole(pinhole);
+            for (image, corners) in cache.corners() {
+                calibrator.add_corners(image, corners);
+            }
+            if calibrator.is_empty() {
+                return Err(exit::Failure::new(
+                    exit::Code::NoBoards,
+                    format!("no board in {corners_file} or the new images"),
+                )
+                .into());
+            }
+            let CalibrationResult {
+                calibration,
+                rms,
+                used,
+                views,
+                ..
+            } = calibrator.calibrate(size, reject_threshold)?;
+            let name_width = views.iter().map(|view| view.name.len()).max().unwrap_or(0);
+            info!("reprojection error per image, worst first");
+            for view in &views {
+                info!("  {:<name_width$}  {:.3}px", view.name, view.rms);
+            }
+            info!("rms {rms:.3}px over {used} images");
+            info!("[3/3] store to file {calibration_file}");
+            calibration.save(&calibration_file, format)?;
         }
         Action::Correct {
             correction_dir,
+            files_from,
+            selection,
             output_dir,
             calibration_file,
+            preset,
+            zoom_profiles,
+            model,
+            rectification,
+            alpha,
+            crop,
+            no_crop: _,
+            manifest,
+            state,
+            jobs,
+            interpolation,
+            map_cache,
+            maps,
+            gpu,
+            encoding,
+            watch,
+            settle_ms,
+            metrics_port,
+            #[cfg(feature = "s3")]
+            s3_endpoint,
+            #[cfg(feature = "s3")]
+            s3_concurrency,
         } => {
-            let calibraion: Calibration =
-                serde_json::from_slice(&fs::read(calibration_file).unwrap()).unwrap();
-            let mtx = Mat::new_rows_cols_with_data(3, 3, &calibraion.camera_matrix).unwrap();
-            let dist = Mat::new_rows_cols_with_data(1, 5, &calibraion.dist_coeffs).unwrap();
-            fs::read_dir(correction_dir)?
-                .flatten()
-                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
-                .for_each(|image| {
-                    let img = imgcodecs::imread_def(&image.path().to_string_lossy()).unwrap();
-                    let new_image = format!("u_{}", image.file_name().to_string_lossy());
-                    println!("save new image {new_image}");
-
-                    let mut dst_undistort = Mat::default();
-                    undistort_def(&img, &mut dst_undistort, &mtx, &dist).unwrap();
-
-                    imwrite_def(
-                        format!("{}/{}", output_dir, new_image).as_str(),
-                        &dst_undistort,
-                    )
-                    .unwrap();
-
-                    // Using remapping
-                    let mut mapx = Mat::default();
-                    let mut mapy = Mat::default();
-                    init_undistort_rectify_map(
-                        &mtx,
-                        &dist,
-                        &no_array(),
-                        &no_array(),
-                        img.size().unwrap(),
-                        f32::opencv_type(),
-                        &mut mapx,
-                        &mut mapy,
-                    )
-                    .unwrap();
-                    let mut dst_remap = Mat::default();
-                    imgproc::remap_def(&img, &mut dst_remap, &mapx, &mapy, imgproc::INTER_LINEAR)
-                        .unwrap();
-                    imwrite_def(
-                        format!("{}/u1_{}", output_dir, new_image).as_str(),
-                        &dst_undistort,
+            // cudawarping has no lanczos remap, better to refuse than fail on the first image
+            #[cfg(feature = "cuda")]
+            if gpu == Some(gpu::Gpu::Cuda) && interpolation == Interpolation::Lanczos {
+                return Err("--gpu cuda can't remap with --interpolation lanczos".into());
+            }
+            // --maps corrects without a calibration
+            let lens = match (zoom_profiles.as_deref(), &maps) {
+                (Some(path), _) => Some(zoom::Lens::Zoom(zoom::ZoomProfiles::load(path)?)),
+                (None, Some(_)) => None,
+                (None, None) => Some(zoom::Lens::Fixed(Box::new(
+                    Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?
+                        .with_model(model)?
+                        .with_rectification(rectification)?
+                        .with_alpha(alpha)?,
+                ))),
+            };
+            if let (true, Some(zoom::Lens::Fixed(calibration))) = (crop, &lens)
+                && calibration.roi.is_none()
+            {
+                warn!("the calibration has no roi to crop to, compute one with --alpha");
+            }
+            let tables = maps.as_deref().map(mapfile::read).transpose()?;
+            // what the outputs were corrected with, for the state and the manifest
+            let checksum = match (&lens, &maps) {
+                (Some(lens), _) => lens.checksum(),
+                (None, maps) => format!(
+                    "{:08x}",
+                    crc32fast::hash(&fs::read(maps.as_deref().unwrap_or_default())?)
+                ),
+            };
+            #[cfg(feature = "s3")]
+            if correction_dir.as_deref().is_some_and(storage::is_s3) || storage::is_s3(&output_dir)
+            {
+                if watch {
+                    return Err("--watch needs a local correction and output directory".into());
+                }
+                if tables.is_some() {
+                    return Err("--maps corrects local files only".into());
+                }
+                let correction_dir = correction_dir
+                    .ok_or("--files-from reads local files, write to a local output directory")?;
+                let Some(zoom::Lens::Fixed(calibration)) = &lens else {
+                    return Err("zoom profiles read the exif of local files".into());
+                };
+                return storage::correct(
+                    calibration,
+                    &correction_dir,
+                    &output_dir,
+                    s3_endpoint.as_deref(),
+                    s3_concurrency,
+                    manifest.as_deref(),
+                );
+            }
+            if let Some(correction_dir) = correction_dir.as_deref().filter(|_| watch) {
+                let gpu = gpu.map(gpu::available).transpose()?.flatten();
+                let correction =
+                    Correction::new(lens, interpolation, map_cache, gpu, encoding, crop)
+                        .with_maps(tables);
+                // a new calibration corrects everything again
+                let calibrations = [&zoom_profiles, &calibration_file, &maps]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect::<Vec<&str>>();
+                serve_metrics(metrics_port)?;
+                return watch::run(
+                    correction_dir,
+                    &selection,
+                    &IMAGE_EXTENSIONS,
+                    Duration::from_millis(settle_ms),
+                    |image| {
+                        let name = relative_name(Some(correction_dir), image);
+                        let output = output_file(&correction, name, &output_dir);
+                        if watch::up_to_date(&[&[image][..], &calibrations].concat(), &output) {
+                            info!("{image} corrected earlier, skipped");
+                            return;
+                        }
+                        let result = metrics::time("correct", || {
+                            correct_image(&correction, image, name, &output_dir)
+                        });
+                        metrics::frame(result.is_ok());
+                        if let Err(e) = result {
+                            warn!("{image}: {e}");
+                        }
+                    },
+                );
+            }
+            let state = state
+                .as_deref()
+                .map(|path| {
+                    // images corrected with other settings are not reused
+                    resume::State::<String>::open(
+                        path,
+                        &format!(
+                            "correct {checksum} into {output_dir}, {interpolation:?}, crop {crop}, \
+                             maps {maps:?}, {encoding:?}"
+                        ),
                     )
-                    .unwrap();
-                });
+                })
+                .transpose()?;
+            // a resumed run writes into its own earlier output
+            if !state.as_ref().is_some_and(resume::State::is_resumed) {
+                confirm::output_dir(&output_dir)?;
+            }
+            let images = input_images(
+                correction_dir.as_deref(),
+                files_from.as_deref(),
+                &IMAGE_EXTENSIONS,
+                &selection,
+            )?;
+            let mut entries = Manifest::new("correct", checksum);
+            if let Some(correction_dir) = &correction_dir {
+                entries.skip_other_files(correction_dir, selection.recursive, &images)?;
+            }
+            let pool = WorkerPool::new(jobs.map(NonZeroUsize::get))?;
+            let pb = progress::progress_bar(images.len() as u64);
+            let workers = progress::WorkerLines::new(pool.current_num_threads());
+            let gpu = gpu.map(gpu::available).transpose()?.flatten();
+            let correction = Correction::new(lens, interpolation, map_cache, gpu, encoding, crop)
+                .with_maps(tables);
+            let state = state.map(Mutex::new);
+            // entries in image order, whatever order the workers finish in
+            let results = pool.install(|| {
+                images
+                    .par_iter()
+                    .map(|image| {
+                        pb.inc(1);
+                        let done = state
+                            .as_ref()
+                            .is_some_and(|state| state.lock().unwrap().get(image).is_some());
+                        if done {
+                            return Ok(Entry::skipped(image, "corrected by an earlier run"));
+                        }
+                        workers.set(format!("correct {image}"));
+                        let started = Instant::now();
+                        let name = relative_name(correction_dir.as_deref(), image);
+                        match correct_image(&correction, image, name, &output_dir) {
+                            Ok((output, data)) => {
+                                if let Some(state) = &state {
+                                    state
+                                        .lock()
+                                        .unwrap()
+                                        .record(image, output.clone())
+                                        .map_err(|e| e.to_string())?;
+                                }
+                                Ok(Entry::corrected(image, &output, &data, started.elapsed()))
+                            }
+                            Err(e) => {
+                                warn!("{image}: {e}");
+                                Ok(Entry::failed(image, e.as_ref(), started.elapsed()))
+                            }
+                        }
+                    })
+                    .collect::<Result<Vec<Entry>, WorkerError>>()
+            });
+            workers.finish();
+            pb.finish_and_clear();
+            for entry in results.map_err(|e| e as Box<dyn Error>)? {
+                entries.push(entry);
+            }
+            entries.summary();
+            let (corrected, failed) = (entries.corrected(), entries.failed());
+            if let Some(manifest) = manifest {
+                entries.write(&manifest)?;
+            }
+            // failed images are retried by the next run
+            if let Some(state) = state.filter(|_| failed == 0) {
+                state.into_inner().unwrap().finish()?;
+            }
+            if failed > 0 && corrected == 0 {
+                return Err(exit::Failure::new(
+                    exit::Code::AllFailed,
+                    format!("none of the {failed} images could be corrected"),
+                )
+                .into());
+            }
+            if failed > 0 {
+                return Err(exit::Failure::new(
+                    exit::Code::PartialFailure,
+                    format!("{failed} images could not be corrected"),
+                )
+                .into());
+            }
         }
-        Action::Solve {
+        Action::Pose {
             calibration_file,
+            preset,
             image_dir,
+            selection,
+            board,
+            corrected,
+            overlay_dir,
         } => {
-            // termination criteria
-            let criteria = TermCriteria {
-                typ: TermCriteria_EPS + TermCriteria_MAX_ITER,
-                max_count: 30,
-                epsilon: 0.001,
-            };
-
-            // prepare object points, like (0,0,0), (1,0,0), (2,0,0) ....,(6,5,0)
-            let width_dim = 11;
-            let height_dim = 8;
-            let objp_len = width_dim * height_dim;
-            let objp = Vector::<Point3f>::from_iter(
-                (0..objp_len)
-                    .map(|i| Point3f::new((i % width_dim) as f32, (i / height_dim) as f32, 0.)),
-            );
-
-            let calibraion: Calibration =
-                serde_json::from_slice(&fs::read(calibration_file).unwrap()).unwrap();
-            let mtx = Mat::new_rows_cols_with_data(3, 3, &calibraion.camera_matrix).unwrap();
-            let dist = Mat::new_rows_cols_with_data(1, 5, &calibraion.dist_coeffs).unwrap();
-
-            let count = fs::read_dir(&image_dir)?
-                .flatten()
-                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
-                .count();
-            let pb = ProgressBar::new(count as u64);
-            pb.println("[1/3] process images");
-            let started = Instant::now();
-            fs::read_dir(&image_dir)?
-                .flatten()
-                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jpg"))
-                .map(|entry| entry.path().to_string_lossy().to_string())
-                .for_each(|image| {
-                    // Arrays to store object points and image points from all the images.
-                    pb.inc(1);
-                    let img = imgcodecs::imread_def(&image).unwrap();
-                    let mut gray = Mat::default();
-                    imgproc::cvt_color_def(&img, &mut gray, imgproc::COLOR_BGR2GRAY).unwrap();
-
-                    let mut corners = Vector::<Point2f>::default();
-                    if let Ok(_) = find_chessboard_corners_def(
-                        &gray,
-                        Size::new(width_dim, height_dim),
-                        &mut corners,
-                    ) {
-                        imgproc::corner_sub_pix(
-                            &gray,
-                            &mut corners,
-                            Size::new(11, 11),
-                            Size::new(-1, -1),
-                            criteria,
-                        )
-                        .unwrap();
-
-                        let mut rvecs = Vector::<Mat>::new();
-                        let mut tvecs = Vector::<Mat>::new();
-
-                        // println!("image {image}");
-                        // objp.iter()
-                        //     .zip(corners.clone())
-                        //     .for_each(|item| println!("pair {item:?}"));
-                        if let Ok(_result) = solve_pnp(
-                            &objp, &corners, &mtx, &dist, &mut rvecs, // rotation
-                            &mut tvecs, // translation
-                            true, RANSAC,
-                        ) {
-                            pb.println(format!("{image} rotation {:?}", rvecs));
-                            pb.println(format!("{image} translation {:?}", tvecs));
-                        } else {
-                            pb.println(format!("{image} coult not estimate pose"));
-                        }
-
-                        // Draw and display corners
-                        //draw_chessboard_corners(&mut img, Size::new(width_dim, height_dim), &corners, ret)?;
-                        pb.set_message(format!(
-                            "{image} processed. in progress for {}",
-                            HumanDuration(started.elapsed())
-                        ));
-                    } else {
-                        pb.println(format!("[!] chessboard not found for image {image}"));
+            let board = board.board()?;
+            if let Some(overlay_dir) = &overlay_dir {
+                confirm::output_dir(overlay_dir)?;
+            }
+            pose::poses(
+                &Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?,
+                &board,
+                &selection.files(&image_dir, board.extensions())?,
+                corrected,
+                overlay_dir.as_deref(),
+            )?
+        }
+        Action::Rectify {
+            calibration_file,
+            preset,
+            image_dir,
+            selection,
+            output_dir,
+            board,
+            points,
+            points_width_mm,
+            points_height_mm,
+            mm_per_pixel,
+            output_width,
+            output_height,
+            interpolation,
+        } => {
+            if mm_per_pixel.is_some_and(|mm| mm <= 0.0) {
+                return Err("--mm-per-pixel must be positive".into());
+            }
+            let calibration = Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?;
+            let board = board.board_or(calibration.board)?;
+            let reference = match (points, points_width_mm, points_height_mm) {
+                (Some(points), Some(width_mm), Some(height_mm)) => rectify::Reference::Points {
+                    points: [points[0], points[1], points[2], points[3]],
+                    width_mm,
+                    height_mm,
+                },
+                _ => {
+                    if board.square_size_mm.is_none() {
+                        warn!("the board has no square size, the scale is in squares per pixel");
                     }
-                });
-            pb.println(format!("done in {}", HumanDuration(started.elapsed())));
-            pb.finish_and_clear();
+                    rectify::Reference::Board(board)
+                }
+            };
+            let options = rectify::Options {
+                mm_per_pixel,
+                size: output_width
+                    .zip(output_height)
+                    .map(|(w, h)| Size::new(w, h)),
+                interpolation,
+            };
+            confirm::output_dir(&output_dir)?;
+            fs::create_dir_all(&output_dir)?;
+            let images = input_images(
+                Some(image_dir.as_str()),
+                None,
+                board.extensions(),
+                &selection,
+            )?;
+            let mut rectified = 0;
+            for image in &images {
+                // the board is found on the image prepared for detection, thermal or inverted,
+                // the output is warped from the image as it is
+                let prepared = board.read_image(image)?;
+                let img = imgcodecs::imread(
+                    image,
+                    imgcodecs::IMREAD_ANYDEPTH | imgcodecs::IMREAD_ANYCOLOR,
+                )?;
+                if img.empty() || prepared.empty() {
+                    warn!("could not read {image}");
+                    continue;
+                }
+                let Some(output) =
+                    rectify::rectify(&calibration, &img, &prepared, &reference, &options)?
+                else {
+                    warn!("board not found for image {image}");
+                    continue;
+                };
+                // images of subdirectories keep their place, like in correct
+                let name = relative_name(Some(&image_dir), image);
+                let dir = match name
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                {
+                    Some(parent) => format!("{output_dir}/{}", parent.display()),
+                    None => output_dir.clone(),
+                };
+                fs::create_dir_all(&dir)?;
+                let new_image = format!(
+                    "{dir}/r_{}",
+                    name.file_name().unwrap_or_default().to_string_lossy()
+                );
+                info!("save new image {new_image}");
+                if !imgcodecs::imwrite_def(&new_image, &output)? {
+                    return Err(format!("could not write {new_image}").into());
+                }
+                rectified += 1;
+            }
+            info!("{rectified} of {} images rectified", images.len());
+        }
+        Action::StereoCalibrate {
+            left_dir,
+            right_dir,
+            calibration_file,
+            pairing,
+            board,
+            model,
+            rig_ply,
+        } => {
+            confirm::overwrite(&calibration_file)?;
+            let board = board.board()?;
+            stereo::calibrate(
+                &left_dir,
+                &right_dir,
+                &calibration_file,
+                &pairing,
+                &board,
+                model,
+                rig_ply.as_deref(),
+            )?
+        }
+        Action::MarkerPose {
+            calibration_file,
+            preset,
+            image_dir,
+            dictionary,
+            marker_size_mm,
+            marker_id,
+            distorted,
+        } => marker::poses(
+            &Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?,
+            &image_dir,
+            dictionary,
+            marker_size_mm,
+            marker_id,
+            distorted,
+        )?,
+        Action::ProjectorPatterns {
+            projector,
+            output_dir,
+        } => {
+            confirm::output_dir(&output_dir)?;
+            projector::patterns(&projector, &output_dir)?
+        }
+        Action::ProjectorCalibrate {
+            captures_dir,
+            calibration_file,
+            projector,
+            board,
+        } => {
+            confirm::overwrite(&calibration_file)?;
+            projector::calibrate(
+                &captures_dir,
+                &calibration_file,
+                &projector,
+                &board.board()?,
+            )?
+        }
+        Action::StereoCorrect {
+            calibration_file,
+            left_dir,
+            right_dir,
+            output_dir,
+            pairing,
+        } => {
+            confirm::output_dir(&output_dir)?;
+            stereo::correct(
+                &calibration_file,
+                &left_dir,
+                &right_dir,
+                &output_dir,
+                &pairing,
+            )?
+        }
+        Action::CorrectVideo {
+            calibration_file,
+            preset,
+            input,
+            output,
+            codec,
+            encoder,
+            model,
+            rectification,
+        } => {
+            confirm::overwrite(&output)?;
+            video::correct(
+                &Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?
+                    .with_model(model)?
+                    .with_rectification(rectification)?,
+                &input,
+                &output,
+                &codec,
+                encoder,
+            )?
+        }
+        Action::CalibrateLive {
+            device,
+            calibration_file,
+            board,
+            model,
+            auto,
+            save_dir,
+        } => {
+            confirm::overwrite(&calibration_file)?;
+            if let Some(save_dir) = &save_dir {
+                confirm::output_dir(save_dir)?;
+            }
+            live::calibrate(
+                device,
+                &calibration_file,
+                board.board()?,
+                model,
+                auto,
+                save_dir.as_deref(),
+            )?
+        }
+        Action::Drift {
+            history,
+            camera_id,
+            plot,
+        } => drift::report(&history, &camera_id, plot.as_deref())?,
+        Action::PlumbLine {
+            image_dir,
+            calibration_file,
+            focal_px,
+        } => {
+            confirm::overwrite(&calibration_file)?;
+            plumbline::calibrate(&image_dir, &calibration_file, focal_px)?
+        }
+        Action::Convert {
+            input,
+            output,
+            format,
+        } => {
+            confirm::overwrite(&output)?;
+            Calibration::load(&input)?.save(&output, format)?;
+            info!("{input} written to {output}");
+        }
+        Action::Presets => presets::list(),
+        Action::ZoomAdd {
+            zoom_profiles,
+            calibration_file,
+            focal_length_mm,
+        } => zoom::ZoomProfiles::add(
+            &zoom_profiles,
+            focal_length_mm,
+            Calibration::load(&calibration_file)?,
+        )?,
+        Action::SelfTest => selftest::self_test()?,
+        Action::Run { config } => pipeline::run_pipeline(&config)?,
+        Action::Validate {
+            calibration_file,
+            test_dir,
+            selection,
+            board,
+            max_error,
+            statistic,
+            json,
+            jobs,
+        } => {
+            let calibration = Calibration::load(&calibration_file)?;
+            let board = board.board_or(calibration.board)?;
+            let images = input_images(
+                Some(test_dir.as_str()),
+                None,
+                board.extensions(),
+                &selection,
+            )?;
+            if images.is_empty() {
+                return Err(format!("no images in {test_dir}").into());
+            }
+            let report =
+                validate::score(&calibration, &board, &images, jobs.map(NonZeroUsize::get))?;
+            if json {
+                println!("{}", serde_json::to_string(&report)?);
+            }
+            if let Some(max_error) = max_error {
+                let value = report.overall.get(statistic);
+                if value > max_error {
+                    return Err(exit::Failure::new(
+                        exit::Code::QualityBelowThreshold,
+                        format!(
+                            "{statistic:?} reprojection error {value:.3}px is above --max-error {max_error}"
+                        ),
+                    )
+                    .into());
+                }
+                info!(
+                    "{statistic:?} reprojection error {value:.3}px within --max-error {max_error}"
+                );
+            }
+        }
+        Action::Gstreamer {
+            calibration_file,
+            input_pipeline,
+            output_pipeline,
+            fps,
+        } => gstreamer::run(&calibration_file, &input_pipeline, &output_pipeline, fps)?,
+        Action::Pipe {
+            calibration_file,
+            metrics_port,
+        } => {
+            serve_metrics(metrics_port)?;
+            pipe::run(&calibration_file)?
+        }
+        Action::Serve {
+            calibration_file,
+            port,
+            metrics_port,
+        } => {
+            serve_metrics(metrics_port)?;
+            http::serve(&calibration_file, port)?
+        }
+        #[cfg(feature = "grpc")]
+        Action::Grpc {
+            calibration_dir,
+            port,
+            metrics_port,
+        } => {
+            serve_metrics(metrics_port)?;
+            grpc::serve(&calibration_dir, port)?
+        }
+        #[cfg(feature = "mqtt")]
+        Action::Mqtt {
+            calibration_file,
+            output_dir,
+            host,
+            port,
+            client_id,
+            capture_topic,
+            done_topic,
+            metrics_port,
+        } => {
+            serve_metrics(metrics_port)?;
+            mqtt::run(
+                &calibration_file,
+                &output_dir,
+                &host,
+                port,
+                &client_id,
+                &capture_topic,
+                &done_topic,
+            )?
+        }
+        Action::Nvr {
+            calibration_file,
+            input,
+            rtsp_output,
+            mjpeg_port,
+            jpeg_quality,
+            metrics_port,
+        } => {
+            serve_metrics(metrics_port)?;
+            nvr::run(
+                &calibration_file,
+                &input,
+                rtsp_output.as_deref(),
+                mjpeg_port,
+                jpeg_quality,
+            )?
+        }
+        #[cfg(feature = "ros2")]
+        Action::Ros2 {
+            calibration_file,
+            image_topic,
+            output_topic,
+            camera_info_topic,
+        } => ros2::run(
+            &calibration_file,
+            &image_topic,
+            &output_topic,
+            &camera_info_topic,
+        )?,
+        Action::ExportOpensfm {
+            calibration_file,
+            output,
+            image_width,
+            image_height,
+            camera_id,
+        } => opensfm::export(
+            &calibration_file,
+            &output,
+            image_width,
+            image_height,
+            camera_id.as_deref(),
+        )?,
+        Action::ExportBlender {
+            calibration_file,
+            output,
+            image_width,
+            image_height,
+            sensor_width,
+        } => {
+            confirm::overwrite(&output)?;
+            blender::export(
+                &calibration_file,
+                &output,
+                image_width,
+                image_height,
+                sensor_width,
+            )?
+        }
+        Action::Export {
+            calibration_file,
+            lensfun,
+            pto,
+            image_width,
+            image_height,
+            lens,
+        } => {
+            for output in lensfun.iter().chain(&pto) {
+                confirm::overwrite(output)?;
+            }
+            let size = image_width
+                .zip(image_height)
+                .map(|(width, height)| Size::new(width, height));
+            panotools::export(
+                &calibration_file,
+                size,
+                lensfun.as_deref(),
+                pto.as_deref(),
+                &lens,
+            )?
+        }
+        Action::ExportStmap {
+            calibration_file,
+            image_width,
+            image_height,
+            output_dir,
+        } => {
+            confirm::output_dir(&output_dir)?;
+            stmap::export(&calibration_file, image_width, image_height, &output_dir)?
+        }
+        Action::MakeMaps {
+            calibration_file,
+            preset,
+            image_width,
+            image_height,
+            alpha,
+            output,
+            format,
+        } => {
+            let calibration = Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?
+                .with_alpha(alpha)?;
+            let size = match (image_width.zip(image_height), calibration.image_size) {
+                (Some((width, height)), _) | (None, Some([width, height])) => {
+                    Size::new(width, height)
+                }
+                (None, None) => {
+                    return Err(
+                        "the calibration has no image size, pass --image-width and --image-height"
+                            .into(),
+                    );
+                }
+            };
+            confirm::overwrite(&output)?;
+            let format = format.unwrap_or_else(|| mapfile::Format::of(&output));
+            mapfile::export(&calibration, size, &output, format)?
+        }
+        Action::UndistortPoints {
+            calibration_file,
+            preset,
+            input,
+            output,
+            image_width,
+            image_height,
+            inverse,
+            normalized,
+        } => {
+            let calibration = Calibration::resolve(calibration_file.as_deref(), preset.as_deref())?;
+            let size = match (image_width, image_height, calibration.image_size) {
+                (Some(width), Some(height), _) => Size::new(width, height),
+                (_, _, Some([width, height])) => Size::new(width, height),
+                _ => {
+                    return Err(
+                        "the calibration doesn't record the image size, pass --image-width and --image-height".into(),
+                    );
+                }
+            };
+            if let Some(output) = &output {
+                confirm::overwrite(output)?;
+            }
+            points::correct(
+                &calibration,
+                &input,
+                output.as_deref(),
+                size,
+                inverse,
+                normalized,
+            )?
+        }
+        Action::StereoExportRos {
+            calibration_file,
+            output_dir,
+        } => {
+            confirm::output_dir(&output_dir)?;
+            stereo::export_ros(&calibration_file, &output_dir)?
         }
     }
     Ok(())
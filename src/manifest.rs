@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use indicatif::HumanDuration;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{order, provenance, select};
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Corrected,
+    Skipped,
+    Failed,
+}
+
+/// one input of the run
+#[derive(Serialize)]
+pub struct Entry {
+    input: String,
+    outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    /// crc32 of the written output, hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crc32: Option<String>,
+    /// why the input was skipped or failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    seconds: f64,
+}
+
+impl Entry {
+    pub fn corrected(input: &str, output: &str, data: &[u8], elapsed: Duration) -> Self {
+        Entry {
+            input: input.to_string(),
+            outcome: Outcome::Corrected,
+            output: Some(output.to_string()),
+            crc32: Some(format!("{:08x}", crc32fast::hash(data))),
+            reason: None,
+            seconds: elapsed.as_secs_f64(),
+        }
+    }
+
+    pub fn skipped(input: &str, reason: &str) -> Self {
+        Entry {
+            input: input.to_string(),
+            outcome: Outcome::Skipped,
+            output: None,
+            crc32: None,
+            reason: Some(reason.to_string()),
+            seconds: 0.0,
+        }
+    }
+
+    pub fn failed(input: &str, error: &dyn Error, elapsed: Duration) -> Self {
+        Entry {
+            input: input.to_string(),
+            outcome: Outcome::Failed,
+            output: None,
+            crc32: None,
+            reason: Some(error.to_string()),
+            seconds: elapsed.as_secs_f64(),
+        }
+    }
+}
+
+/// everything a run read and wrote, so downstream tools can check that a batch completed
+#[derive(Serialize)]
+pub struct Manifest {
+    command: &'static str,
+    run_id: &'static str,
+    tool_version: &'static str,
+    arguments: Vec<String>,
+    /// crc32 of the calibration used, see the comment segment of the corrected jpegs
+    calibration_crc32: String,
+    /// unix time the run started
+    started: f64,
+    seconds: f64,
+    corrected: usize,
+    skipped: usize,
+    failed: usize,
+    entries: Vec<Entry>,
+    #[serde(skip)]
+    timer: Instant,
+}
+
+impl Manifest {
+    pub fn new(command: &'static str, calibration_crc32: String) -> Self {
+        Manifest {
+            command,
+            run_id: provenance::run_id(),
+            tool_version: provenance::TOOL_VERSION,
+            arguments: std::env::args().collect(),
+            calibration_crc32,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            seconds: 0.0,
+            corrected: 0,
+            skipped: 0,
+            failed: 0,
+            entries: Vec::new(),
+            timer: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        match entry.outcome {
+            Outcome::Corrected => self.corrected += 1,
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Failed => self.failed += 1,
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn corrected(&self) -> usize {
+        self.corrected
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// log the counts of the run and the reason of every failed input
+    pub fn summary(&self) {
+        info!(
+            "{} corrected, {} skipped, {} failed in {}",
+            self.corrected,
+            self.skipped,
+            self.failed,
+            HumanDuration(self.timer.elapsed())
+        );
+        for entry in self
+            .entries
+            .iter()
+            .filter(|entry| entry.outcome == Outcome::Failed)
+        {
+            warn!(
+                "  {}: {}",
+                entry.input,
+                entry.reason.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    /// record the files of a local input directory that are not among the selected images
+    pub fn skip_other_files(
+        &mut self,
+        dir: &str,
+        recursive: bool,
+        images: &[String],
+    ) -> std::io::Result<()> {
+        let images = images.iter().collect::<HashSet<&String>>();
+        let mut others = select::walk(Path::new(dir), recursive)?
+            .into_iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .filter(|path| !images.contains(path))
+            .collect::<Vec<String>>();
+        order::sort(&mut others);
+        for other in others {
+            self.push(Entry::skipped(&other, "not a selected image"));
+        }
+        Ok(())
+    }
+
+    pub fn write(mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.seconds = self.timer.elapsed().as_secs_f64();
+        fs::write(path, serde_json::to_string_pretty(&self)?)?;
+        info!("manifest written to {path}");
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::{debug, warn};
+use opencv::core::Size;
+use opencv::prelude::*;
+
+use crate::{Calibration, undistort_maps};
+
+// bumped when the layout of the cache files or the map computation changes
+const VERSION: u32 = 1;
+
+// numbers the files written aside within this process
+static PARTIAL: AtomicU64 = AtomicU64::new(0);
+
+// one file per calibration and image size, the calibration as it is stored identifies it
+fn path(dir: &str, calibration: &Calibration, size: Size) -> String {
+    let key = crc32fast::hash(&serde_json::to_vec(calibration).unwrap_or_default());
+    format!(
+        "{dir}/maps_v{VERSION}_{key:08x}_{}x{}.bin",
+        size.width, size.height
+    )
+}
+
+// mapx then mapy as native endian f32, row by row
+fn read(path: &str, size: Size) -> Result<Option<(Mat, Mat)>, Box<dyn Error>> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path)?;
+    let pixels = (size.width * size.height) as usize;
+    if bytes.len() != pixels * 2 * size_of::<f32>() {
+        warn!("{path} is truncated, building the maps again");
+        return Ok(None);
+    }
+    let values = bytes
+        .chunks_exact(size_of::<f32>())
+        .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect::<Vec<f32>>();
+    let (xs, ys) = values.split_at(pixels);
+    Ok(Some((
+        Mat::new_rows_cols_with_data(size.height, size.width, xs)?.try_clone()?,
+        Mat::new_rows_cols_with_data(size.height, size.width, ys)?.try_clone()?,
+    )))
+}
+
+fn write(path: &str, mapx: &Mat, mapy: &Mat) -> Result<(), Box<dyn Error>> {
+    let mut bytes = Vec::with_capacity(mapx.total() * 2 * size_of::<f32>());
+    for map in [mapx, mapy] {
+        for value in map.data_typed::<f32>()? {
+            bytes.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+    // written aside and renamed so a concurrent run never reads half a file, under a name of
+    // its own so two writers never share one
+    let partial = format!(
+        "{path}.{}.{}.partial",
+        process::id(),
+        PARTIAL.fetch_add(1, Ordering::Relaxed)
+    );
+    fs::write(&partial, bytes)?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// remap tables of `undistort_maps`, read from `dir` when an earlier run built them for the
+/// same calibration and image size, otherwise built and stored there
+pub fn maps(
+    dir: &str,
+    calibration: &Calibration,
+    size: Size,
+) -> Result<(Mat, Mat), Box<dyn Error>> {
+    let path = path(dir, calibration, size);
+    if let Some(maps) = read(&path, size)? {
+        debug!("maps for {}x{} from {path}", size.width, size.height);
+        return Ok(maps);
+    }
+    let (mapx, mapy) = undistort_maps(calibration, size)?;
+    fs::create_dir_all(dir)?;
+    write(&path, &mapx, &mapy)?;
+    debug!("maps for {}x{} stored in {path}", size.width, size.height);
+    Ok((mapx, mapy))
+}
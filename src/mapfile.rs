@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::info;
+use opencv::core::{CV_32FC3, Size, extract_channel};
+use opencv::imgcodecs::{self, IMREAD_UNCHANGED};
+use opencv::prelude::*;
+
+use crate::{Calibration, stmap, undistort_maps};
+
+const RAW_MAGIC: &[u8; 4] = b"UMAP";
+const RAW_VERSION: u32 = 1;
+// magic, version, width, height
+const RAW_HEADER: usize = 16;
+
+/// file format of exported remap tables
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// 32 bit float openexr, x in red and y in green, once `stmap::enable_exr` ran
+    Exr,
+    /// portable float map, x in red and y in green
+    Pfm,
+    /// little endian `UMAP`, version, width and height as u32, then every x and every y as f32
+    Raw,
+}
+
+impl Format {
+    /// format by the file extension, .exr and .pfm, anything else raw
+    pub fn of(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("exr") => Format::Exr,
+            Some("pfm") => Format::Pfm,
+            _ => Format::Raw,
+        }
+    }
+}
+
+fn check_size(path: &str, size: Size) -> Result<(), Box<dyn Error>> {
+    if size.width <= 0 || size.height <= 0 || size.width.checked_mul(size.height).is_none() {
+        return Err(format!("{path} has maps of {}x{} pixels", size.width, size.height).into());
+    }
+    Ok(())
+}
+
+fn mats(size: Size, xs: &[f32], ys: &[f32]) -> opencv::Result<(Mat, Mat)> {
+    Ok((
+        Mat::new_rows_cols_with_data(size.height, size.width, xs)?.try_clone()?,
+        Mat::new_rows_cols_with_data(size.height, size.width, ys)?.try_clone()?,
+    ))
+}
+
+fn write_pfm(path: &str, size: Size, xs: &[f32], ys: &[f32]) -> Result<(), Box<dyn Error>> {
+    // a negative scale marks little endian, the rows go from the bottom up
+    let mut bytes = format!("PF\n{} {}\n-1.0\n", size.width, size.height).into_bytes();
+    let width = size.width as usize;
+    for row in (0..size.height as usize).rev() {
+        let range = row * width..(row + 1) * width;
+        for (x, y) in xs[range.clone()].iter().zip(&ys[range]) {
+            for value in [*x, *y, 0.0] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+fn write_raw(path: &str, size: Size, xs: &[f32], ys: &[f32]) -> Result<(), Box<dyn Error>> {
+    let mut bytes = Vec::with_capacity(RAW_HEADER + (xs.len() + ys.len()) * size_of::<f32>());
+    bytes.extend_from_slice(RAW_MAGIC);
+    for value in [RAW_VERSION, size.width as u32, size.height as u32] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in xs.iter().chain(ys) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// write the remap tables undistorting images of `size`: the source pixel of every output
+/// pixel, for a gpu or fpga stage applying the remap itself
+pub fn export(
+    calibration: &Calibration,
+    size: Size,
+    path: &str,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let (mapx, mapy) = undistort_maps(calibration, size)?;
+    let (xs, ys) = (mapx.data_typed::<f32>()?, mapy.data_typed::<f32>()?);
+    match format {
+        Format::Exr => {
+            // opencv stores bgr
+            let pixels = xs
+                .iter()
+                .zip(ys)
+                .map(|(x, y)| [0.0, *y, *x])
+                .collect::<Vec<[f32; 3]>>();
+            return stmap::write_exr(Path::new(path), size, &pixels);
+        }
+        Format::Pfm => write_pfm(path, size, xs, ys)?,
+        Format::Raw => write_raw(path, size, xs, ys)?,
+    }
+    info!("{path} written");
+    Ok(())
+}
+
+fn read_exr(path: &str) -> Result<(Mat, Mat), Box<dyn Error>> {
+    let img = imgcodecs::imread(path, IMREAD_UNCHANGED)?;
+    if img.empty() {
+        return Err(format!("could not read {path}").into());
+    }
+    if img.typ() != CV_32FC3 {
+        return Err(format!("{path} is not a 3 channel float exr").into());
+    }
+    let mut mapx = Mat::default();
+    let mut mapy = Mat::default();
+    extract_channel(&img, &mut mapx, 2)?;
+    extract_channel(&img, &mut mapy, 1)?;
+    Ok((mapx, mapy))
+}
+
+fn read_pfm(path: &str) -> Result<(Mat, Mat), Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    // "PF", width, height and scale separated by whitespace, the data follows a single
+    // whitespace character
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        if byte.is_ascii_whitespace() {
+            if i > start {
+                fields.push(String::from_utf8_lossy(&bytes[start..i]).to_string());
+            }
+            start = i + 1;
+            if fields.len() == 4 {
+                break;
+            }
+        }
+    }
+    let [kind, width, height, scale] = fields.as_slice() else {
+        return Err(format!("{path} has no pfm header").into());
+    };
+    if kind.as_str() != "PF" {
+        return Err(format!("{path} is not a 3 channel pfm").into());
+    }
+    let size = Size::new(width.parse()?, height.parse()?);
+    check_size(path, size)?;
+    let little_endian = scale.parse::<f32>()? < 0.0;
+    let data = &bytes[start..];
+    let (width, pixels) = (size.width as usize, (size.width * size.height) as usize);
+    if data.len() != pixels * 3 * size_of::<f32>() {
+        return Err(format!("{path} doesn't hold {}x{} pixels", size.width, size.height).into());
+    }
+    let values = data
+        .chunks_exact(size_of::<f32>())
+        .map(|chunk| {
+            let chunk = chunk.try_into().unwrap();
+            if little_endian {
+                f32::from_le_bytes(chunk)
+            } else {
+                f32::from_be_bytes(chunk)
+            }
+        })
+        .collect::<Vec<f32>>();
+    let mut xs = Vec::with_capacity(pixels);
+    let mut ys = Vec::with_capacity(pixels);
+    for row in values.chunks_exact(width * 3).rev() {
+        for pixel in row.chunks_exact(3) {
+            xs.push(pixel[0]);
+            ys.push(pixel[1]);
+        }
+    }
+    Ok(mats(size, &xs, &ys)?)
+}
+
+fn read_raw(path: &str) -> Result<(Mat, Mat), Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    if bytes.len() < RAW_HEADER || bytes[..4] != *RAW_MAGIC {
+        return Err(format!("{path} is not a raw map file").into());
+    }
+    let header = |i: usize| u32::from_le_bytes(bytes[i * 4..(i + 1) * 4].try_into().unwrap());
+    if header(1) != RAW_VERSION {
+        return Err(format!("{path} has map file version {}", header(1)).into());
+    }
+    let size = Size::new(header(2) as i32, header(3) as i32);
+    check_size(path, size)?;
+    let pixels = (size.width * size.height) as usize;
+    if bytes.len() != RAW_HEADER + pixels * 2 * size_of::<f32>() {
+        return Err(format!("{path} doesn't hold {}x{} maps", size.width, size.height).into());
+    }
+    let values = bytes[RAW_HEADER..]
+        .chunks_exact(size_of::<f32>())
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect::<Vec<f32>>();
+    let (xs, ys) = values.split_at(pixels);
+    Ok(mats(size, xs, ys)?)
+}
+
+/// remap tables written by `export` or another tool, the format by the file extension
+pub fn read(path: &str) -> Result<(Mat, Mat), Box<dyn Error>> {
+    let (mapx, mapy) = match Format::of(path) {
+        Format::Exr => read_exr(path)?,
+        Format::Pfm => read_pfm(path)?,
+        Format::Raw => read_raw(path)?,
+    };
+    info!("maps for {}x{} from {path}", mapx.cols(), mapx.rows());
+    Ok((mapx, mapy))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+
+    // 3x2 maps, every value different and some negative
+    const SIZE: Size = Size::new(3, 2);
+    const XS: [f32; 6] = [0.5, 1.5, 2.5, -0.25, 1.0e-3, 4096.75];
+    const YS: [f32; 6] = [-1.0, 0.0, 0.125, 1.5, 2.5, 3.5];
+
+    fn temp_file(extension: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "opencv-undistort-maps-{}-{}.{extension}",
+                process::id(),
+                FILES.fetch_add(1, Ordering::Relaxed)
+            ))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn read_back(path: &str) -> (Vec<f32>, Vec<f32>) {
+        let (mapx, mapy) = read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(mapx.size().unwrap(), SIZE);
+        assert_eq!(mapy.size().unwrap(), SIZE);
+        (
+            mapx.data_typed::<f32>().unwrap().to_vec(),
+            mapy.data_typed::<f32>().unwrap().to_vec(),
+        )
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let path = temp_file("pfm");
+        write_pfm(&path, SIZE, &XS, &YS).unwrap();
+        assert_eq!(read_back(&path), (XS.to_vec(), YS.to_vec()));
+    }
+
+    #[test]
+    fn pfm_bottom_up_little_endian() {
+        let path = temp_file("pfm");
+        write_pfm(&path, SIZE, &XS, &YS).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert!(bytes.starts_with(header));
+        // the first pixel in the file is the first one of the bottom row
+        let first = &bytes[header.len()..header.len() + 8];
+        assert_eq!(first[..4], XS[3].to_le_bytes());
+        assert_eq!(first[4..], YS[3].to_le_bytes());
+    }
+
+    #[test]
+    fn pfm_big_endian() {
+        // a positive scale marks big endian, as other tools write it
+        let mut bytes = b"PF\n3 2\n1.0\n".to_vec();
+        for row in [1, 0] {
+            for i in row * 3..(row + 1) * 3 {
+                for value in [XS[i], YS[i], 0.0] {
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        let path = temp_file("pfm");
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(read_back(&path), (XS.to_vec(), YS.to_vec()));
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let path = temp_file("raw");
+        write_raw(&path, SIZE, &XS, &YS).unwrap();
+        assert_eq!(read_back(&path), (XS.to_vec(), YS.to_vec()));
+    }
+
+    #[test]
+    fn truncated_raw() {
+        let path = temp_file("raw");
+        write_raw(&path, SIZE, &XS, &YS).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let error = read(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("doesn't hold 3x2 maps"));
+    }
+}
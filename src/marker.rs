@@ -0,0 +1,143 @@
+use std::error::Error;
+
+use clap::ValueEnum;
+use log::{info, warn};
+use opencv::calib3d::{SOLVEPNP_IPPE_SQUARE, solve_pnp};
+use opencv::core::{Point2f, Point3f, Vector};
+use opencv::imgcodecs;
+use opencv::objdetect::{
+    ArucoDetector, DetectorParameters, PredefinedDictionaryType, RefineParameters,
+    get_predefined_dictionary,
+};
+use opencv::prelude::*;
+use serde::Serialize;
+
+use crate::{Calibration, CameraModel, list_images, mat_to_vec};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Dictionary {
+    #[value(name = "4x4_50")]
+    Dict4x4_50,
+    #[value(name = "5x5_100")]
+    Dict5x5_100,
+    #[value(name = "6x6_250")]
+    Dict6x6_250,
+    #[value(name = "7x7_1000")]
+    Dict7x7_1000,
+    /// the original aruco library codes
+    Original,
+}
+
+impl Dictionary {
+    fn predefined(self) -> PredefinedDictionaryType {
+        match self {
+            Dictionary::Dict4x4_50 => PredefinedDictionaryType::DICT_4X4_50,
+            Dictionary::Dict5x5_100 => PredefinedDictionaryType::DICT_5X5_100,
+            Dictionary::Dict6x6_250 => PredefinedDictionaryType::DICT_6X6_250,
+            Dictionary::Dict7x7_1000 => PredefinedDictionaryType::DICT_7X7_1000,
+            Dictionary::Original => PredefinedDictionaryType::DICT_ARUCO_ORIGINAL,
+        }
+    }
+}
+
+/// one marker in one image, printed as a json line
+#[derive(Serialize)]
+struct MarkerPose<'a> {
+    image: &'a str,
+    id: i32,
+    /// marker pose in the camera frame, rodrigues rotation and translation in mm
+    rvec: Vec<f64>,
+    tvec: Vec<f64>,
+    distance_mm: f64,
+    /// image scale at the marker
+    mm_per_pixel: f64,
+}
+
+// marker corners in detection order: top left, top right, bottom right, bottom left
+fn marker_points(size_mm: f32) -> Vector<Point3f> {
+    let half = size_mm / 2.0;
+    Vector::from_iter([
+        Point3f::new(-half, half, 0.0),
+        Point3f::new(half, half, 0.0),
+        Point3f::new(half, -half, 0.0),
+        Point3f::new(-half, -half, 0.0),
+    ])
+}
+
+/// pose and scale of every marker in the images of a directory. Images written by `correct`
+/// share the camera matrix with the calibration and have no distortion left, `distorted`
+/// images have their marker corners undistorted first
+pub fn poses(
+    calibration: &Calibration,
+    image_dir: &str,
+    dictionary: Dictionary,
+    marker_size_mm: f32,
+    marker_id: Option<i32>,
+    distorted: bool,
+) -> Result<(), Box<dyn Error>> {
+    if calibration.model == CameraModel::Affine {
+        return Err("marker poses need a perspective camera, not an affine calibration".into());
+    }
+    let detector = ArucoDetector::new(
+        &get_predefined_dictionary(dictionary.predefined())?,
+        &DetectorParameters::default()?,
+        RefineParameters::new_def()?,
+    )?;
+    let objp = marker_points(marker_size_mm);
+    let mut found = 0;
+    for image in list_images(image_dir)? {
+        let img = imgcodecs::imread_def(&image)?;
+        let calibration = calibration.scaled(img.size()?);
+        let mtx = calibration.new_matrix()?;
+        let mut corners = Vector::<Vector<Point2f>>::new();
+        let mut ids = Vector::<i32>::new();
+        detector.detect_markers_def(&img, &mut corners, &mut ids)?;
+        for (id, marker) in ids.iter().zip(corners.iter()) {
+            if marker_id.is_some_and(|marker_id| marker_id != id) {
+                continue;
+            }
+            let marker = if distorted {
+                let mut undistorted = Vector::<Point2f>::new();
+                calibration.undistort_points(&marker, &mut undistorted, img.size()?)?;
+                undistorted
+            } else {
+                marker
+            };
+            let mut rvec = Mat::default();
+            let mut tvec = Mat::default();
+            if !solve_pnp(
+                &objp,
+                &marker,
+                &mtx,
+                &Mat::default(),
+                &mut rvec,
+                &mut tvec,
+                false,
+                SOLVEPNP_IPPE_SQUARE,
+            )? {
+                warn!("{image}: no pose for marker {id}");
+                continue;
+            }
+            let tvec = mat_to_vec(&tvec)?;
+            let side = (0..4)
+                .map(|i| {
+                    let (a, b) = (marker.get(i)?, marker.get((i + 1) % 4)?);
+                    Ok(((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt() as f64)
+                })
+                .sum::<opencv::Result<f64>>()?
+                / 4.0;
+            let pose = MarkerPose {
+                image: &image,
+                id,
+                rvec: mat_to_vec(&rvec)?,
+                distance_mm: tvec.iter().map(|t| t * t).sum::<f64>().sqrt(),
+                tvec,
+                mm_per_pixel: marker_size_mm as f64 / side,
+            };
+            println!("{}", serde_json::to_string(&pose)?);
+            found += 1;
+        }
+    }
+    info!("{found} marker poses");
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+// latency bucket upper bounds in seconds
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+// a scrape that sends or reads nothing for this long is dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+static FRAMES_PROCESSED: AtomicU64 = AtomicU64::new(0);
+static FRAMES_FAILED: AtomicU64 = AtomicU64::new(0);
+static STAGES: LazyLock<Mutex<BTreeMap<&'static str, Histogram>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// count a corrected or failed frame
+pub fn frame(ok: bool) {
+    if ok {
+        FRAMES_PROCESSED.fetch_add(1, Ordering::Relaxed);
+    } else {
+        FRAMES_FAILED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn observe(stage: &'static str, elapsed: Duration) {
+    STAGES
+        .lock()
+        .unwrap()
+        .entry(stage)
+        .or_default()
+        .observe(elapsed.as_secs_f64());
+}
+
+/// run one stage of the frame pipeline and record its latency
+pub fn time<T>(stage: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    observe(stage, started.elapsed());
+    result
+}
+
+// prometheus text exposition format
+fn render() -> String {
+    let mut text = String::new();
+    writeln!(
+        text,
+        "# HELP undistort_frames_processed_total frames corrected\n# TYPE undistort_frames_processed_total counter\nundistort_frames_processed_total {}",
+        FRAMES_PROCESSED.load(Ordering::Relaxed)
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "# HELP undistort_frames_failed_total frames that could not be corrected\n# TYPE undistort_frames_failed_total counter\nundistort_frames_failed_total {}",
+        FRAMES_FAILED.load(Ordering::Relaxed)
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "# HELP undistort_stage_seconds latency of each pipeline stage\n# TYPE undistort_stage_seconds histogram"
+    )
+    .unwrap();
+    for (stage, histogram) in STAGES.lock().unwrap().iter() {
+        for (count, bound) in histogram.counts.iter().zip(BUCKETS) {
+            writeln!(
+                text,
+                "undistort_stage_seconds_bucket{{stage=\"{stage}\",le=\"{bound}\"}} {count}"
+            )
+            .unwrap();
+        }
+        writeln!(
+            text,
+            "undistort_stage_seconds_bucket{{stage=\"{stage}\",le=\"+Inf\"}} {}",
+            histogram.count
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "undistort_stage_seconds_sum{{stage=\"{stage}\"}} {}",
+            histogram.sum
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "undistort_stage_seconds_count{{stage=\"{stage}\"}} {}",
+            histogram.count
+        )
+        .unwrap();
+    }
+    text
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    // one silent client must not hold up every later scrape
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream)
+        .take(MAX_REQUEST_LINE)
+        .read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, content_type, body) = if path == "/metrics" {
+        ("200 OK", "text/plain; version=0.0.4", render())
+    } else {
+        ("404 Not Found", "text/plain", "not found\n".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// serve /metrics on a background thread
+pub fn serve(port: u16) -> io::Result<()> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(address)?;
+    info!("metrics on http://{address}/metrics");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream) {
+                warn!("metrics request failed: {e}");
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use opencv::core::Size;
+use opencv::imgcodecs::{self, IMREAD_UNCHANGED};
+use opencv::imgproc;
+use opencv::prelude::*;
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+
+use crate::{Calibration, metrics, undistort_maps};
+
+/// capture-complete message, either this json or a bare file path
+#[derive(Deserialize)]
+struct Capture {
+    path: String,
+    /// echoed back so the cell controller can match the reply
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct Corrected<'a> {
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    elapsed_ms: u128,
+}
+
+fn parse_capture(payload: &[u8]) -> Capture {
+    serde_json::from_slice(payload).unwrap_or_else(|_| Capture {
+        path: String::from_utf8_lossy(payload).trim().to_string(),
+        id: None,
+    })
+}
+
+pub fn run(
+    calibration_file: &str,
+    output_dir: &str,
+    host: &str,
+    port: u16,
+    client_id: &str,
+    capture_topic: &str,
+    done_topic: &str,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+
+    // maps are built once per incoming image size
+    let mut maps: Option<(Size, Mat, Mat)> = None;
+    let mut correct = |path: &str| -> Result<String, Box<dyn Error>> {
+        let img = metrics::time("read", || imgcodecs::imread(path, IMREAD_UNCHANGED))?;
+        if img.empty() {
+            return Err(format!("could not read image {path}").into());
+        }
+        let size = img.size()?;
+        if maps
+            .as_ref()
+            .is_none_or(|(map_size, _, _)| *map_size != size)
+        {
+            let (mapx, mapy) = undistort_maps(&calibration, size)?;
+            maps = Some((size, mapx, mapy));
+        }
+        let (_, mapx, mapy) = maps.as_ref().unwrap();
+        let mut corrected = Mat::default();
+        metrics::time("undistort", || {
+            imgproc::remap_def(&img, &mut corrected, mapx, mapy, imgproc::INTER_LINEAR)
+        })?;
+        let name = Path::new(path)
+            .file_name()
+            .ok_or_else(|| format!("no file name in {path}"))?;
+        let output = Path::new(output_dir)
+            .join(format!("u_{}", name.to_string_lossy()))
+            .to_string_lossy()
+            .to_string();
+        if !metrics::time("write", || imgcodecs::imwrite_def(&output, &corrected))? {
+            return Err(format!("could not write {output}").into());
+        }
+        Ok(output)
+    };
+
+    let mut mqtt_options = MqttOptions::new(client_id, host, port);
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut connection) = Client::new(mqtt_options, 16);
+    client.subscribe(capture_topic, QoS::AtLeastOnce)?;
+    info!("waiting for captures on {capture_topic} at {host}:{port}, replies on {done_topic}");
+
+    for notification in connection.iter() {
+        let publish = match notification {
+            Ok(Event::Incoming(Packet::Publish(publish))) => publish,
+            Ok(_) => continue,
+            Err(e) => {
+                // the event loop reconnects on the next poll
+                warn!("mqtt connection error: {e}");
+                std::thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+        let capture = parse_capture(&publish.payload);
+        let started = Instant::now();
+        let result = correct(&capture.path);
+        metrics::frame(result.is_ok());
+        let (output, error) = match result {
+            Ok(output) => {
+                info!("{} corrected to {output}", capture.path);
+                (Some(output), None)
+            }
+            Err(e) => {
+                warn!("could not correct {}: {e}", capture.path);
+                (None, Some(e.to_string()))
+            }
+        };
+        let reply = Corrected {
+            path: &capture.path,
+            id: capture.id.as_ref(),
+            output,
+            error,
+            elapsed_ms: started.elapsed().as_millis(),
+        };
+        client.publish(
+            done_topic,
+            QoS::AtLeastOnce,
+            false,
+            serde_json::to_vec(&reply)?,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use opencv::core::{Size, Vector};
+use opencv::imgcodecs::{self, IMWRITE_JPEG_QUALITY};
+use opencv::imgproc;
+use opencv::prelude::*;
+use opencv::videoio::{CAP_FFMPEG, CAP_PROP_FPS, VideoCapture};
+
+use crate::video::VideoSink;
+use crate::{Calibration, metrics, undistort_maps};
+
+// wait before reconnecting to a camera that dropped the stream
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// latest encoded frame with a sequence number, mjpeg clients wait for the next one
+#[derive(Default)]
+struct LatestFrame {
+    frame: Mutex<(u64, Arc<Vec<u8>>)>,
+    updated: Condvar,
+}
+
+impl LatestFrame {
+    fn publish(&self, jpeg: Vec<u8>) {
+        let mut frame = self.frame.lock().unwrap();
+        *frame = (frame.0 + 1, Arc::new(jpeg));
+        self.updated.notify_all();
+    }
+
+    fn next(&self, after: u64) -> (u64, Arc<Vec<u8>>) {
+        let frame = self
+            .updated
+            .wait_while(self.frame.lock().unwrap(), |(sequence, _)| {
+                *sequence <= after
+            })
+            .unwrap();
+        (frame.0, frame.1.clone())
+    }
+}
+
+fn stream_mjpeg(mut stream: TcpStream, latest: &LatestFrame) -> io::Result<()> {
+    // the request itself doesn't matter, every path gets the stream
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut sequence = 0;
+    loop {
+        let (next, jpeg) = latest.next(sequence);
+        sequence = next;
+        write!(
+            stream,
+            "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            jpeg.len()
+        )?;
+        stream.write_all(&jpeg)?;
+        stream.write_all(b"\r\n")?;
+    }
+}
+
+fn serve_mjpeg(port: u16, latest: Arc<LatestFrame>) -> io::Result<()> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(address)?;
+    info!("mjpeg stream on http://{address}/");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let latest = latest.clone();
+            // a client going away ends its thread, nothing to report
+            thread::spawn(move || stream_mjpeg(stream, &latest));
+        }
+    });
+    Ok(())
+}
+
+fn open(input: &str) -> Result<VideoCapture, Box<dyn Error>> {
+    let capture = VideoCapture::from_file(input, CAP_FFMPEG)?;
+    if !capture.is_opened()? {
+        return Err(format!("could not open {input}").into());
+    }
+    Ok(capture)
+}
+
+/// pull a camera stream, undistort it and re-publish it over rtsp and/or mjpeg, reconnecting
+/// whenever the camera drops out
+pub fn run(
+    calibration_file: &str,
+    input: &str,
+    rtsp_output: Option<&str>,
+    mjpeg_port: Option<u16>,
+    jpeg_quality: i32,
+) -> Result<(), Box<dyn Error>> {
+    if rtsp_output.is_none() && mjpeg_port.is_none() {
+        return Err("nothing to publish, pass --rtsp-output and/or --mjpeg-port".into());
+    }
+    let calibration = Calibration::load(calibration_file)?;
+    let latest = Arc::new(LatestFrame::default());
+    if let Some(port) = mjpeg_port {
+        serve_mjpeg(port, latest.clone())?;
+    }
+    let jpeg_params = Vector::from_slice(&[IMWRITE_JPEG_QUALITY, jpeg_quality]);
+
+    let mut frame = Mat::default();
+    let mut corrected = Mat::default();
+    // maps and the rtsp publisher follow the stream resolution
+    let mut maps: Option<(Size, Mat, Mat)> = None;
+    let mut publisher: Option<VideoSink> = None;
+    // no new publisher before this while the rtsp server is down
+    let mut publish_after = Instant::now();
+    loop {
+        let mut capture = match open(input) {
+            Ok(capture) => capture,
+            Err(e) => {
+                warn!("{e}, retrying");
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+        };
+        let fps = match capture.get(CAP_PROP_FPS)? {
+            reported if reported > 0.0 => reported,
+            _ => 25.0,
+        };
+        info!("reading {input} at {fps} fps");
+        while capture.read(&mut frame).unwrap_or(false) && !frame.empty() {
+            let size = frame.size()?;
+            if maps
+                .as_ref()
+                .is_none_or(|(map_size, _, _)| *map_size != size)
+            {
+                let (mapx, mapy) = undistort_maps(&calibration, size)?;
+                maps = Some((size, mapx, mapy));
+                // the encoder can't change resolution mid stream
+                if let Some(publisher) = publisher.take() {
+                    publisher.finish().ok();
+                }
+            }
+            let (_, mapx, mapy) = maps.as_ref().unwrap();
+            metrics::time("undistort", || {
+                imgproc::remap_def(&frame, &mut corrected, mapx, mapy, imgproc::INTER_LINEAR)
+            })?;
+            if let Some(url) = rtsp_output
+                && (publisher.is_some() || Instant::now() >= publish_after)
+            {
+                if publisher.is_none() {
+                    match VideoSink::rtsp(url, fps, size, corrected.channels()) {
+                        Ok(sink) => publisher = Some(sink),
+                        Err(e) => warn!("publishing to {url}: {e}, retrying"),
+                    }
+                }
+                if let Some(sink) = publisher.as_mut()
+                    && let Err(e) = sink.write(&corrected)
+                {
+                    warn!("publishing to {url} failed: {e}, retrying");
+                    publisher.take().unwrap().finish().ok();
+                }
+                // the rtsp server went away, the frames until the delay passed aren't published
+                if publisher.is_none() {
+                    publish_after = Instant::now() + RECONNECT_DELAY;
+                }
+            }
+            if mjpeg_port.is_some() {
+                let mut jpeg = Vector::<u8>::new();
+                metrics::time("encode", || {
+                    imgcodecs::imencode(".jpg", &corrected, &mut jpeg, &jpeg_params)
+                })?;
+                latest.publish(jpeg.to_vec());
+            }
+            metrics::frame(true);
+        }
+        warn!("{input} stopped, reconnecting");
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
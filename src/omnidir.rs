@@ -0,0 +1,174 @@
+use clap::ValueEnum;
+use opencv::ccalib::{
+    CALIB_FIX_SKEW, RECTIFY_CYLINDRICAL, RECTIFY_LONGLATI, RECTIFY_PERSPECTIVE,
+    RECTIFY_STEREOGRAPHIC, calibrate, init_undistort_rectify_map, project_points_def,
+    undistort_points,
+};
+use opencv::core::{
+    Mat, Point2f, Point3f, Size, TermCriteria, TermCriteria_COUNT, TermCriteria_EPS, Vector,
+};
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Calibration, mat_to_vec, view_rms};
+
+/// view the undistorted image is rendered in
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rectification {
+    /// pinhole view of the central part of the field of view
+    #[default]
+    Perspective,
+    /// straight verticals over the whole horizontal field of view
+    Cylindrical,
+    /// longitude and latitude, equirectangular
+    Longlat,
+    Stereographic,
+}
+
+/// parameters of the unified camera model beyond the camera matrix and k1, k2, p1, p2
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Omnidir {
+    /// mirror parameter of the unified model, 0 is a pinhole camera
+    pub xi: f64,
+    #[serde(default)]
+    pub rectification: Rectification,
+}
+
+/// omnidirectional calibration, rms error, camera matrix, k1, k2, p1, p2, xi and the rms
+/// reprojection error of every view, infinite for views the calibration could not use
+pub fn calibrate_camera(
+    objpoints: &Vector<Vector<Point3f>>,
+    imgpoints: &Vector<Vector<Point2f>>,
+    size: Size,
+) -> opencv::Result<(f64, Mat, Mat, f64, Vec<f64>)> {
+    let mut mtx = Mat::default();
+    let mut xi = Mat::default();
+    let mut dist = Mat::default();
+    let mut rvecs = Vector::<Mat>::new();
+    let mut tvecs = Vector::<Mat>::new();
+    // indices of the views the initialization succeeded for, the poses are theirs
+    let mut used = Mat::default();
+    let rms = calibrate(
+        objpoints,
+        imgpoints,
+        size,
+        &mut mtx,
+        &mut xi,
+        &mut dist,
+        &mut rvecs,
+        &mut tvecs,
+        CALIB_FIX_SKEW,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 200, 1e-8)?,
+        &mut used,
+    )?;
+    let xi = mat_to_vec(&xi)?.first().copied().unwrap_or_default();
+    let mut view_errors = vec![f64::INFINITY; objpoints.len()];
+    let used = if used.empty() {
+        (0..objpoints.len() as i32).collect()
+    } else {
+        used.data_typed::<i32>()?.to_vec()
+    };
+    for (pose, view) in used.iter().enumerate() {
+        let view = *view as usize;
+        let mut projected = Vector::<Point2f>::new();
+        project_points_def(
+            &objpoints.get(view)?,
+            &mut projected,
+            &rvecs.get(pose)?,
+            &tvecs.get(pose)?,
+            &mtx,
+            xi,
+            &dist,
+        )?;
+        view_errors[view] = view_rms(&projected, &imgpoints.get(view)?);
+    }
+    Ok((rms, mtx, dist, xi, view_errors))
+}
+
+// camera matrix of the rectified view, the choices of opencv's omnidir tutorial
+fn new_camera_matrix(rectification: Rectification, size: Size) -> opencv::Result<Mat> {
+    let (w, h) = (size.width as f64, size.height as f64);
+    let k = match rectification {
+        Rectification::Perspective | Rectification::Stereographic => {
+            [w / 4.0, 0.0, w / 2.0, 0.0, h / 4.0, h / 2.0, 0.0, 0.0, 1.0]
+        }
+        Rectification::Cylindrical | Rectification::Longlat => [
+            w / std::f64::consts::PI,
+            0.0,
+            0.0,
+            0.0,
+            h / std::f64::consts::PI,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ],
+    };
+    Mat::new_rows_cols_with_data(3, 3, &k)?.try_clone()
+}
+
+fn flags(rectification: Rectification) -> i32 {
+    match rectification {
+        Rectification::Perspective => RECTIFY_PERSPECTIVE,
+        Rectification::Cylindrical => RECTIFY_CYLINDRICAL,
+        Rectification::Longlat => RECTIFY_LONGLATI,
+        Rectification::Stereographic => RECTIFY_STEREOGRAPHIC,
+    }
+}
+
+fn parameters(calibration: &Calibration) -> Omnidir {
+    calibration.omnidir.unwrap_or(Omnidir {
+        xi: 0.0,
+        rectification: Rectification::default(),
+    })
+}
+
+/// remap tables for a calibration already scaled to the image size
+pub fn maps(calibration: &Calibration, size: Size) -> opencv::Result<(Mat, Mat)> {
+    let omnidir = parameters(calibration);
+    let (mtx, dist) = calibration.matrices()?;
+    let mut map1 = Mat::default();
+    let mut map2 = Mat::default();
+    init_undistort_rectify_map(
+        &mtx,
+        &dist,
+        &Vector::<f64>::from_slice(&[omnidir.xi]),
+        &Mat::eye(3, 3, f64::opencv_type())?.to_mat()?,
+        &new_camera_matrix(omnidir.rectification, size)?,
+        size,
+        f32::opencv_type(),
+        &mut map1,
+        &mut map2,
+        flags(omnidir.rectification),
+    )?;
+    Ok((map1, map2))
+}
+
+/// pixel positions in the perspective rectified image
+pub fn undistort_pixels(
+    calibration: &Calibration,
+    distorted: &Vector<Point2f>,
+    undistorted: &mut Vector<Point2f>,
+    size: Size,
+) -> opencv::Result<()> {
+    let omnidir = parameters(calibration);
+    let (mtx, dist) = calibration.matrices()?;
+    let mut normalized = Vector::<Point2f>::new();
+    undistort_points(
+        distorted,
+        &mut normalized,
+        &mtx,
+        &dist,
+        &Vector::<f64>::from_slice(&[omnidir.xi]),
+        &Mat::eye(3, 3, f64::opencv_type())?.to_mat()?,
+    )?;
+    let k = mat_to_vec(&new_camera_matrix(Rectification::Perspective, size)?)?;
+    *undistorted = Vector::from_iter(normalized.iter().map(|point| {
+        Point2f::new(
+            (k[0] * point.x as f64 + k[2]) as f32,
+            (k[4] * point.y as f64 + k[5]) as f32,
+        )
+    }));
+    Ok(())
+}
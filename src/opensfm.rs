@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{Calibration, CameraModel};
+
+/// brown camera of camera_models.json, lengths normalized by the larger image side and the
+/// principal point relative to the image center
+#[derive(Serialize)]
+struct BrownCamera {
+    projection_type: &'static str,
+    width: i32,
+    height: i32,
+    focal_x: f64,
+    focal_y: f64,
+    c_x: f64,
+    c_y: f64,
+    k1: f64,
+    k2: f64,
+    p1: f64,
+    p2: f64,
+    k3: f64,
+}
+
+impl BrownCamera {
+    fn new(calibration: &Calibration, width: i32, height: i32) -> Self {
+        let k = &calibration.camera_matrix;
+        let scale = width.max(height) as f64;
+        // opencv order k1, k2, p1, p2, k3, missing terms are zero
+        let coefficient = |i: usize| calibration.dist_coeffs.get(i).copied().unwrap_or(0.0);
+        if calibration
+            .dist_coeffs
+            .iter()
+            .skip(5)
+            .any(|term| *term != 0.0)
+        {
+            warn!("rational and thin prism distortion terms are left out of the brown camera");
+        }
+        BrownCamera {
+            projection_type: "brown",
+            width,
+            height,
+            focal_x: k[0] / scale,
+            focal_y: k[4] / scale,
+            c_x: (k[2] - width as f64 / 2.0) / scale,
+            c_y: (k[5] - height as f64 / 2.0) / scale,
+            k1: coefficient(0),
+            k2: coefficient(1),
+            p1: coefficient(2),
+            p2: coefficient(3),
+            k3: coefficient(4),
+        }
+    }
+
+    // the id opensfm derives from exif when make and model are unknown
+    fn default_id(&self) -> String {
+        format!(
+            "v2 unknown unknown {} {} brown {:.4}",
+            self.width, self.height, self.focal_x
+        )
+    }
+}
+
+/// add the calibration to an OpenSfM/ODM camera_models.json (or cameras.json), keeping the
+/// cameras already in the file
+pub fn export(
+    calibration_file: &str,
+    output: &str,
+    width: i32,
+    height: i32,
+    camera_id: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+    if calibration.model != CameraModel::Pinhole {
+        return Err(format!(
+            "only pinhole calibrations convert to opensfm brown cameras, not {:?}",
+            calibration.model
+        )
+        .into());
+    }
+    let camera = BrownCamera::new(&calibration, width, height);
+    let id = camera_id.map_or_else(|| camera.default_id(), str::to_string);
+
+    let mut cameras = if Path::new(output).exists() {
+        serde_json::from_slice::<Map<String, Value>>(&fs::read(output)?)?
+    } else {
+        Map::new()
+    };
+    cameras.insert(id.clone(), serde_json::to_value(&camera)?);
+    fs::write(output, serde_json::to_string_pretty(&cameras)?)?;
+    info!("camera \"{id}\" written to {output}");
+    Ok(())
+}
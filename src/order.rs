@@ -0,0 +1,88 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortOrder {
+    /// by name with digit runs compared as numbers, img2 before img10
+    #[default]
+    Natural,
+    /// by name, byte by byte
+    Name,
+    /// oldest modification time first
+    Mtime,
+    /// as listed by the filesystem
+    None,
+}
+
+// set once from the global --sort flag
+static ORDER: OnceLock<SortOrder> = OnceLock::new();
+
+pub fn init(order: SortOrder) {
+    ORDER.set(order).expect("sort order is set once");
+}
+
+// leading zeros don't change the value, a longer run of significant digits is larger
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let (a_value, b_value) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+    a_value
+        .len()
+        .cmp(&b_value.len())
+        .then_with(|| a_value.cmp(b_value))
+        .then_with(|| a.len().cmp(&b.len()))
+}
+
+// text and digit runs of a name
+fn chunks(name: &str) -> impl Iterator<Item = &str> {
+    let mut rest = name;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != first.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+fn natural(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    loop {
+        let ordering = match (a_chunks.next(), b_chunks.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b))
+                if a.starts_with(|c: char| c.is_ascii_digit())
+                    && b.starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                compare_numbers(a, b)
+            }
+            (Some(a), Some(b)) => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// sort file paths in the order picked with `--sort` so point order, logs and outputs are the
+/// same on every platform
+pub fn sort(paths: &mut [String]) {
+    match ORDER.get().copied().unwrap_or_default() {
+        SortOrder::Natural => paths.sort_by(|a, b| natural(a, b)),
+        SortOrder::Name => paths.sort(),
+        SortOrder::Mtime => paths.sort_by_cached_key(|path| {
+            let modified = fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, path.clone())
+        }),
+        SortOrder::None => {}
+    }
+}
@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use clap::Args;
+use log::{info, warn};
+use opencv::core::Size;
+
+use crate::{Calibration, CameraModel};
+
+// diagonal of a full frame sensor, the reference of crop factors
+const FULL_FRAME_DIAGONAL_MM: f64 = 43.2666;
+// radii the ptlens polynomial is fitted at, from the center to the image corner
+const FIT_SAMPLES: usize = 200;
+
+/// the lens a profile is written for
+#[derive(Args, Debug, Clone)]
+pub struct LensArgs {
+    /// lens maker of the lensfun profile
+    #[arg(long, default_value = "Unknown")]
+    pub maker: String,
+    /// lens model of the lensfun profile, the calibration file name by default
+    #[arg(long)]
+    pub lens_model: Option<String>,
+    /// lensfun mount the lens fits, needed for lensfun to offer it for a camera
+    #[arg(long)]
+    pub mount: Option<String>,
+    /// crop factor of the sensor the calibration images were taken with
+    #[arg(long, default_value_t = 1.0)]
+    pub crop_factor: f64,
+    /// focal length the lens is looked up at, by default from the calibrated focal length in
+    /// pixels and the crop factor
+    #[arg(long)]
+    pub focal_length_mm: Option<f64>,
+}
+
+/// the calibration in the panotools model both lensfun's ptlens profiles and hugin use:
+/// rd = ru * (a * ru^3 + b * ru^2 + c * ru + 1 - a - b - c) with radii in units of half the
+/// shorter image side, the lens shift in pixels and the focal length that goes with a, b, c
+pub struct Ptlens {
+    pub width: i32,
+    pub height: i32,
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    /// principal point relative to the image center
+    pub d: f64,
+    pub e: f64,
+    pub focal_px: f64,
+}
+
+impl Ptlens {
+    /// least squares fit of opencv's radial k1, k2, k3 from the center to the image corner,
+    /// tangential and higher terms have no panotools counterpart and are left out
+    pub fn fit(calibration: &Calibration, size: Size) -> Result<Self, Box<dyn Error>> {
+        if calibration.model != CameraModel::Pinhole {
+            return Err(format!(
+                "only pinhole calibrations convert to the panotools model, not {:?}",
+                calibration.model
+            )
+            .into());
+        }
+        let calibration = calibration.scaled(size);
+        let k = &calibration.camera_matrix;
+        let dist = &calibration.dist_coeffs;
+        let coefficient = |i: usize| dist.get(i).copied().unwrap_or(0.0);
+        if coefficient(2) != 0.0 || coefficient(3) != 0.0 || dist.len() > 5 {
+            warn!("tangential and rational distortion terms are left out of the profile");
+        }
+        let (k1, k2, k3) = (coefficient(0), coefficient(1), coefficient(4));
+        // opencv's ratio of distorted to undistorted radius in focal lengths
+        let ratio = |r: f64| 1.0 + k1 * r.powi(2) + k2 * r.powi(4) + k3 * r.powi(6);
+
+        let focal = (k[0] + k[4]) / 2.0;
+        let unit = size.width.min(size.height) as f64 / 2.0;
+        let s = unit / focal;
+        // panotools keeps the radius of one unit in place, the focal length grows by the
+        // ratio there: scale = ratio(s / scale)
+        let mut scale = 1.0;
+        for _ in 0..50 {
+            scale = ratio(s / scale);
+        }
+        let target = |u: f64| ratio(u * s / scale) / scale;
+
+        // minimize the sum of (a (u^3 - 1) + b (u^2 - 1) + c (u - 1) - (target(u) - 1))^2
+        let corner = (size.width as f64).hypot(size.height as f64) / 2.0 / unit;
+        let mut normal = [[0.0; 3]; 3];
+        let mut rhs = [0.0; 3];
+        for i in 1..=FIT_SAMPLES {
+            let u = corner * i as f64 / FIT_SAMPLES as f64;
+            let basis = [u.powi(3) - 1.0, u.powi(2) - 1.0, u - 1.0];
+            let value = target(u) - 1.0;
+            for (row, x) in basis.iter().enumerate() {
+                for (column, y) in basis.iter().enumerate() {
+                    normal[row][column] += x * y;
+                }
+                rhs[row] += x * value;
+            }
+        }
+        let [a, b, c] = solve3(normal, rhs).ok_or("the distortion can't be fitted")?;
+        Ok(Ptlens {
+            width: size.width,
+            height: size.height,
+            a,
+            b,
+            c,
+            d: k[2] - size.width as f64 / 2.0,
+            e: k[5] - size.height as f64 / 2.0,
+            focal_px: focal * scale,
+        })
+    }
+
+    /// horizontal field of view in degrees of the undistorted rectilinear image
+    pub fn hfov(&self) -> f64 {
+        2.0 * (self.width as f64 / 2.0 / self.focal_px)
+            .atan()
+            .to_degrees()
+    }
+
+    /// focal length in mm on a sensor of the crop factor
+    pub fn focal_mm(&self, crop_factor: f64) -> f64 {
+        let diagonal_px = (self.width as f64).hypot(self.height as f64);
+        self.focal_px * FULL_FRAME_DIAGONAL_MM / crop_factor / diagonal_px
+    }
+
+    /// lensfun database with the one lens, for `~/.local/share/lensfun/`
+    pub fn lensfun_xml(&self, lens: &LensArgs, model: &str) -> String {
+        let focal = lens
+            .focal_length_mm
+            .unwrap_or_else(|| self.focal_mm(lens.crop_factor));
+        let mount = lens.mount.as_deref().map_or(String::new(), |mount| {
+            format!("        <mount>{}</mount>\n", escape(mount))
+        });
+        format!(
+            r#"<lensdatabase version="2">
+    <lens>
+        <maker>{}</maker>
+        <model>{}</model>
+{mount}        <cropfactor>{}</cropfactor>
+        <aspect-ratio>{:.4}</aspect-ratio>
+        <calibration>
+            <distortion model="ptlens" focal="{focal:.1}" a="{:.6}" b="{:.6}" c="{:.6}"/>
+        </calibration>
+    </lens>
+</lensdatabase>
+"#,
+            escape(&lens.maker),
+            escape(model),
+            lens.crop_factor,
+            self.width.max(self.height) as f64 / self.width.min(self.height) as f64,
+            self.a,
+            self.b,
+            self.c
+        )
+    }
+
+    /// hugin project with one rectilinear image of the lens, apply it as a template or copy
+    /// the lens parameters of the `i` line
+    pub fn pto(&self) -> String {
+        let (width, height, hfov) = (self.width, self.height, self.hfov());
+        format!(
+            "# hugin project file\n\
+             p f0 w{width} h{height} v{hfov:.4} n\"TIFF_m c:LZW\"\n\
+             m i0\n\
+             i w{width} h{height} f0 v{hfov:.4} a{:.6} b{:.6} c{:.6} d{:.2} e{:.2} g0 t0 r0 p0 y0 \
+             n\"image.jpg\"\n",
+            self.a, self.b, self.c, self.d, self.e
+        )
+    }
+}
+
+fn determinant(m: [[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+// cramer's rule, None for a singular system
+fn solve3(m: [[f64; 3]; 3], v: [f64; 3]) -> Option<[f64; 3]> {
+    let det = determinant(m);
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+    Some([0, 1, 2].map(|column| {
+        let mut replaced = m;
+        for (row, value) in replaced.iter_mut().zip(v) {
+            row[column] = value;
+        }
+        determinant(replaced) / det
+    }))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// write a lensfun profile and/or a hugin project of the calibration, for images of `size`
+/// or the calibrated size
+pub fn export(
+    calibration_file: &str,
+    size: Option<Size>,
+    lensfun: Option<&str>,
+    pto: Option<&str>,
+    lens: &LensArgs,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+    let size = match (size, calibration.image_size) {
+        (Some(size), _) => size,
+        (None, Some([width, height])) => Size::new(width, height),
+        (None, None) => {
+            return Err(format!(
+                "{calibration_file} has no image size, pass --image-width and --image-height"
+            )
+            .into());
+        }
+    };
+    let ptlens = Ptlens::fit(&calibration, size)?;
+    info!(
+        "a {:.6} b {:.6} c {:.6}, hfov {:.2} degrees",
+        ptlens.a,
+        ptlens.b,
+        ptlens.c,
+        ptlens.hfov()
+    );
+    if let Some(lensfun) = lensfun {
+        let model = lens.lens_model.clone().unwrap_or_else(|| {
+            Path::new(calibration_file)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        fs::write(lensfun, ptlens.lensfun_xml(lens, &model))?;
+        info!("lensfun profile of {model} written to {lensfun}");
+    }
+    if let Some(pto) = pto {
+        fs::write(pto, ptlens.pto())?;
+        info!("hugin project written to {pto}");
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::error::Error;
+
+use clap::Args;
+use opencv::calib3d::{
+    CALIB_FIX_ASPECT_RATIO, CALIB_FIX_K3, CALIB_FIX_PRINCIPAL_POINT, CALIB_RATIONAL_MODEL,
+    CALIB_THIN_PRISM_MODEL, CALIB_USE_INTRINSIC_GUESS, CALIB_ZERO_TANGENT_DIST, project_points_def,
+};
+use opencv::core::{
+    Point2f, Point3f, Size, TermCriteria, TermCriteria_COUNT, TermCriteria_EPS, Vector,
+};
+use opencv::prelude::*;
+use opencv::{not_opencv_branch_5, opencv_branch_5};
+
+use crate::{Calibration, view_rms};
+
+opencv_branch_5! {
+    use opencv::calib::calibrate_camera as opencv_calibrate_camera;
+}
+
+not_opencv_branch_5! {
+    use opencv::calib3d::calibrate_camera as opencv_calibrate_camera;
+}
+
+/// calibrateCamera flags of the pinhole model
+#[derive(Args, Debug, Clone, Default)]
+pub struct PinholeArgs {
+    /// k4, k5, k6 on top of k1, k2, p1, p2, k3, 8 distortion coefficients for wide lenses
+    #[arg(long)]
+    pub rational_model: bool,
+    /// s1..s4 thin prism terms, 12 distortion coefficients
+    #[arg(long)]
+    pub thin_prism: bool,
+    /// keep k3 at zero, or at the `--intrinsic-guess`
+    #[arg(long)]
+    pub fix_k3: bool,
+    /// no tangential distortion, p1 and p2 stay zero
+    #[arg(long)]
+    pub zero_tangential_dist: bool,
+    /// square pixels, or the fx/fy ratio of the `--intrinsic-guess`
+    #[arg(long)]
+    pub fix_aspect_ratio: bool,
+    /// principal point at the image center, or at the one of the `--intrinsic-guess`
+    #[arg(long)]
+    pub fix_principal_point: bool,
+    /// start from the camera matrix of this calibration file instead of estimating one, e.g.
+    /// from the lens specs or an earlier calibration
+    #[arg(long)]
+    pub intrinsic_guess: Option<String>,
+}
+
+impl PinholeArgs {
+    pub fn options(&self) -> Result<Options, Box<dyn Error>> {
+        let flags = [
+            (self.rational_model, CALIB_RATIONAL_MODEL),
+            (self.thin_prism, CALIB_THIN_PRISM_MODEL),
+            (self.fix_k3, CALIB_FIX_K3),
+            (self.zero_tangential_dist, CALIB_ZERO_TANGENT_DIST),
+            (self.fix_aspect_ratio, CALIB_FIX_ASPECT_RATIO),
+            (self.fix_principal_point, CALIB_FIX_PRINCIPAL_POINT),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag);
+        let guess = self
+            .intrinsic_guess
+            .as_deref()
+            .map(Calibration::load)
+            .transpose()?;
+        if let Some(guess) = &guess
+            && guess.model != crate::CameraModel::Pinhole
+        {
+            return Err(format!(
+                "--intrinsic-guess needs a pinhole calibration, not {:?}",
+                guess.model
+            )
+            .into());
+        }
+        Ok(Options { flags, guess })
+    }
+}
+
+/// flags and starting point of a pinhole calibration
+#[derive(Clone, Default)]
+pub struct Options {
+    pub flags: i32,
+    /// its camera matrix, scaled to the calibrated image size, is the initial estimate
+    pub guess: Option<Calibration>,
+}
+
+impl Options {
+    /// no flags and no initial estimate, opencv's defaults
+    pub fn is_default(&self) -> bool {
+        self.flags == 0 && self.guess.is_none()
+    }
+}
+
+/// single camera pinhole calibration, rms error, camera matrix, the 5, 8 or 12 distortion
+/// coefficients of the flags and the rms reprojection error of every view
+pub fn calibrate_camera(
+    objpoints: &Vector<Vector<Point3f>>,
+    imgpoints: &Vector<Vector<Point2f>>,
+    size: Size,
+    options: &Options,
+) -> opencv::Result<(f64, Mat, Mat, Vec<f64>)> {
+    let (mut mtx, flags) = match &options.guess {
+        Some(guess) => (
+            guess.scaled(size).matrices()?.0,
+            options.flags | CALIB_USE_INTRINSIC_GUESS,
+        ),
+        None => (Mat::default(), options.flags),
+    };
+    // sized by opencv for the model of the flags
+    let mut dist = Mat::default();
+    let mut rvecs = Vector::<Mat>::new();
+    let mut tvecs = Vector::<Mat>::new();
+    let rms = opencv_calibrate_camera(
+        objpoints,
+        imgpoints,
+        size,
+        &mut mtx,
+        &mut dist,
+        &mut rvecs,
+        &mut tvecs,
+        flags,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 30, f64::EPSILON)?,
+    )?;
+    let view_errors = (0..objpoints.len())
+        .map(|i| {
+            let mut projected = Vector::<Point2f>::new();
+            project_points_def(
+                &objpoints.get(i)?,
+                &rvecs.get(i)?,
+                &tvecs.get(i)?,
+                &mtx,
+                &dist,
+                &mut projected,
+            )?;
+            Ok(view_rms(&projected, &imgpoints.get(i)?))
+        })
+        .collect::<opencv::Result<Vec<f64>>>()?;
+    Ok((rms, mtx, dist, view_errors))
+}
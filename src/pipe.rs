@@ -0,0 +1,151 @@
+//! Binary frame protocol on stdin/stdout, so a caller can push many images through one
+//! long lived process.
+//!
+//! Every frame, in both directions, is
+//!
+//! | bytes | content                                                  |
+//! |-------|----------------------------------------------------------|
+//! | 4     | magic `UNDS`                                             |
+//! | 4     | payload length, big endian u32                           |
+//! | 4     | format, ascii extension zero padded (`jpg\0`, `png\0`)   |
+//! | n     | payload, the encoded image                               |
+//!
+//! Replies come back in request order with the request format. A frame that could not be
+//! corrected is answered with format `err\0` and the utf-8 error message as payload.
+//! The process exits when stdin is closed on a frame boundary.
+
+use std::error::Error;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+
+use crate::service::Undistorters;
+use log::info;
+
+const MAGIC: &[u8; 4] = b"UNDS";
+const ERROR_FORMAT: &[u8; 4] = b"err\0";
+// refuse frames a corrupted length would make us allocate
+const MAX_PAYLOAD: u32 = 512 * 1024 * 1024;
+
+struct Frame {
+    format: [u8; 4],
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn format(&self) -> String {
+        String::from_utf8_lossy(&self.format)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+}
+
+// None on a clean end of stream before a new frame
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<Frame>> {
+    let mut header = [0u8; 12];
+    match reader.read_exact(&mut header[..4]) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    reader.read_exact(&mut header[4..])?;
+    if &header[..4] != MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("bad frame magic {:?}", &header[..4]),
+        ));
+    }
+    let length = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    if length > MAX_PAYLOAD {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {length} bytes is too large"),
+        ));
+    }
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(Frame {
+        format: header[8..12].try_into().unwrap(),
+        payload,
+    }))
+}
+
+fn write_frame(writer: &mut impl Write, format: &[u8; 4], payload: &[u8]) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(format)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+pub fn run(calibration_file: &str) -> Result<(), Box<dyn Error>> {
+    let undistorters = Undistorters::load_file(calibration_file)?;
+    let id = undistorters.ids()[0].to_string();
+    let mut reader = BufReader::new(io::stdin().lock());
+    let mut writer = BufWriter::new(io::stdout().lock());
+    // stdout carries the frames, everything else goes to stderr
+    info!("reading frames from stdin");
+    let mut frames = 0;
+    while let Some(frame) = read_frame(&mut reader)? {
+        match undistorters.undistort_encoded(&id, &frame.payload, &frame.format()) {
+            Ok(corrected) => write_frame(&mut writer, &frame.format, &corrected)?,
+            Err(e) => write_frame(&mut writer, ERROR_FORMAT, e.to_string().as_bytes())?,
+        }
+        frames += 1;
+    }
+    info!("{frames} frames processed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(format: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, format, payload).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = frame(b"jpg\0", b"first");
+        bytes.extend(frame(b"png\0", b""));
+        let mut reader = &bytes[..];
+        let first = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(first.format(), "jpg");
+        assert_eq!(first.payload, b"first");
+        let second = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(second.format(), "png");
+        assert!(second.payload.is_empty());
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_header() {
+        let bytes = frame(b"jpg\0", b"payload");
+        let error = read_frame(&mut &bytes[..6]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let bytes = frame(b"jpg\0", b"payload");
+        let error = read_frame(&mut &bytes[..bytes.len() - 1]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = frame(b"jpg\0", b"payload");
+        bytes[0] = b'X';
+        let error = read_frame(&mut &bytes[..]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn payload_limit() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((MAX_PAYLOAD + 1).to_be_bytes());
+        bytes.extend(b"jpg\0");
+        let error = read_frame(&mut &bytes[..]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,260 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::panic;
+use std::thread;
+use std::time::Instant;
+
+use clap::FromArgMatches;
+use indicatif::HumanDuration;
+use log::{error, info};
+use opencv_undistort::exit;
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::{Args, config, confirm, run};
+
+/// a subcommand run for a camera, `calibrate` or `correct` with its arguments
+struct Step {
+    subcommand: &'static str,
+    args: Vec<String>,
+}
+
+impl Step {
+    fn command_line(&self) -> Vec<OsString> {
+        [env!("CARGO_PKG_NAME"), self.subcommand]
+            .into_iter()
+            .map(OsString::from)
+            .chain(self.args.iter().map(OsString::from))
+            .collect()
+    }
+
+    // the arguments with the defaults of the configuration file, like on the command line
+    fn parse(&self) -> Result<Args, Box<dyn Error>> {
+        let args = config::with_defaults(self.command_line())?;
+        let matches = config::command().try_get_matches_from(args)?;
+        Ok(Args::from_arg_matches(&matches)?)
+    }
+}
+
+struct Camera {
+    name: String,
+    steps: Vec<Step>,
+}
+
+// the result of one camera, the error of the step it stopped at
+struct Outcome {
+    name: String,
+    steps: usize,
+    seconds: f64,
+    result: Result<(), String>,
+}
+
+// keys of a step table as long options
+fn options(table: &Table, context: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args = Vec::new();
+    for (key, item) in table.iter() {
+        if key == "calibration_file" || key == "preset" {
+            return Err(format!("{context}: {key} belongs to the camera").into());
+        }
+        if global(key) {
+            return Err(
+                format!("{context}: {key} is a global option, set it on the command line").into(),
+            );
+        }
+        let value = item
+            .as_value()
+            .ok_or_else(|| format!("{context}: {key} is not an option"))?;
+        args.extend(
+            config::value_args(&key.replace('_', "-"), value)
+                .map_err(|e| format!("{context}: {e}"))?,
+        );
+    }
+    Ok(args)
+}
+
+// --threads, --yes and the log options are set up once before the first step, a step
+// would parse them and ignore them
+fn global(key: &str) -> bool {
+    let flag = key.replace('_', "-");
+    config::command()
+        .get_arguments()
+        .filter(|arg| arg.is_global_set())
+        .filter_map(|arg| arg.get_long_and_visible_aliases())
+        .any(|names| names.contains(&flag.as_str()))
+}
+
+fn camera(index: usize, table: &Table) -> Result<Camera, Box<dyn Error>> {
+    let name = table
+        .get("name")
+        .and_then(Item::as_str)
+        .map_or_else(|| format!("camera {}", index + 1), str::to_string);
+    let calibration = match (
+        table.get("calibration_file").and_then(Item::as_str),
+        table.get("preset").and_then(Item::as_str),
+    ) {
+        (Some(file), None) => format!("--calibration-file={file}"),
+        (None, Some(preset)) => format!("--preset={preset}"),
+        _ => return Err(format!("{name}: set either calibration_file or preset").into()),
+    };
+    let mut steps = Vec::new();
+    if let Some(item) = table.get("calibrate") {
+        let calibrate = item
+            .as_table()
+            .ok_or_else(|| format!("{name}: calibrate is not a table"))?;
+        if table.contains_key("preset") {
+            return Err(format!("{name}: a preset can't be calibrated").into());
+        }
+        let mut args = vec![calibration.clone()];
+        args.extend(options(calibrate, &format!("{name} calibrate"))?);
+        steps.push(Step {
+            subcommand: "calibrate",
+            args,
+        });
+    }
+    if let Some(item) = table.get("correct") {
+        let jobs = item
+            .as_array_of_tables()
+            .ok_or_else(|| format!("{name}: correct is not a list of [[camera.correct]] tables"))?;
+        for (i, job) in jobs.iter().enumerate() {
+            let mut args = vec![calibration.clone()];
+            args.extend(options(job, &format!("{name} correct {}", i + 1))?);
+            steps.push(Step {
+                subcommand: "correct",
+                args,
+            });
+        }
+    }
+    if steps.is_empty() {
+        return Err(
+            format!("{name}: nothing to do, add [camera.calibrate] or [[camera.correct]]").into(),
+        );
+    }
+    Ok(Camera { name, steps })
+}
+
+// the steps of a camera one after another, a failed step skips the rest of the camera
+fn run_camera(camera: &Camera) -> Outcome {
+    let started = Instant::now();
+    let mut result = Ok(());
+    for (i, step) in camera.steps.iter().enumerate() {
+        info!(
+            "[{}] {} ({}/{})",
+            camera.name,
+            step.subcommand,
+            i + 1,
+            camera.steps.len()
+        );
+        if let Err(e) = step.parse().and_then(|args| run(args.action)) {
+            error!("[{}] {}: {e}", camera.name, step.subcommand);
+            result = Err(format!("{} {}: {e}", step.subcommand, i + 1));
+            break;
+        }
+    }
+    Outcome {
+        name: camera.name.clone(),
+        steps: camera.steps.len(),
+        seconds: started.elapsed().as_secs_f64(),
+        result,
+    }
+}
+
+/// calibrate and correct every `[[camera]]` of the pipeline file, the cameras one after
+/// another or all at the same time with `parallel = true`. Every step is checked before the
+/// first one starts, a failing camera doesn't stop the others
+pub fn run_pipeline(path: &str) -> Result<(), Box<dyn Error>> {
+    let document = fs::read_to_string(path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("{path}: {e}"))?;
+    let parallel = match document.get("parallel") {
+        Some(item) => item
+            .as_bool()
+            .ok_or_else(|| format!("{path}: parallel is not true or false"))?,
+        None => false,
+    };
+    let mut cameras = document
+        .get("camera")
+        .and_then(Item::as_array_of_tables)
+        .ok_or_else(|| format!("{path}: no [[camera]] tables"))?
+        .iter()
+        .enumerate()
+        .map(|(i, table)| camera(i, table))
+        .collect::<Result<Vec<Camera>, Box<dyn Error>>>()
+        .map_err(|e| format!("{path}: {e}"))?;
+    if parallel {
+        // the cameras would all ask on the same terminal
+        if !confirm::assumes_yes() {
+            return Err(format!("{path}: parallel = true needs --yes").into());
+        }
+        // each camera gets its share of --threads for the steps that don't set --jobs
+        let jobs = (rayon::current_num_threads() / cameras.len()).max(1);
+        for step in cameras.iter_mut().flat_map(|camera| &mut camera.steps) {
+            if !step.args.iter().any(|arg| arg.starts_with("--jobs=")) {
+                step.args.push(format!("--jobs={jobs}"));
+            }
+        }
+    }
+    // a typo in the last step shows up now and not after hours of calibrating
+    for camera in &cameras {
+        for step in &camera.steps {
+            step.parse()
+                .map_err(|e| format!("{path}: {} {}: {e}", camera.name, step.subcommand))?;
+        }
+    }
+
+    let started = Instant::now();
+    info!(
+        "{} cameras, {} steps{}",
+        cameras.len(),
+        cameras
+            .iter()
+            .map(|camera| camera.steps.len())
+            .sum::<usize>(),
+        if parallel { " in parallel" } else { "" }
+    );
+    let outcomes = if parallel {
+        thread::scope(|scope| {
+            cameras
+                .iter()
+                .map(|camera| scope.spawn(move || run_camera(camera)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(panic::resume_unwind))
+                .collect::<Vec<Outcome>>()
+        })
+    } else {
+        cameras.iter().map(run_camera).collect()
+    };
+
+    info!("pipeline done in {}", HumanDuration(started.elapsed()));
+    let name_width = outcomes
+        .iter()
+        .map(|outcome| outcome.name.len())
+        .max()
+        .unwrap_or(0);
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok(()) => info!(
+                "  {:<name_width$}  ok      {} steps in {:.1}s",
+                outcome.name, outcome.steps, outcome.seconds
+            ),
+            Err(e) => error!("  {:<name_width$}  failed  {e}", outcome.name),
+        }
+    }
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+    match failed {
+        0 => Ok(()),
+        failed if failed == outcomes.len() => Err(exit::Failure::new(
+            exit::Code::AllFailed,
+            format!("all {failed} cameras failed"),
+        )
+        .into()),
+        failed => Err(exit::Failure::new(
+            exit::Code::PartialFailure,
+            format!("{failed} of {} cameras failed", outcomes.len()),
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,245 @@
+use std::error::Error;
+
+use glam::{DMat2, DVec2};
+use log::{info, warn};
+use opencv::core::{Point, Size, Vector};
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::provenance::Provenance;
+use crate::{Calibration, CameraModel, SCHEMA_VERSION, list_images};
+
+// edge pieces as a fraction of the image diagonal, long enough to show the bending
+const PIECE: f64 = 0.12;
+// largest rms distance of a piece from its chord, relative to its length, for a straight
+// edge bent by the lens rather than a curve in the scene
+const MAX_BEND: f64 = 0.03;
+// every n-th edge pixel of a piece is used
+const STRIDE: usize = 4;
+const ITERATIONS: usize = 30;
+const STEP: f64 = 1e-6;
+
+// normalized radial model of opencv, undistorted by fixed point iteration
+fn undistort(point: DVec2, center: DVec2, focal: f64, k: DVec2) -> DVec2 {
+    let distorted = (point - center) / focal;
+    let mut undistorted = distorted;
+    for _ in 0..20 {
+        let r2 = undistorted.length_squared();
+        undistorted = distorted / (1.0 + k.x * r2 + k.y * r2 * r2);
+    }
+    center + undistorted * focal
+}
+
+// centroid and unit normal of the least squares line through the points
+fn fit_line(points: &[DVec2]) -> (DVec2, DVec2) {
+    let centroid = points.iter().copied().sum::<DVec2>() / points.len() as f64;
+    let covariance = points
+        .iter()
+        .map(|p| *p - centroid)
+        .map(|d| DMat2::from_cols(d * d.x, d * d.y))
+        .fold(DMat2::ZERO, |a, b| a + b);
+    let (a, b, c) = (
+        covariance.x_axis.x,
+        covariance.x_axis.y,
+        covariance.y_axis.y,
+    );
+    // direction of the largest eigenvalue, the normal is perpendicular to it
+    let angle = 0.5 * (2.0 * b).atan2(a - c);
+    (centroid, DVec2::new(-angle.sin(), angle.cos()))
+}
+
+// distances from the fitted line of every undistorted piece, scaled by the piece length
+// before and after undistortion so shrinking the image does not count as straightening
+fn residuals(pieces: &[Vec<DVec2>], center: DVec2, focal: f64, k: DVec2) -> Vec<f64> {
+    let mut residuals = Vec::new();
+    for piece in pieces {
+        let undistorted = piece
+            .iter()
+            .map(|p| undistort(*p, center, focal, k))
+            .collect::<Vec<DVec2>>();
+        let scale = piece[0].distance(piece[piece.len() - 1])
+            / undistorted[0]
+                .distance(undistorted[undistorted.len() - 1])
+                .max(1e-9);
+        let (centroid, normal) = fit_line(&undistorted);
+        residuals.extend(
+            undistorted
+                .iter()
+                .map(|p| (*p - centroid).dot(normal) * scale),
+        );
+    }
+    residuals
+}
+
+fn cost(residuals: &[f64]) -> f64 {
+    residuals.iter().map(|r| r * r).sum()
+}
+
+// roughly straight pieces of the long edges of an image
+fn edge_pieces(img: &Mat) -> opencv::Result<Vec<Vec<DVec2>>> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(img, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    let mut blurred = Mat::default();
+    imgproc::gaussian_blur_def(&gray, &mut blurred, Size::new(5, 5), 1.5)?;
+    let mut edges = Mat::default();
+    imgproc::canny(&blurred, &mut edges, 50.0, 150.0, 3, true)?;
+    let mut contours = Vector::<Vector<Point>>::new();
+    imgproc::find_contours_def(
+        &edges,
+        &mut contours,
+        imgproc::RETR_LIST,
+        imgproc::CHAIN_APPROX_NONE,
+    )?;
+    let length = (PIECE * (img.cols() as f64).hypot(img.rows() as f64)) as usize;
+    let mut pieces = Vec::new();
+    for contour in contours.iter() {
+        let points = contour
+            .iter()
+            .map(|p| DVec2::new(p.x as f64, p.y as f64))
+            .collect::<Vec<DVec2>>();
+        for chunk in points.chunks_exact(length.max(STRIDE * 8)) {
+            let piece = chunk
+                .iter()
+                .step_by(STRIDE)
+                .copied()
+                .collect::<Vec<DVec2>>();
+            let chord = piece[0].distance(piece[piece.len() - 1]);
+            // an edge traced forth and back ends where it started
+            if chord < 0.5 * length as f64 {
+                continue;
+            }
+            let (centroid, normal) = fit_line(&piece);
+            let rms = (piece
+                .iter()
+                .map(|p| (*p - centroid).dot(normal).powi(2))
+                .sum::<f64>()
+                / piece.len() as f64)
+                .sqrt();
+            if rms < MAX_BEND * chord {
+                pieces.push(piece);
+            }
+        }
+    }
+    Ok(pieces)
+}
+
+// gauss-newton on k1, k2 with a numeric jacobian, halving steps that increase the cost
+fn solve(pieces: &[Vec<DVec2>], center: DVec2, focal: f64) -> DVec2 {
+    let mut k = DVec2::ZERO;
+    let mut r = residuals(pieces, center, focal, k);
+    for _ in 0..ITERATIONS {
+        let r1 = residuals(pieces, center, focal, k + DVec2::new(STEP, 0.0));
+        let r2 = residuals(pieces, center, focal, k + DVec2::new(0.0, STEP));
+        let mut jtj = DMat2::ZERO;
+        let mut jtr = DVec2::ZERO;
+        for i in 0..r.len() {
+            let j = DVec2::new((r1[i] - r[i]) / STEP, (r2[i] - r[i]) / STEP);
+            jtj += DMat2::from_cols(j * j.x, j * j.y);
+            jtr += j * r[i];
+        }
+        let mut step = jtj.inverse() * jtr;
+        if !step.is_finite() {
+            break;
+        }
+        let mut improved = false;
+        for _ in 0..10 {
+            let candidate = residuals(pieces, center, focal, k - step);
+            if cost(&candidate) < cost(&r) {
+                k -= step;
+                r = candidate;
+                improved = true;
+                break;
+            }
+            step /= 2.0;
+        }
+        if !improved || step.length() < 1e-10 {
+            break;
+        }
+    }
+    k
+}
+
+/// approximate radial distortion from straight edges in ordinary photos of one camera, for
+/// footage without a calibration board. The principal point is the image center and the
+/// focal length is assumed, only k1 and k2 are estimated
+pub fn calibrate(
+    image_dir: &str,
+    calibration_file: &str,
+    focal_px: Option<f64>,
+) -> Result<(), Box<dyn Error>> {
+    let images = list_images(image_dir)?;
+    let mut pieces = Vec::new();
+    let mut size = None;
+    for image in &images {
+        let img = opencv::imgcodecs::imread_def(image)?;
+        if img.empty() {
+            warn!("{image}: can not read image");
+            continue;
+        }
+        if size.is_some_and(|size| size != img.size().unwrap_or_default()) {
+            warn!("{image}: different image size, skipped");
+            continue;
+        }
+        size = Some(img.size()?);
+        let found = edge_pieces(&img)?;
+        info!("{image}: {} straight edge pieces", found.len());
+        pieces.extend(found);
+    }
+    let Some(size) = size else {
+        return Err(format!("no images in {image_dir}").into());
+    };
+    if pieces.is_empty() {
+        return Err(
+            "no long straight edges found, use photos of buildings, door frames or horizons".into(),
+        );
+    }
+    let center = DVec2::new(size.width as f64 / 2.0, size.height as f64 / 2.0);
+    // about a 60 degree horizontal field of view unless told otherwise
+    let focal = focal_px.unwrap_or(size.width.max(size.height) as f64 * 0.87);
+
+    let k = solve(&pieces, center, focal);
+    // edges that are curved in the scene stay curved, drop the worst and solve again
+    let bends = pieces
+        .iter()
+        .map(|piece| {
+            cost(&residuals(std::slice::from_ref(piece), center, focal, k)) / piece.len() as f64
+        })
+        .collect::<Vec<f64>>();
+    let mut sorted = bends.clone();
+    sorted.sort_by(f64::total_cmp);
+    let limit = 9.0 * sorted[sorted.len() / 2];
+    let pieces = pieces
+        .into_iter()
+        .zip(bends)
+        .filter(|(_, bend)| *bend <= limit)
+        .map(|(piece, _)| piece)
+        .collect::<Vec<Vec<DVec2>>>();
+    let k = solve(&pieces, center, focal);
+    let rms = (cost(&residuals(&pieces, center, focal, k))
+        / pieces.iter().map(Vec::len).sum::<usize>().max(1) as f64)
+        .sqrt();
+    info!(
+        "plumb-line: {} edge pieces, k1 {:.5}, k2 {:.5}, straightness rms {rms:.3}px",
+        pieces.len(),
+        k.x,
+        k.y
+    );
+    warn!("plumb-line calibrations are approximate, the focal length {focal:.0}px is assumed");
+
+    let calibration = Calibration {
+        version: SCHEMA_VERSION,
+        model: CameraModel::Pinhole,
+        camera_matrix: vec![focal, 0.0, center.x, 0.0, focal, center.y, 0.0, 0.0, 1.0],
+        dist_coeffs: vec![k.x, k.y, 0.0, 0.0, 0.0],
+        image_size: Some([size.width, size.height]),
+        board: None,
+        omnidir: None,
+        rms: None,
+        new_camera_matrix: None,
+        roi: None,
+        provenance: Some(Provenance::new()),
+    };
+    calibration.save(calibration_file, None)?;
+    info!("store to file {calibration_file}");
+    Ok(())
+}
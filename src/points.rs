@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+
+use opencv::core::{Point2f, Size, Vector};
+use serde_json::Value;
+
+use crate::{Calibration, CameraModel};
+
+// a csv of points, the other columns are kept as they are
+struct Table {
+    header: Option<Vec<String>>,
+    rows: Vec<Vec<String>>,
+    x: usize,
+    y: usize,
+}
+
+impl Table {
+    // x and y are the columns named so in the header, otherwise the first two
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut rows = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split(',')
+                    .map(|cell| cell.trim().to_string())
+                    .collect()
+            })
+            .collect::<Vec<Vec<String>>>();
+        let numeric = |row: &[String]| row.iter().take(2).all(|cell| cell.parse::<f64>().is_ok());
+        let header = match rows.first() {
+            Some(first) if !numeric(first) => Some(rows.remove(0)),
+            _ => None,
+        };
+        let column = |name: &str, default: usize| {
+            header
+                .as_ref()
+                .and_then(|header| {
+                    header
+                        .iter()
+                        .position(|cell| cell.eq_ignore_ascii_case(name))
+                })
+                .unwrap_or(default)
+        };
+        let (x, y) = (column("x", 0), column("y", 1));
+        Ok(Table { header, rows, x, y })
+    }
+
+    fn points(&self) -> Result<Vec<Point2f>, Box<dyn Error>> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let cell = |column: usize| {
+                    row.get(column)
+                        .and_then(|cell| cell.parse::<f32>().ok())
+                        .ok_or_else(|| format!("row {}: no number in column {}", i + 1, column + 1))
+                };
+                Ok(Point2f::new(cell(self.x)?, cell(self.y)?))
+            })
+            .collect()
+    }
+
+    fn write(mut self, points: &[[String; 2]]) -> String {
+        let mut lines = self
+            .header
+            .take()
+            .map(|header| header.join(","))
+            .into_iter()
+            .collect::<Vec<String>>();
+        for (row, [x, y]) in self.rows.iter_mut().zip(points) {
+            row[self.x] = x.clone();
+            row[self.y] = y.clone();
+            lines.push(row.join(","));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+// [x, y, ...] arrays or objects with x and y of a json array
+fn json_points(value: &Value) -> Result<Vec<Point2f>, Box<dyn Error>> {
+    let items = value
+        .as_array()
+        .ok_or("a json array of points is expected")?;
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let (x, y) = match item {
+                Value::Array(values) => (values.first(), values.get(1)),
+                Value::Object(object) => (object.get("x"), object.get("y")),
+                _ => (None, None),
+            };
+            match (x.and_then(Value::as_f64), y.and_then(Value::as_f64)) {
+                (Some(x), Some(y)) => Ok(Point2f::new(x as f32, y as f32)),
+                _ => Err(format!("point {i}: no x and y").into()),
+            }
+        })
+        .collect()
+}
+
+fn set_json_points(value: &mut Value, points: &[Point2f]) {
+    let Some(items) = value.as_array_mut() else {
+        return;
+    };
+    for (item, point) in items.iter_mut().zip(points) {
+        match item {
+            Value::Array(values) => {
+                values[0] = (point.x as f64).into();
+                values[1] = (point.y as f64).into();
+            }
+            Value::Object(object) => {
+                object.insert("x".to_string(), (point.x as f64).into());
+                object.insert("y".to_string(), (point.y as f64).into());
+            }
+            _ => {}
+        }
+    }
+}
+
+// camera matrix of normalized coordinates, the one of the undistorted image
+fn normalizing_matrix(calibration: &Calibration) -> Result<&[f64], Box<dyn Error>> {
+    match calibration.model {
+        CameraModel::Pinhole | CameraModel::Fisheye => Ok(calibration
+            .new_camera_matrix
+            .as_deref()
+            .unwrap_or(&calibration.camera_matrix)),
+        model => Err(format!(
+            "normalized coordinates need a pinhole or fisheye calibration, not {model:?}"
+        )
+        .into()),
+    }
+}
+
+/// undistorted pixels of distorted ones in an image of the given size, or with `inverse` the
+/// distorted pixels of undistorted ones. `normalized` points are in normalized image
+/// coordinates on the undistorted side instead of pixels
+pub fn map_points(
+    calibration: &Calibration,
+    points: &[Point2f],
+    size: Size,
+    inverse: bool,
+    normalized: bool,
+) -> Result<Vec<Point2f>, Box<dyn Error>> {
+    let scaled = calibration.scaled(size);
+    let k = if normalized {
+        Some(normalizing_matrix(&scaled)?.to_vec())
+    } else {
+        None
+    };
+    let input = Vector::<Point2f>::from_iter(points.iter().map(|point| match (&k, inverse) {
+        (Some(k), true) => Point2f::new(
+            (point.x as f64 * k[0] + k[2]) as f32,
+            (point.y as f64 * k[4] + k[5]) as f32,
+        ),
+        _ => *point,
+    }));
+    let mut output = Vector::<Point2f>::new();
+    if inverse {
+        calibration.distort_points(&input, &mut output, size)?;
+    } else {
+        calibration.undistort_points(&input, &mut output, size)?;
+    }
+    Ok(output
+        .iter()
+        .map(|point| match (&k, inverse) {
+            (Some(k), false) => Point2f::new(
+                ((point.x as f64 - k[2]) / k[0]) as f32,
+                ((point.y as f64 - k[5]) / k[4]) as f32,
+            ),
+            _ => point,
+        })
+        .collect())
+}
+
+/// map the points of a csv or json file, `-` reads stdin, to `output` or stdout in the same
+/// format
+pub fn correct(
+    calibration: &Calibration,
+    input: &str,
+    output: Option<&str>,
+    size: Size,
+    inverse: bool,
+    normalized: bool,
+) -> Result<(), Box<dyn Error>> {
+    let text = if input == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(input)?
+    };
+    let precision = if normalized { 6 } else { 3 };
+    let result = if text.trim_start().starts_with('[') {
+        let mut value = serde_json::from_str::<Value>(&text)?;
+        let points = map_points(
+            calibration,
+            &json_points(&value)?,
+            size,
+            inverse,
+            normalized,
+        )?;
+        set_json_points(&mut value, &points);
+        serde_json::to_string(&value)? + "\n"
+    } else {
+        let table = Table::parse(&text)?;
+        let points = map_points(calibration, &table.points()?, size, inverse, normalized)?;
+        let cells = points
+            .iter()
+            .map(|point| {
+                [
+                    format!("{:.precision$}", point.x),
+                    format!("{:.precision$}", point.y),
+                ]
+            })
+            .collect::<Vec<[String; 2]>>();
+        table.write(&cells)
+    };
+    match output {
+        Some(output) => fs::write(output, result)?,
+        None => print!("{result}"),
+    }
+    Ok(())
+}
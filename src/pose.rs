@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+use opencv::calib3d::{project_points_def, rodrigues_def, solve_pnp_def};
+use opencv::core::{Point, Point2f, Point3f, Scalar, Size, Vector, no_array};
+use opencv::imgcodecs;
+use opencv::imgproc::{self, FILLED, LINE_AA};
+use opencv::prelude::*;
+use serde::Serialize;
+
+use crate::board::Board;
+use crate::{Calibration, CameraModel, mat_to_vec};
+
+// axes drawn on the overlay, in squares of the board
+const AXIS_SQUARES: f32 = 3.0;
+
+/// board pose in one image, printed as a json line
+#[derive(Serialize)]
+struct Pose<'a> {
+    image: &'a str,
+    /// board pose in the camera frame, rodrigues rotation and translation in the units of
+    /// the square size
+    rvec: Vec<f64>,
+    tvec: Vec<f64>,
+    /// camera center in the board frame
+    camera_position: Vec<f64>,
+    /// rms distance in pixels of the reprojected board points to the detected ones, in the
+    /// undistorted image
+    rms: f64,
+}
+
+fn pixel(point: Point2f) -> Point {
+    Point::new(point.x.round() as i32, point.y.round() as i32)
+}
+
+/// points on the board projected into the image, through the lens unless it is corrected
+pub fn project(
+    calibration: &Calibration,
+    points: &Vector<Point3f>,
+    (rvec, tvec): (&Mat, &Mat),
+    size: Size,
+    corrected: bool,
+) -> opencv::Result<Vector<Point2f>> {
+    let mut projected = Vector::<Point2f>::new();
+    project_points_def(
+        points,
+        rvec,
+        tvec,
+        &calibration.new_matrix()?,
+        &no_array(),
+        &mut projected,
+    )?;
+    if corrected {
+        return Ok(projected);
+    }
+    let mut distorted = Vector::<Point2f>::new();
+    calibration.distort_points(&projected, &mut distorted, size)?;
+    Ok(distorted)
+}
+
+// detected corners as rings, reprojected ones as dots and the board axes x red, y green and z
+// blue towards the camera
+fn overlay(
+    path: &str,
+    image: &str,
+    detected: &Vector<Point2f>,
+    reprojected: &Vector<Point2f>,
+    axes: &Vector<Point2f>,
+) -> opencv::Result<()> {
+    let mut img = imgcodecs::imread_def(image)?;
+    let radius = (img.cols().max(img.rows()) / 400).max(3);
+    for point in detected {
+        let green = Scalar::new(0.0, 255.0, 0.0, 0.0);
+        imgproc::circle(&mut img, pixel(point), 2 * radius, green, 2, LINE_AA, 0)?;
+    }
+    for point in reprojected {
+        let red = Scalar::new(0.0, 0.0, 255.0, 0.0);
+        imgproc::circle(&mut img, pixel(point), radius / 2, red, FILLED, LINE_AA, 0)?;
+    }
+    let origin = pixel(axes.get(0)?);
+    for (i, color) in [(0.0, 0.0, 255.0), (0.0, 255.0, 0.0), (255.0, 0.0, 0.0)]
+        .into_iter()
+        .enumerate()
+    {
+        let color = Scalar::new(color.0, color.1, color.2, 0.0);
+        let end = pixel(axes.get(i + 1)?);
+        imgproc::line(&mut img, origin, end, color, radius, LINE_AA, 0)?;
+    }
+    if !imgcodecs::imwrite_def(path, &img)? {
+        warn!("could not write {path}");
+    }
+    Ok(())
+}
+
+/// pose of the calibration board in every image it is found in. `corrected` images were
+/// written by `correct` without cropping and have no distortion left, otherwise the corners
+/// are undistorted first. With an `overlay_dir` each image is written there with the detected
+/// and reprojected corners and the board axes drawn
+pub fn poses(
+    calibration: &Calibration,
+    board: &Board,
+    images: &[String],
+    corrected: bool,
+    overlay_dir: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if !matches!(
+        calibration.model,
+        CameraModel::Pinhole | CameraModel::Fisheye
+    ) {
+        return Err(format!(
+            "board poses need a pinhole or fisheye calibration, not {:?}",
+            calibration.model
+        )
+        .into());
+    }
+    if let Some(overlay_dir) = overlay_dir {
+        fs::create_dir_all(overlay_dir)?;
+    }
+    let objp = board.object_points();
+    let length = AXIS_SQUARES * board.square_size_mm.unwrap_or(1.0);
+    let axes = Vector::<Point3f>::from_iter([
+        Point3f::new(0.0, 0.0, 0.0),
+        Point3f::new(length, 0.0, 0.0),
+        Point3f::new(0.0, length, 0.0),
+        Point3f::new(0.0, 0.0, -length),
+    ]);
+    let mut found = 0;
+    for image in images {
+        let img = board.read_image(image)?;
+        let size = img.size()?;
+        let Some(corners) = board.detect(&img)? else {
+            warn!("{image}: board not found");
+            continue;
+        };
+        let calibration = calibration.scaled(size);
+        let undistorted = if corrected {
+            corners.clone()
+        } else {
+            let mut undistorted = Vector::<Point2f>::new();
+            calibration.undistort_points(&corners, &mut undistorted, size)?;
+            undistorted
+        };
+        let mtx = calibration.new_matrix()?;
+        let mut rvec = Mat::default();
+        let mut tvec = Mat::default();
+        if !solve_pnp_def(&objp, &undistorted, &mtx, &no_array(), &mut rvec, &mut tvec)? {
+            warn!("{image}: no board pose");
+            continue;
+        }
+        let mut reprojected = Vector::<Point2f>::new();
+        project_points_def(&objp, &rvec, &tvec, &mtx, &no_array(), &mut reprojected)?;
+        let squared = undistorted
+            .iter()
+            .zip(reprojected.iter())
+            .map(|(a, b)| ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)) as f64)
+            .sum::<f64>();
+        let mut rotation = Mat::default();
+        rodrigues_def(&rvec, &mut rotation)?;
+        let (r, t) = (mat_to_vec(&rotation)?, mat_to_vec(&tvec)?);
+        // -R^T t
+        let camera_position = (0..3)
+            .map(|i| -(0..3).map(|j| r[j * 3 + i] * t[j]).sum::<f64>())
+            .collect();
+
+        if let Some(overlay_dir) = overlay_dir {
+            let stem = Path::new(image)
+                .file_stem()
+                .map_or("image".into(), |stem| stem.to_string_lossy());
+            overlay(
+                &format!("{overlay_dir}/pose_{stem}.jpg"),
+                image,
+                &corners,
+                &project(&calibration, &objp, (&rvec, &tvec), size, corrected)?,
+                &project(&calibration, &axes, (&rvec, &tvec), size, corrected)?,
+            )?;
+        }
+        let pose = Pose {
+            image,
+            rvec: mat_to_vec(&rvec)?,
+            tvec: t,
+            camera_position,
+            rms: (squared / objp.len() as f64).sqrt(),
+        };
+        println!("{}", serde_json::to_string(&pose)?);
+        found += 1;
+    }
+    info!("board pose in {found} of {} images", images.len());
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::error::Error;
+
+use serde::Deserialize;
+
+use crate::Calibration;
+
+// focal lengths from the lens and sensor specs, distortion from typical calibrations of
+// these cameras. Good enough to correct footage, calibrate when accuracy matters
+const PRESETS: &str = include_str!("../presets/presets.json");
+
+#[derive(Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub description: String,
+    #[serde(flatten)]
+    pub calibration: Calibration,
+}
+
+pub fn presets() -> Vec<Preset> {
+    serde_json::from_str(PRESETS).expect("presets.json is valid")
+}
+
+pub fn find(name: &str) -> Result<Calibration, Box<dyn Error>> {
+    let presets = presets();
+    let names = presets
+        .iter()
+        .map(|preset| preset.name.clone())
+        .collect::<Vec<String>>();
+    presets
+        .into_iter()
+        .find(|preset| preset.name == name)
+        .map(|preset| preset.calibration)
+        .ok_or_else(|| format!("unknown preset {name}, available: {}", names.join(", ")).into())
+}
+
+pub fn list() {
+    for preset in presets() {
+        let [width, height] = preset.calibration.image_size.unwrap_or_default();
+        println!(
+            "{:<24} {width}x{height}  {}",
+            preset.name, preset.description
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::LazyLock;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+// progress bars share one draw target so log lines are printed above them instead of
+// tearing through
+static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// run `f`, e.g. printing a log line, with the progress bars cleared and drawn again after
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    PROGRESS.suspend(f)
+}
+
+/// draw no progress bars, for quiet or machine readable output
+pub fn hide() {
+    PROGRESS.set_draw_target(ProgressDrawTarget::hidden());
+}
+
+/// progress bar with a known total, position and eta
+pub fn progress_bar(len: u64) -> ProgressBar {
+    let style = ProgressStyle::with_template("{wide_bar} {pos}/{len} eta {eta} {msg}")
+        .expect("valid progress template");
+    PROGRESS.add(ProgressBar::new(len).with_style(style))
+}
+
+pub fn spinner() -> ProgressBar {
+    PROGRESS.add(ProgressBar::new_spinner())
+}
+
+/// one status line per worker of a pool below the progress bar of the batch
+pub struct WorkerLines {
+    lines: Vec<ProgressBar>,
+}
+
+impl WorkerLines {
+    pub fn new(workers: usize) -> Self {
+        let style =
+            ProgressStyle::with_template("  {spinner} {msg}").expect("valid progress template");
+        WorkerLines {
+            lines: (0..workers)
+                .map(|_| PROGRESS.add(ProgressBar::new_spinner().with_style(style.clone())))
+                .collect(),
+        }
+    }
+
+    /// status of the calling worker thread of the pool
+    pub fn set(&self, message: String) {
+        if let Some(line) = rayon::current_thread_index().and_then(|i| self.lines.get(i)) {
+            line.set_message(message);
+            line.tick();
+        }
+    }
+
+    pub fn finish(&self) {
+        self.lines.iter().for_each(ProgressBar::finish_and_clear);
+    }
+}
@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::fs;
+use std::time::Instant;
+
+use clap::Args;
+use indicatif::HumanDuration;
+use log::{info, warn};
+use opencv::calib3d::{
+    CALIB_FIX_INTRINSIC, calibrate_camera_def, find_homography_def, stereo_calibrate,
+};
+use opencv::core::{
+    Point, Point2f, Point3f, Size, TermCriteria, TermCriteria_COUNT, TermCriteria_EPS, Vector,
+    perspective_transform,
+};
+use opencv::imgcodecs::{self, IMREAD_GRAYSCALE, imwrite_def};
+use opencv::prelude::*;
+use opencv::structured_light::{GrayCodePattern, GrayCodePatternTraitConst};
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::exit::{Code, Failure};
+use crate::provenance::Provenance;
+use crate::{
+    Calibration, CameraModel, IMAGE_EXTENSIONS, SCHEMA_VERSION, list_files, mat_to_vec, order,
+    progress,
+};
+
+// half size of the camera window around a board corner whose decoded projector pixels give
+// the local camera to projector homography
+const WINDOW: i32 = 20;
+// fewest decoded pixels in the window for a usable corner
+const MIN_DECODED: usize = 50;
+// smallest white minus black brightness of a pixel lit by the projector
+const MIN_CONTRAST: i32 = 40;
+// smallest brightness difference between a pattern and its inverse
+const WHITE_THRESHOLD: usize = 5;
+
+#[derive(Args, Debug)]
+pub struct ProjectorArgs {
+    /// projector resolution
+    #[arg(long)]
+    projector_width: i32,
+    #[arg(long)]
+    projector_height: i32,
+}
+
+impl ProjectorArgs {
+    fn pattern(&self) -> opencv::Result<opencv::core::Ptr<GrayCodePattern>> {
+        let mut pattern = GrayCodePattern::create_1(self.projector_width, self.projector_height)?;
+        pattern.set_white_threshold(WHITE_THRESHOLD)?;
+        Ok(pattern)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectorCalibration {
+    camera: Calibration,
+    /// the projector as an inverse camera, at the projector resolution
+    projector: Calibration,
+    // rotation and translation of the projector relative to the camera
+    r: Vec<f64>,
+    t: Vec<f64>,
+    rms: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+}
+
+/// write the gray code patterns followed by an all white and an all black image, to be
+/// projected and captured in this order for every board pose
+pub fn patterns(projector: &ProjectorArgs, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    let mut pattern = projector.pattern()?;
+    let mut images = Vector::<Mat>::new();
+    pattern.generate(&mut images)?;
+    let mut white = Mat::default();
+    let mut black = Mat::default();
+    pattern.get_images_for_shadow_masks(&mut black, &mut white)?;
+    images.push(white);
+    images.push(black);
+    fs::create_dir_all(output_dir)?;
+    for (i, image) in images.iter().enumerate() {
+        imwrite_def(&format!("{output_dir}/{i:02}.png"), &image)?;
+    }
+    info!("{} projector images in {output_dir}", images.len());
+    Ok(())
+}
+
+// subdirectories in `--sort` order
+fn pose_dirs(dir: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut dirs = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    order::sort(&mut dirs);
+    Ok(dirs)
+}
+
+// projector pixel of a board corner through the local homography of the decoded pixels around
+// it, which keeps the sub-pixel corner accuracy
+fn projector_point(
+    pattern: &impl GrayCodePatternTraitConst,
+    captures: &Vector<Mat>,
+    white: &Mat,
+    black: &Mat,
+    corner: Point2f,
+) -> opencv::Result<Option<Point2f>> {
+    let mut camera = Vector::<Point2f>::new();
+    let mut projector = Vector::<Point2f>::new();
+    let (cx, cy) = (corner.x.round() as i32, corner.y.round() as i32);
+    for y in (cy - WINDOW).max(0)..=(cy + WINDOW).min(white.rows() - 1) {
+        for x in (cx - WINDOW).max(0)..=(cx + WINDOW).min(white.cols() - 1) {
+            let contrast = *white.at_2d::<u8>(y, x)? as i32 - *black.at_2d::<u8>(y, x)? as i32;
+            if contrast < MIN_CONTRAST {
+                continue;
+            }
+            let mut decoded = Point::default();
+            // true when the pixel could not be decoded
+            if pattern.get_proj_pixel(captures, x, y, &mut decoded)? {
+                continue;
+            }
+            camera.push(Point2f::new(x as f32, y as f32));
+            projector.push(Point2f::new(decoded.x as f32, decoded.y as f32));
+        }
+    }
+    if camera.len() < MIN_DECODED {
+        return Ok(None);
+    }
+    let homography = find_homography_def(&camera, &projector, &mut Mat::default())?;
+    if homography.empty() {
+        return Ok(None);
+    }
+    let mut projected = Vector::<Point2f>::new();
+    perspective_transform(
+        &Vector::<Point2f>::from_iter([corner]),
+        &mut projected,
+        &homography,
+    )?;
+    Ok(Some(projected.get(0)?))
+}
+
+/// calibrate the camera from the white captures and the projector from the board corners
+/// decoded into projector pixels, then the camera to projector pose. Every subdirectory of
+/// `captures_dir` holds one board pose captured under the images of `patterns`, in order
+pub fn calibrate(
+    captures_dir: &str,
+    calibration_file: &str,
+    projector: &ProjectorArgs,
+    board: &Board,
+) -> Result<(), Box<dyn Error>> {
+    let pattern = projector.pattern()?;
+    let patterns = pattern.get_number_of_pattern_images()?;
+    let objp = board.object_points();
+    let mut objpoints = Vector::<Vector<Point3f>>::new();
+    let mut camera_points = Vector::<Vector<Point2f>>::new();
+    let mut projector_points = Vector::<Vector<Point2f>>::new();
+    let mut image_size = Size::default();
+
+    let poses = pose_dirs(captures_dir)?;
+    let pb = progress::progress_bar(poses.len() as u64);
+    info!("[1/3] decode board poses");
+    let started = Instant::now();
+    for pose in &poses {
+        pb.inc(1);
+        let images = list_files(pose, &IMAGE_EXTENSIONS)?;
+        if images.len() != patterns + 2 {
+            warn!(
+                "{pose}: {} captures, expected {} patterns and a white and a black image",
+                images.len(),
+                patterns
+            );
+            continue;
+        }
+        let captures = images
+            .iter()
+            .map(|image| imgcodecs::imread(image, IMREAD_GRAYSCALE))
+            .collect::<opencv::Result<Vector<Mat>>>()?;
+        let (white, black) = (captures.get(patterns)?, captures.get(patterns + 1)?);
+        image_size = white.size()?;
+        let Some(corners) = board.detect(&board.read_image(&images[patterns])?)? else {
+            warn!("{pose}: board not found in the white capture");
+            continue;
+        };
+        let pattern_captures = Vector::<Mat>::from_iter(captures.iter().take(patterns));
+        let mut decoded = Vector::<Point2f>::new();
+        for corner in corners.iter() {
+            match projector_point(&pattern, &pattern_captures, &white, &black, corner)? {
+                Some(point) => decoded.push(point),
+                None => break,
+            }
+        }
+        if decoded.len() != corners.len() {
+            warn!("{pose}: board corners outside the projected area or in shadow");
+            continue;
+        }
+        objpoints.push(objp.clone());
+        camera_points.push(corners);
+        projector_points.push(decoded);
+        pb.set_message(format!(
+            "{pose} processed. in progress for {}",
+            HumanDuration(started.elapsed())
+        ));
+    }
+    pb.finish_and_clear();
+    if objpoints.is_empty() {
+        return Err(Failure::new(Code::NoBoards, "no board pose could be decoded").into());
+    }
+
+    info!("[2/3] compute camera, projector and camera-projector calibration");
+    let projector_size = Size::new(projector.projector_width, projector.projector_height);
+    let mut camera_mtx = Mat::default();
+    let mut camera_dist = Mat::default();
+    let camera_rms = calibrate_camera_def(
+        &objpoints,
+        &camera_points,
+        image_size,
+        &mut camera_mtx,
+        &mut camera_dist,
+        &mut Vector::<Mat>::new(),
+        &mut Vector::<Mat>::new(),
+    )?;
+    let mut projector_mtx = Mat::default();
+    let mut projector_dist = Mat::default();
+    let projector_rms = calibrate_camera_def(
+        &objpoints,
+        &projector_points,
+        projector_size,
+        &mut projector_mtx,
+        &mut projector_dist,
+        &mut Vector::<Mat>::new(),
+        &mut Vector::<Mat>::new(),
+    )?;
+    info!("camera rms {camera_rms:.4}, projector rms {projector_rms:.4}");
+    let mut r = Mat::default();
+    let mut t = Mat::default();
+    let rms = stereo_calibrate(
+        &objpoints,
+        &camera_points,
+        &projector_points,
+        &mut camera_mtx,
+        &mut camera_dist,
+        &mut projector_mtx,
+        &mut projector_dist,
+        image_size,
+        &mut r,
+        &mut t,
+        &mut Mat::default(),
+        &mut Mat::default(),
+        CALIB_FIX_INTRINSIC,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 100, 1e-6)?,
+    )?;
+
+    let calibration = ProjectorCalibration {
+        camera: Calibration {
+            version: SCHEMA_VERSION,
+            model: CameraModel::Pinhole,
+            camera_matrix: mat_to_vec(&camera_mtx)?,
+            dist_coeffs: mat_to_vec(&camera_dist)?,
+            image_size: Some([image_size.width, image_size.height]),
+            board: Some(board.geometry()),
+            omnidir: None,
+            rms: None,
+            new_camera_matrix: None,
+            roi: None,
+            provenance: None,
+        },
+        projector: Calibration {
+            version: SCHEMA_VERSION,
+            model: CameraModel::Pinhole,
+            camera_matrix: mat_to_vec(&projector_mtx)?,
+            dist_coeffs: mat_to_vec(&projector_dist)?,
+            image_size: Some([projector_size.width, projector_size.height]),
+            board: Some(board.geometry()),
+            omnidir: None,
+            rms: None,
+            new_camera_matrix: None,
+            roi: None,
+            provenance: None,
+        },
+        r: mat_to_vec(&r)?,
+        t: mat_to_vec(&t)?,
+        rms,
+        provenance: Some(Provenance::new()),
+    };
+    info!(
+        "[3/3] store to file {calibration_file}, {} poses, rms {rms:.4}",
+        objpoints.len()
+    );
+    fs::write(calibration_file, serde_json::to_string(&calibration)?)?;
+    info!("done in {}", HumanDuration(started.elapsed()));
+    Ok(())
+}
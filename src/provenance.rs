@@ -0,0 +1,79 @@
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Calibration;
+
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// unix seconds and a random suffix, sortable by start time and unique across machines
+static RUN_ID: LazyLock<String> = LazyLock::new(|| {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("{started:x}-{:08x}", rand::random::<u32>())
+});
+
+/// id of this invocation, in logs, manifests, calibration files and image comments
+pub fn run_id() -> &'static str {
+    &RUN_ID
+}
+
+/// where a calibration file came from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Provenance {
+    run_id: String,
+    tool_version: String,
+    /// unix time the file was written
+    created: f64,
+    /// command line of the run
+    arguments: Vec<String>,
+}
+
+impl Provenance {
+    pub fn new() -> Self {
+        Provenance {
+            run_id: run_id().to_string(),
+            tool_version: TOOL_VERSION.to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            arguments: std::env::args().collect(),
+        }
+    }
+}
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// crc32 of the calibration as it is stored, hex
+pub fn calibration_checksum(calibration: &Calibration) -> String {
+    let content = serde_json::to_vec(calibration).unwrap_or_default();
+    format!("{:08x}", crc32fast::hash(&content))
+}
+
+/// add a COM segment after the SOI marker naming the run and calibration that corrected the
+/// image, data that is not a jpeg is left as it is
+pub fn tag_jpeg(data: &mut Vec<u8>, calibration: &Calibration) {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return;
+    }
+    let comment = serde_json::json!({
+        "run_id": run_id(),
+        "tool_version": TOOL_VERSION,
+        "calibration_crc32": calibration_checksum(calibration),
+    })
+    .to_string();
+    // the segment length counts its own two bytes
+    let length = (comment.len() + 2) as u16;
+    let mut segment = vec![0xff, 0xfe];
+    segment.extend(length.to_be_bytes());
+    segment.extend(comment.as_bytes());
+    data.splice(2..2, segment);
+}
@@ -0,0 +1,200 @@
+use std::error::Error;
+
+use log::{info, warn};
+use opencv::calib3d::find_homography_def;
+use opencv::core::{BORDER_CONSTANT, Point2f, Scalar, Size, Vector, perspective_transform};
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::board::Board;
+use crate::{Calibration, CameraModel, Interpolation, Undistorter};
+
+// longest side of an output whose size is picked from the image, a plane seen up to the
+// horizon would not fit into memory
+const MAX_SIDE: f64 = 16384.0;
+
+/// what spans the plane in the image
+#[derive(Clone, Copy)]
+pub enum Reference {
+    /// the calibration board lying on the plane, its square size sets the scale
+    Board(Board),
+    /// four image points, clockwise from the top left, of a rectangle of this size in mm
+    Points {
+        points: [Point2f; 4],
+        width_mm: f32,
+        height_mm: f32,
+    },
+}
+
+impl Reference {
+    // the reference in the captured image and on the plane in mm, None when the board is not
+    // visible
+    fn find(&self, img: &Mat) -> opencv::Result<Option<(Vector<Point2f>, Vector<Point2f>)>> {
+        Ok(match self {
+            Reference::Board(board) => board.detect(img)?.map(|corners| {
+                let plane = board
+                    .object_points()
+                    .iter()
+                    .map(|point| Point2f::new(point.x, point.y))
+                    .collect::<Vector<Point2f>>();
+                (corners, plane)
+            }),
+            Reference::Points {
+                points,
+                width_mm,
+                height_mm,
+            } => Some((
+                Vector::from_slice(points),
+                Vector::from_slice(&[
+                    Point2f::new(0.0, 0.0),
+                    Point2f::new(*width_mm, 0.0),
+                    Point2f::new(*width_mm, *height_mm),
+                    Point2f::new(0.0, *height_mm),
+                ]),
+            )),
+        })
+    }
+}
+
+/// output of the rectification, by default the reference keeps about its size in pixels
+/// and the output covers the whole image
+#[derive(Clone, Copy, Default)]
+pub struct Options {
+    pub mm_per_pixel: Option<f64>,
+    /// centered on the reference
+    pub size: Option<Size>,
+    pub interpolation: Interpolation,
+}
+
+// corners of the bounding box of the points
+fn bounds(points: &Vector<Point2f>) -> (Point2f, Point2f) {
+    points.iter().fold(
+        (
+            Point2f::new(f32::MAX, f32::MAX),
+            Point2f::new(f32::MIN, f32::MIN),
+        ),
+        |(min, max), point| {
+            (
+                Point2f::new(min.x.min(point.x), min.y.min(point.y)),
+                Point2f::new(max.x.max(point.x), max.y.max(point.y)),
+            )
+        },
+    )
+}
+
+fn diagonal((min, max): (Point2f, Point2f)) -> f64 {
+    ((max.x - min.x) as f64).hypot((max.y - min.y) as f64)
+}
+
+/// undistort the image and warp the plane of the reference to a fronto-parallel view with
+/// a fixed scale, None when the board is not found. The reference is looked for in
+/// `prepared`, the image as the board reads it for detection
+pub fn rectify(
+    calibration: &Calibration,
+    img: &Mat,
+    prepared: &Mat,
+    reference: &Reference,
+    options: &Options,
+) -> Result<Option<Mat>, Box<dyn Error>> {
+    if !matches!(
+        calibration.model,
+        CameraModel::Pinhole | CameraModel::Fisheye
+    ) {
+        return Err(format!(
+            "rectification needs a pinhole or fisheye calibration, not {:?}",
+            calibration.model
+        )
+        .into());
+    }
+    let size = img.size()?;
+    if prepared.size()? != size {
+        return Err("the prepared image doesn't have the size of the image".into());
+    }
+    let Some((points, plane)) = reference.find(prepared)? else {
+        return Ok(None);
+    };
+    let mut undistorted_points = Vector::<Point2f>::new();
+    calibration.undistort_points(&points, &mut undistorted_points, size)?;
+    let undistorted = Undistorter::new(calibration, size)?
+        .with_interpolation(options.interpolation)
+        .undistort(img)?;
+
+    // image to plane in mm
+    let homography = find_homography_def(&undistorted_points, &plane, &mut Mat::default())?;
+    let plane_bounds = bounds(&plane);
+    let mm_per_pixel = options
+        .mm_per_pixel
+        .unwrap_or_else(|| diagonal(plane_bounds) / diagonal(bounds(&undistorted_points)));
+    let (origin, output_size) = match options.size {
+        Some(output_size) => {
+            let center = Point2f::new(
+                (plane_bounds.0.x + plane_bounds.1.x) / 2.0,
+                (plane_bounds.0.y + plane_bounds.1.y) / 2.0,
+            );
+            let half = Point2f::new(
+                (output_size.width as f64 * mm_per_pixel / 2.0) as f32,
+                (output_size.height as f64 * mm_per_pixel / 2.0) as f32,
+            );
+            (center - half, output_size)
+        }
+        None => {
+            let (width, height) = (size.width as f32, size.height as f32);
+            let corners = Vector::<Point2f>::from_slice(&[
+                Point2f::new(0.0, 0.0),
+                Point2f::new(width, 0.0),
+                Point2f::new(width, height),
+                Point2f::new(0.0, height),
+            ]);
+            let mut on_plane = Vector::<Point2f>::new();
+            perspective_transform(&corners, &mut on_plane, &homography)?;
+            let (min, max) = bounds(&on_plane);
+            let extent = (
+                (max.x - min.x) as f64 / mm_per_pixel,
+                (max.y - min.y) as f64 / mm_per_pixel,
+            );
+            // the image reaches the horizon or the homography flipped a corner behind the
+            // camera, only the reference itself is kept
+            let (min, max) = if extent.0.is_finite()
+                && extent.1.is_finite()
+                && extent.0.max(extent.1) <= MAX_SIDE
+            {
+                (min, max)
+            } else {
+                warn!("the image reaches too far across the plane, keeping the reference only");
+                plane_bounds
+            };
+            let output_size = Size::new(
+                ((max.x - min.x) as f64 / mm_per_pixel).ceil() as i32,
+                ((max.y - min.y) as f64 / mm_per_pixel).ceil() as i32,
+            );
+            (min, output_size)
+        }
+    };
+
+    // image to output pixels
+    let output_points = plane
+        .iter()
+        .map(|point| {
+            Point2f::new(
+                ((point.x - origin.x) as f64 / mm_per_pixel) as f32,
+                ((point.y - origin.y) as f64 / mm_per_pixel) as f32,
+            )
+        })
+        .collect::<Vector<Point2f>>();
+    let homography = find_homography_def(&undistorted_points, &output_points, &mut Mat::default())?;
+    let mut rectified = Mat::default();
+    imgproc::warp_perspective(
+        &undistorted,
+        &mut rectified,
+        &homography,
+        output_size,
+        options.interpolation.flag(),
+        BORDER_CONSTANT,
+        Scalar::default(),
+    )?;
+    info!(
+        "{}x{} rectified at {mm_per_pixel:.4} mm per pixel",
+        output_size.width, output_size.height
+    );
+    Ok(Some(rectified))
+}
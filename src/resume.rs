@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    job: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line<T> {
+    input: String,
+    value: T,
+}
+
+/// progress of a batch as json lines: a header naming the job, then one line per completed
+/// input, synced to disk before the next input so a crash loses at most the one in progress
+pub struct State<T> {
+    path: String,
+    file: File,
+    done: HashMap<String, T>,
+}
+
+impl<T: Serialize + DeserializeOwned> State<T> {
+    /// resume the state file of the same job or start a new one, `job` names the command and
+    /// the settings that make earlier results reusable
+    pub fn open(path: &str, job: &str) -> Result<Self, Box<dyn Error>> {
+        let mut done = HashMap::new();
+        if Path::new(path).exists() {
+            let content = fs::read_to_string(path)?;
+            let mut lines = content.lines();
+            let header = lines
+                .next()
+                .map(serde_json::from_str::<Header>)
+                .transpose()
+                .map_err(|e| format!("{path}: {e}"))?;
+            if let Some(header) = header
+                && header.job != job
+            {
+                return Err(format!(
+                    "{path} is the state of \"{}\", not \"{job}\", remove it to start over",
+                    header.job
+                )
+                .into());
+            }
+            for line in lines {
+                // the last line is cut short when the run died while writing it
+                match serde_json::from_str::<Line<T>>(line) {
+                    Ok(line) => {
+                        done.insert(line.input, line.value);
+                    }
+                    Err(_) => warn!("{path}: ignoring incomplete line"),
+                }
+            }
+            info!("resuming from {path}, {} inputs already done", done.len());
+        }
+        // rewrite the valid part so appends never follow a cut off line
+        let mut file = File::create(path)?;
+        writeln!(
+            file,
+            "{}",
+            serde_json::to_string(&Header {
+                job: job.to_string()
+            })?
+        )?;
+        let mut state = State {
+            path: path.to_string(),
+            file,
+            done: HashMap::new(),
+        };
+        for (input, value) in &done {
+            state.append(input, value)?;
+        }
+        state.file.sync_data()?;
+        state.done = done;
+        Ok(state)
+    }
+
+    fn append(&mut self, input: &str, value: &T) -> Result<(), Box<dyn Error>> {
+        let line = Line {
+            input: input.to_string(),
+            value,
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&line)?)?;
+        Ok(())
+    }
+
+    pub fn is_resumed(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    /// result of an input completed by an earlier run
+    pub fn get(&self, input: &str) -> Option<&T> {
+        self.done.get(input)
+    }
+
+    pub fn record(&mut self, input: &str, value: T) -> Result<(), Box<dyn Error>> {
+        self.append(input, &value)?;
+        self.file.sync_data()?;
+        self.done.insert(input.to_string(), value);
+        Ok(())
+    }
+
+    /// the batch completed, the next run starts over
+    pub fn finish(self) -> std::io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::Write;
+
+use glam::{DMat3, DVec3};
+
+pub const LEFT_COLOR: [u8; 3] = [255, 0, 0];
+pub const RIGHT_COLOR: [u8; 3] = [0, 255, 0];
+pub const BOARD_COLOR: [u8; 3] = [0, 128, 255];
+
+/// wireframe of the calibrated rig, everything expressed in the left camera frame
+#[derive(Default)]
+pub struct Rig {
+    vertices: Vec<(DVec3, [u8; 3])>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Rig {
+    fn add_loop(&mut self, points: &[DVec3], color: [u8; 3]) -> usize {
+        let first = self.vertices.len();
+        self.vertices
+            .extend(points.iter().map(|point| (*point, color)));
+        self.edges
+            .extend((0..points.len()).map(|i| (first + i, first + (i + 1) % points.len())));
+        first
+    }
+
+    /// camera frustum with the image corners placed at `depth` along the optical axis,
+    /// `pose` is the camera to rig rotation and the camera center
+    pub fn add_camera(
+        &mut self,
+        camera_matrix: &[f64],
+        (width, height): (i32, i32),
+        (rotation, center): (DMat3, DVec3),
+        depth: f64,
+        color: [u8; 3],
+    ) {
+        let (fx, cx, fy, cy) = (
+            camera_matrix[0],
+            camera_matrix[2],
+            camera_matrix[4],
+            camera_matrix[5],
+        );
+        let corners = [(0, 0), (width, 0), (width, height), (0, height)].map(|(u, v)| {
+            let ray = DVec3::new((u as f64 - cx) / fx, (v as f64 - cy) / fy, 1.0);
+            rotation * (ray * depth) + center
+        });
+        let first = self.add_loop(&corners, color);
+        let apex = self.vertices.len();
+        self.vertices.push((center, color));
+        self.edges
+            .extend((0..corners.len()).map(|i| (apex, first + i)));
+    }
+
+    /// board outline from its corners in camera coordinates
+    pub fn add_board(&mut self, corners: &[DVec3; 4]) {
+        self.add_loop(corners, BOARD_COLOR);
+    }
+
+    /// ascii ply with colored vertices and edges, readable by MeshLab and Blender
+    pub fn to_ply(&self) -> String {
+        let mut ply = String::new();
+        writeln!(ply, "ply\nformat ascii 1.0").unwrap();
+        writeln!(ply, "element vertex {}", self.vertices.len()).unwrap();
+        writeln!(
+            ply,
+            "property float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue"
+        )
+        .unwrap();
+        writeln!(ply, "element edge {}", self.edges.len()).unwrap();
+        writeln!(
+            ply,
+            "property int vertex1\nproperty int vertex2\nend_header"
+        )
+        .unwrap();
+        for (point, [red, green, blue]) in &self.vertices {
+            writeln!(
+                ply,
+                "{} {} {} {red} {green} {blue}",
+                point.x, point.y, point.z
+            )
+            .unwrap();
+        }
+        for (from, to) in &self.edges {
+            writeln!(ply, "{from} {to}").unwrap();
+        }
+        ply
+    }
+}
@@ -0,0 +1,40 @@
+use std::fmt::Write;
+
+use crate::Calibration;
+
+// yaml flow sequence for a matrix node
+fn matrix(name: &str, rows: usize, cols: usize, data: &[f64]) -> String {
+    let data = data
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("{name}:\n  rows: {rows}\n  cols: {cols}\n  data: [{data}]\n")
+}
+
+/// camera_info yaml as read by camera_calibration_parsers
+pub fn camera_info_yaml(
+    camera_name: &str,
+    width: i32,
+    height: i32,
+    camera: &Calibration,
+    distortion_model: &str,
+    rectification: &[f64],
+    projection: &[f64],
+) -> String {
+    let mut yaml = String::new();
+    writeln!(yaml, "image_width: {width}").unwrap();
+    writeln!(yaml, "image_height: {height}").unwrap();
+    writeln!(yaml, "camera_name: {camera_name}").unwrap();
+    yaml.push_str(&matrix("camera_matrix", 3, 3, &camera.camera_matrix));
+    writeln!(yaml, "distortion_model: {distortion_model}").unwrap();
+    yaml.push_str(&matrix(
+        "distortion_coefficients",
+        1,
+        camera.dist_coeffs.len(),
+        &camera.dist_coeffs,
+    ));
+    yaml.push_str(&matrix("rectification_matrix", 3, 3, rectification));
+    yaml.push_str(&matrix("projection_matrix", 3, 4, projection));
+    yaml
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::time::Duration;
+
+use futures::executor::LocalPool;
+use futures::task::LocalSpawnExt;
+use futures::{StreamExt, future};
+use log::{info, warn};
+use opencv::core::{Rect, Size};
+use opencv::imgproc;
+use opencv::prelude::*;
+use r2r::QosProfile;
+use r2r::sensor_msgs::msg::{CameraInfo, Image};
+
+use crate::{Calibration, undistort_maps};
+
+fn channels(encoding: &str) -> Option<i32> {
+    match encoding {
+        "bgr8" | "rgb8" => Some(3),
+        "mono8" => Some(1),
+        _ => None,
+    }
+}
+
+fn image_to_mat(image: &Image) -> Result<Mat, Box<dyn Error>> {
+    let channels = channels(&image.encoding)
+        .ok_or_else(|| format!("unsupported image encoding {}", image.encoding))?;
+    // rows may be padded, reshape by step and cut the valid columns
+    let data = Mat::from_slice(&image.data)?;
+    let rows = data.reshape(channels, image.height as i32)?;
+    Ok(Mat::roi(
+        &rows,
+        Rect::new(0, 0, image.width as i32, image.height as i32),
+    )?
+    .try_clone()?)
+}
+
+fn mat_to_image(mat: &Mat, source: &Image) -> opencv::Result<Image> {
+    Ok(Image {
+        header: source.header.clone(),
+        height: mat.rows() as u32,
+        width: mat.cols() as u32,
+        encoding: source.encoding.clone(),
+        is_bigendian: 0,
+        step: (mat.cols() * mat.channels()) as u32,
+        data: mat.data_bytes()?.to_vec(),
+    })
+}
+
+// the published image is undistorted, so the info carries no distortion and an identity rectification.
+// K and P are the camera matrix the image was remapped to, at the size of the stream
+fn camera_info(calibration: &Calibration, image: &Image) -> CameraInfo {
+    let scaled = calibration.scaled(Size::new(image.width as i32, image.height as i32));
+    let k = scaled
+        .new_camera_matrix
+        .as_ref()
+        .unwrap_or(&scaled.camera_matrix);
+    CameraInfo {
+        header: image.header.clone(),
+        height: image.height,
+        width: image.width,
+        distortion_model: "plumb_bob".to_string(),
+        d: vec![0.0; 5],
+        k: k.clone(),
+        r: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        p: vec![
+            k[0], k[1], k[2], 0.0, k[3], k[4], k[5], 0.0, k[6], k[7], k[8], 0.0,
+        ],
+        ..Default::default()
+    }
+}
+
+pub fn run(
+    calibration_file: &str,
+    image_topic: &str,
+    output_topic: &str,
+    camera_info_topic: &str,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+
+    let ctx = r2r::Context::create()?;
+    let mut node = r2r::Node::create(ctx, "opencv_undistort", "")?;
+    let subscriber = node.subscribe::<Image>(image_topic, QosProfile::default())?;
+    let image_publisher = node.create_publisher::<Image>(output_topic, QosProfile::default())?;
+    let info_publisher =
+        node.create_publisher::<CameraInfo>(camera_info_topic, QosProfile::default())?;
+    info!("undistort {image_topic} to {output_topic}, camera info on {camera_info_topic}");
+
+    // maps are built once per incoming image size
+    let mut maps: Option<(Size, Mat, Mat)> = None;
+    let mut correct = move |image: &Image| -> Result<(), Box<dyn Error>> {
+        let img = image_to_mat(image)?;
+        let size = img.size()?;
+        if maps
+            .as_ref()
+            .is_none_or(|(map_size, _, _)| *map_size != size)
+        {
+            let (mapx, mapy) = undistort_maps(&calibration, size)?;
+            maps = Some((size, mapx, mapy));
+        }
+        let (_, mapx, mapy) = maps.as_ref().unwrap();
+        let mut corrected = Mat::default();
+        imgproc::remap_def(&img, &mut corrected, mapx, mapy, imgproc::INTER_LINEAR)?;
+        image_publisher.publish(&mat_to_image(&corrected, image)?)?;
+        info_publisher.publish(&camera_info(&calibration, image))?;
+        Ok(())
+    };
+
+    let mut pool = LocalPool::new();
+    pool.spawner().spawn_local(async move {
+        subscriber
+            .for_each(|image| {
+                if let Err(e) = correct(&image) {
+                    warn!("could not correct image: {e}");
+                }
+                future::ready(())
+            })
+            .await
+    })?;
+    loop {
+        node.spin_once(Duration::from_millis(100));
+        pool.run_until_stalled();
+    }
+}
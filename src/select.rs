@@ -0,0 +1,80 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use glob::Pattern;
+
+use crate::order;
+
+/// which files of an input directory are read
+#[derive(Args, Clone, Debug, Default)]
+pub struct Selection {
+    /// also read the images in subdirectories
+    #[arg(long)]
+    pub recursive: bool,
+    /// only images whose path below the input directory matches, e.g. `*_left.tif` or
+    /// `cam1/**/*.png`
+    #[arg(long)]
+    pub glob: Option<String>,
+}
+
+/// the extension is one of `extensions`, in any case
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().is_some_and(|ext| {
+        extensions
+            .iter()
+            .any(|extension| ext.eq_ignore_ascii_case(extension))
+    })
+}
+
+/// all files in a directory, and in its subdirectories when `recursive`, unsorted
+pub fn walk(dir: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() && recursive {
+            files.extend(walk(&entry.path(), recursive)?);
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+impl Selection {
+    fn pattern(&self) -> std::io::Result<Option<Pattern>> {
+        self.glob
+            .as_deref()
+            .map(Pattern::new)
+            .transpose()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    }
+
+    /// a file below `dir` is selected, for files appearing after the directory was listed
+    pub fn matches(&self, dir: &str, path: &Path, extensions: &[&str]) -> std::io::Result<bool> {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        Ok(has_extension(path, extensions)
+            && (self.recursive || relative.parent().is_none_or(|p| p.as_os_str().is_empty()))
+            && self
+                .pattern()?
+                .is_none_or(|pattern| pattern.matches_path(relative)))
+    }
+
+    /// files of `dir` with one of the extensions that match the glob, in `--sort` order
+    pub fn files(&self, dir: &str, extensions: &[&str]) -> std::io::Result<Vec<String>> {
+        let pattern = self.pattern()?;
+        let mut files = walk(Path::new(dir), self.recursive)?
+            .into_iter()
+            .filter(|path| has_extension(path, extensions))
+            .filter(|path| {
+                pattern.as_ref().is_none_or(|pattern| {
+                    pattern.matches_path(path.strip_prefix(dir).unwrap_or(path))
+                })
+            })
+            .map(|path| path.to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        order::sort(&mut files);
+        Ok(files)
+    }
+}
@@ -0,0 +1,304 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::info;
+use opencv::calib3d::{project_points_def, undistort_points};
+use opencv::core::{
+    BORDER_CONSTANT, CV_8UC3, Point2f, Point3f, Rect, Scalar, Size, Vector, no_array,
+};
+use opencv::imgcodecs::imwrite_def;
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::{
+    Action, BOARD_HEIGHT, BOARD_WIDTH, Calibration, CameraModel, Interpolation, SCHEMA_VERSION,
+    board, encoding, list_images, pinhole, provenance, run, select,
+};
+
+const IMAGE_WIDTH: i32 = 640;
+const IMAGE_HEIGHT: i32 = 480;
+// pixels per square of the rendered board
+const SQUARE: i32 = 40;
+// ground truth of the synthetic camera
+const CAMERA_MATRIX: [f64; 9] = [500.0, 0.0, 320.0, 0.0, 500.0, 240.0, 0.0, 0.0, 1.0];
+const DIST_COEFFS: [f64; 5] = [-0.2, 0.05, 0.0, 0.0, 0.0];
+// board rotation (rodrigues) and translation in squares for each view
+const POSES: [([f64; 3], [f64; 3]); 10] = [
+    ([0.0, 0.0, 0.0], [0.0, 0.0, 20.0]),
+    ([0.35, 0.0, 0.0], [0.0, -1.0, 21.0]),
+    ([-0.35, 0.0, 0.0], [0.0, 1.0, 21.0]),
+    ([0.0, 0.35, 0.0], [-1.5, 0.0, 20.0]),
+    ([0.0, -0.35, 0.0], [1.5, 0.0, 20.0]),
+    ([0.2, 0.25, 0.1], [1.0, 1.0, 19.0]),
+    ([-0.2, 0.25, -0.1], [-1.0, 1.0, 22.0]),
+    ([0.25, -0.2, 0.2], [1.0, -1.0, 18.0]),
+    ([-0.25, -0.25, 0.0], [-1.0, -1.0, 23.0]),
+    ([0.1, -0.1, 0.3], [0.0, 0.0, 24.0]),
+];
+// largest allowed difference of the radial distortion factor over the board area
+const RADIAL_TOLERANCE: f64 = 0.01;
+const MAX_RMS: f64 = 0.5;
+// largest allowed relative error of the focal lengths and offset of the principal point
+const FOCAL_TOLERANCE: f64 = 0.02;
+const PRINCIPAL_POINT_TOLERANCE: f64 = 5.0;
+
+// white board with a margin, one square more than the interior corners in each direction
+fn render_board() -> opencv::Result<Mat> {
+    let (columns, rows) = (BOARD_WIDTH + 1, BOARD_HEIGHT + 1);
+    let mut board = Mat::new_rows_cols_with_default(
+        (rows + 2) * SQUARE,
+        (columns + 2) * SQUARE,
+        CV_8UC3,
+        Scalar::all(255.0),
+    )?;
+    for row in 0..rows {
+        for column in (0..columns).filter(|column| (column + row) % 2 == 0) {
+            imgproc::rectangle(
+                &mut board,
+                Rect::new((column + 1) * SQUARE, (row + 1) * SQUARE, SQUARE, SQUARE),
+                Scalar::all(0.0),
+                imgproc::FILLED,
+                imgproc::LINE_8,
+                0,
+            )?;
+        }
+    }
+    Ok(board)
+}
+
+// for every pixel of the distorted image the position in the ideal pinhole image
+fn distortion_maps(mtx: &Mat, dist: &Mat) -> opencv::Result<(Mat, Mat)> {
+    let pixels = Vector::<Point2f>::from_iter(
+        (0..IMAGE_HEIGHT)
+            .flat_map(|y| (0..IMAGE_WIDTH).map(move |x| Point2f::new(x as f32, y as f32))),
+    );
+    let mut ideal = Vector::<Point2f>::new();
+    undistort_points(&pixels, &mut ideal, mtx, dist, &no_array(), mtx)?;
+    let xs = ideal.iter().map(|point| point.x).collect::<Vec<f32>>();
+    let ys = ideal.iter().map(|point| point.y).collect::<Vec<f32>>();
+    Ok((
+        Mat::new_rows_cols_with_data(IMAGE_HEIGHT, IMAGE_WIDTH, &xs)?.try_clone()?,
+        Mat::new_rows_cols_with_data(IMAGE_HEIGHT, IMAGE_WIDTH, &ys)?.try_clone()?,
+    ))
+}
+
+// the board seen from one pose through the distorted ground truth camera
+fn render_view(
+    board: &Mat,
+    rvec: [f64; 3],
+    tvec: [f64; 3],
+    mtx: &Mat,
+    maps: &(Mat, Mat),
+) -> opencv::Result<Mat> {
+    let (width, height) = (board.cols() as f32, board.rows() as f32);
+    let image_corners = Vector::<Point2f>::from_iter([
+        Point2f::new(0.0, 0.0),
+        Point2f::new(width, 0.0),
+        Point2f::new(width, height),
+        Point2f::new(0.0, height),
+    ]);
+    // board plane centered on the origin, measured in squares
+    let plane = Vector::<Point3f>::from_iter(image_corners.iter().map(|corner| {
+        Point3f::new(
+            (corner.x - width / 2.0) / SQUARE as f32,
+            (corner.y - height / 2.0) / SQUARE as f32,
+            0.0,
+        )
+    }));
+    let mut projected = Vector::<Point2f>::new();
+    project_points_def(
+        &plane,
+        &Vector::<f64>::from_slice(&rvec),
+        &Vector::<f64>::from_slice(&tvec),
+        mtx,
+        &no_array(),
+        &mut projected,
+    )?;
+    let homography = imgproc::get_perspective_transform_def(&image_corners, &projected)?;
+    let mut ideal = Mat::default();
+    imgproc::warp_perspective(
+        board,
+        &mut ideal,
+        &homography,
+        Size::new(IMAGE_WIDTH, IMAGE_HEIGHT),
+        imgproc::INTER_LINEAR,
+        BORDER_CONSTANT,
+        Scalar::all(255.0),
+    )?;
+    let mut distorted = Mat::default();
+    imgproc::remap(
+        &ideal,
+        &mut distorted,
+        &maps.0,
+        &maps.1,
+        imgproc::INTER_LINEAR,
+        BORDER_CONSTANT,
+        Scalar::all(255.0),
+    )?;
+    Ok(distorted)
+}
+
+// radial factor 1 + k1 r^2 + k2 r^4 + k3 r^6 at a normalized radius
+fn radial(dist_coeffs: &[f64], r: f64) -> f64 {
+    let k = |i: usize| dist_coeffs.get(i).copied().unwrap_or(0.0);
+    let r2 = r * r;
+    1.0 + k(0) * r2 + k(1) * r2 * r2 + k(4) * r2 * r2 * r2
+}
+
+fn check(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let images_dir = dir.join("images");
+    let output_dir = dir.join("corrected");
+    fs::create_dir_all(&images_dir)?;
+    fs::create_dir_all(&output_dir)?;
+    let calibration_file = dir.join("calibration.json");
+    let path = |path: &Path| path.to_string_lossy().to_string();
+
+    let truth = Calibration {
+        version: SCHEMA_VERSION,
+        model: CameraModel::Pinhole,
+        camera_matrix: CAMERA_MATRIX.to_vec(),
+        dist_coeffs: DIST_COEFFS.to_vec(),
+        image_size: Some([IMAGE_WIDTH, IMAGE_HEIGHT]),
+        board: None,
+        omnidir: None,
+        rms: None,
+        new_camera_matrix: None,
+        roi: None,
+        provenance: None,
+    };
+    let (mtx, dist) = truth.matrices()?;
+    let maps = distortion_maps(&mtx, &dist)?;
+    let board = render_board()?;
+    for (i, (rvec, tvec)) in POSES.iter().enumerate() {
+        let view = render_view(&board, *rvec, *tvec, &mtx, &maps)?;
+        imwrite_def(&path(&images_dir.join(format!("view_{i:02}.jpg"))), &view)?;
+    }
+    info!(
+        "[self-test] {} synthetic views in {}",
+        POSES.len(),
+        images_dir.display()
+    );
+
+    run(Action::Calibrate {
+        calibration_dir: Some(path(&images_dir)),
+        files_from: None,
+        selection: select::Selection::default(),
+        calibration_file: path(&calibration_file),
+        board: board::BoardArgs::default(),
+        json: false,
+        max_rms: Some(MAX_RMS),
+        alpha: 1.0,
+        reject_threshold: None,
+        state: None,
+        underwater: false,
+        validate_dir: None,
+        model: CameraModel::Pinhole,
+        pinhole: pinhole::PinholeArgs::default(),
+        history: None,
+        camera_id: None,
+        jobs: None,
+        video: None,
+        frame_interval: 15,
+        max_frames: 60,
+        format: None,
+        debug_dir: None,
+        corners_file: None,
+        auto_board_size: false,
+    })?;
+    let calibration = Calibration::load(&path(&calibration_file))?;
+    let k = &calibration.camera_matrix;
+    let focal_error = [0, 4]
+        .map(|i| (k[i] / CAMERA_MATRIX[i] - 1.0).abs())
+        .into_iter()
+        .fold(0.0, f64::max);
+    let center_error = (k[2] - CAMERA_MATRIX[2]).hypot(k[5] - CAMERA_MATRIX[5]);
+    info!(
+        "[self-test] focal length error {:.2}%, principal point off by {center_error:.2}px",
+        focal_error * 100.0
+    );
+    if focal_error > FOCAL_TOLERANCE || center_error > PRINCIPAL_POINT_TOLERANCE {
+        return Err(format!(
+            "recovered fx {:.1} fy {:.1} cx {:.1} cy {:.1} differ from the ground truth fx {} fy {} \
+             cx {} cy {}",
+            k[0],
+            k[4],
+            k[2],
+            k[5],
+            CAMERA_MATRIX[0],
+            CAMERA_MATRIX[4],
+            CAMERA_MATRIX[2],
+            CAMERA_MATRIX[5]
+        )
+        .into());
+    }
+    // the board covers about half the normalized image radius
+    let error = (0..=10)
+        .map(|i| i as f64 * 0.06)
+        .map(|r| (radial(&calibration.dist_coeffs, r) - radial(&DIST_COEFFS, r)).abs())
+        .fold(0.0, f64::max);
+    info!("[self-test] radial distortion error {error:.4}, tolerance {RADIAL_TOLERANCE}");
+    if error > RADIAL_TOLERANCE {
+        return Err(format!(
+            "recovered distortion {:?} differs from the ground truth {DIST_COEFFS:?}",
+            calibration.dist_coeffs
+        )
+        .into());
+    }
+
+    run(Action::Correct {
+        calibration_file: Some(path(&calibration_file)),
+        preset: None,
+        zoom_profiles: None,
+        model: None,
+        rectification: None,
+        alpha: None,
+        crop: false,
+        no_crop: false,
+        correction_dir: Some(path(&images_dir)),
+        files_from: None,
+        selection: select::Selection::default(),
+        output_dir: path(&output_dir),
+        manifest: None,
+        state: None,
+        jobs: None,
+        interpolation: Interpolation::Linear,
+        map_cache: None,
+        maps: None,
+        gpu: None,
+        encoding: encoding::Encoding::default(),
+        watch: false,
+        settle_ms: 1000,
+        metrics_port: None,
+        #[cfg(feature = "s3")]
+        s3_endpoint: None,
+        #[cfg(feature = "s3")]
+        s3_concurrency: 1,
+    })?;
+    let corrected = list_images(&path(&output_dir))?
+        .iter()
+        .filter(|image| {
+            Path::new(image)
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("u_"))
+        })
+        .count();
+    if corrected != POSES.len() {
+        return Err(format!("{corrected} corrected images, expected {}", POSES.len()).into());
+    }
+    info!("[self-test] {corrected} corrected images");
+    Ok(())
+}
+
+/// calibrate and correct a synthetic dataset with known intrinsics in a temporary directory,
+/// kept for inspection when a check fails
+pub fn self_test() -> Result<(), Box<dyn Error>> {
+    let dir = std::env::temp_dir().join(format!(
+        "opencv-undistort-self-test-{}",
+        provenance::run_id()
+    ));
+    check(&dir).map_err(|e| format!("self-test failed, files in {}: {e}", dir.display()))?;
+    fs::remove_dir_all(&dir)?;
+    info!("[self-test] passed");
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use opencv::core::{Size, Vector};
+use opencv::imgcodecs::{self, IMREAD_UNCHANGED};
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::{Calibration, metrics, undistort_maps};
+
+pub type ServiceError = Box<dyn Error + Send + Sync>;
+
+// mapx/mapy for one calibration id and image width/height
+type Maps = Arc<(Mat, Mat)>;
+type MapKey = (String, i32, i32);
+
+/// calibrations of a long running process, keyed by calibration id, with the undistortion
+/// maps built once per id and image size
+pub struct Undistorters {
+    calibrations: HashMap<String, Calibration>,
+    maps: Mutex<HashMap<MapKey, Maps>>,
+}
+
+impl Undistorters {
+    fn entry(path: &Path) -> Result<(String, Calibration), Box<dyn Error>> {
+        let id = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        Ok((id, Calibration::load(&path.to_string_lossy())?))
+    }
+
+    /// a single calibration, the id is the file stem
+    pub fn load_file(calibration_file: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(HashMap::from([Self::entry(Path::new(
+            calibration_file,
+        ))?])))
+    }
+
+    /// every json calibration file of a directory, the id is the file stem
+    #[cfg(feature = "grpc")]
+    pub fn load_dir(calibration_dir: &str) -> Result<Self, Box<dyn Error>> {
+        let mut calibrations = HashMap::new();
+        for entry in std::fs::read_dir(calibration_dir)?.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let (id, calibration) = Self::entry(&path)?;
+                calibrations.insert(id, calibration);
+            }
+        }
+        if calibrations.is_empty() {
+            return Err(format!("no calibration files in {calibration_dir}").into());
+        }
+        Ok(Self::new(calibrations))
+    }
+
+    fn new(calibrations: HashMap<String, Calibration>) -> Self {
+        Undistorters {
+            calibrations,
+            maps: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids = self
+            .calibrations
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        ids.sort();
+        ids
+    }
+
+    /// calibrated image size of a calibration id, if the file records it
+    pub fn image_size(&self, id: &str) -> Option<[i32; 2]> {
+        self.calibrations.get(id)?.image_size
+    }
+
+    /// build the maps of an id and image size ahead of the first image
+    pub fn prepare(&self, id: &str, size: Size) -> Result<(), ServiceError> {
+        self.maps(id, size).map(|_| ())
+    }
+
+    fn maps(&self, id: &str, size: Size) -> Result<Maps, ServiceError> {
+        let key = (id.to_string(), size.width, size.height);
+        if let Some(maps) = self.maps.lock().unwrap().get(&key) {
+            return Ok(maps.clone());
+        }
+        let calibration = self
+            .calibrations
+            .get(id)
+            .ok_or_else(|| format!("unknown calibration id {id}"))?;
+        let maps = Arc::new(undistort_maps(calibration, size)?);
+        self.maps.lock().unwrap().insert(key, maps.clone());
+        Ok(maps)
+    }
+
+    pub fn undistort(&self, id: &str, img: &Mat) -> Result<Mat, ServiceError> {
+        let maps = self.maps(id, img.size()?)?;
+        let (mapx, mapy) = maps.as_ref();
+        let mut corrected = Mat::default();
+        imgproc::remap_def(img, &mut corrected, mapx, mapy, imgproc::INTER_LINEAR)?;
+        Ok(corrected)
+    }
+
+    /// decode, undistort and re-encode an image, `format` is an extension like `jpg` or `png`
+    pub fn undistort_encoded(
+        &self,
+        id: &str,
+        data: &[u8],
+        format: &str,
+    ) -> Result<Vec<u8>, ServiceError> {
+        let result = self.undistort_encoded_timed(id, data, format);
+        metrics::frame(result.is_ok());
+        result
+    }
+
+    fn undistort_encoded_timed(
+        &self,
+        id: &str,
+        data: &[u8],
+        format: &str,
+    ) -> Result<Vec<u8>, ServiceError> {
+        let img = metrics::time("decode", || {
+            imgcodecs::imdecode(&Vector::<u8>::from_slice(data), IMREAD_UNCHANGED)
+        })?;
+        if img.empty() {
+            return Err("could not decode image".into());
+        }
+        let corrected = metrics::time("undistort", || self.undistort(id, &img))?;
+        let mut buf = Vector::<u8>::new();
+        let format = if format.is_empty() { "jpg" } else { format };
+        metrics::time("encode", || {
+            imgcodecs::imencode_def(&format!(".{format}"), &corrected, &mut buf)
+        })?;
+        Ok(buf.to_vec())
+    }
+}
@@ -0,0 +1,695 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use clap::{Args, ValueEnum};
+use glam::{DMat3, DVec3};
+use indicatif::HumanDuration;
+use log::{info, warn};
+use opencv::calib3d::{
+    CALIB_FIX_INTRINSIC, CALIB_ZERO_DISPARITY, Fisheye_CALIB_CHECK_COND, Fisheye_CALIB_FIX_SKEW,
+    Fisheye_CALIB_RECOMPUTE_EXTRINSIC, calibrate_camera_def, fisheye_init_undistort_rectify_map,
+    fisheye_stereo_calibrate, fisheye_stereo_rectify, fisheye_undistort_points_def,
+    init_undistort_rectify_map, rodrigues_def, solve_pnp_def, stereo_calibrate, stereo_rectify,
+    undistort_points_def,
+};
+use opencv::core::{
+    Point2f, Point3f, Rect, Size, TermCriteria, TermCriteria_COUNT, TermCriteria_EPS, Vector,
+    no_array,
+};
+use opencv::imgcodecs::{self, imwrite_def};
+use opencv::imgproc;
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::diagnose;
+use crate::exit::{Code, Failure};
+use crate::provenance::Provenance;
+use crate::rig::{self, Rig};
+use crate::{
+    Calibration, CameraModel, IMAGE_EXTENSIONS, SCHEMA_VERSION, list_files, mat_to_vec, progress,
+    ros,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct StereoCalibration {
+    image_width: i32,
+    image_height: i32,
+    left: Calibration,
+    right: Calibration,
+    // rotation and translation of the right camera relative to the left one
+    r: Vec<f64>,
+    t: Vec<f64>,
+    // rectification rotations and projections, disparity-to-depth mapping
+    r1: Vec<f64>,
+    r2: Vec<f64>,
+    p1: Vec<f64>,
+    p2: Vec<f64>,
+    q: Vec<f64>,
+    rms: f64,
+    // metric baseline, only known when the board square size was given
+    #[serde(default)]
+    square_size_mm: Option<f32>,
+    #[serde(default)]
+    baseline_mm: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+    #[serde(default)]
+    baseline_std_mm: Option<f64>,
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// last run of digits in the file name, e.g. left_1694012345123.jpg
+fn file_timestamp(path: &str) -> Option<u64> {
+    file_stem(path)
+        .split(|c: char| !c.is_ascii_digit())
+        .rfind(|digits| !digits.is_empty())
+        .and_then(|digits| digits.parse().ok())
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Pairing {
+    /// identical file names in both directories
+    Name,
+    /// same file name apart from the left/right suffix, e.g. img_01_L.jpg / img_01_R.jpg
+    Suffix,
+    /// closest timestamp embedded in the file names
+    Timestamp,
+    /// explicit `left,right` lines in --pairs-file
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub struct PairingArgs {
+    /// how left and right images are matched
+    #[arg(long, value_enum, default_value_t = Pairing::Name)]
+    pairing: Pairing,
+    #[arg(long, default_value = "_L")]
+    left_suffix: String,
+    #[arg(long, default_value = "_R")]
+    right_suffix: String,
+    /// largest timestamp difference accepted for a pair, in file name units
+    #[arg(long, default_value_t = 10)]
+    max_time_delta: u64,
+    /// csv file with one `left,right` pair per line, paths relative to the image directories
+    #[arg(long, required_if_eq("pairing", "csv"))]
+    pairs_file: Option<String>,
+}
+
+// resolve a csv entry against its image directory unless already absolute
+fn resolve(dir: &str, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        path.to_string()
+    } else {
+        Path::new(dir).join(path).to_string_lossy().to_string()
+    }
+}
+
+fn csv_pairs(
+    left_dir: &str,
+    right_dir: &str,
+    pairs_file: &str,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    fs::read_to_string(pairs_file)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once(',') {
+            Some((left, right)) => Ok((
+                resolve(left_dir, left.trim()),
+                resolve(right_dir, right.trim()),
+            )),
+            None => Err(format!("invalid line in {pairs_file}: {line}").into()),
+        })
+        .collect()
+}
+
+fn image_pairs(
+    left_dir: &str,
+    right_dir: &str,
+    pairing: &PairingArgs,
+    extensions: &[&str],
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let left = list_files(left_dir, extensions)?;
+    let mut right = list_files(right_dir, extensions)?;
+    match pairing.pairing {
+        Pairing::Name => Ok(left
+            .into_iter()
+            .filter_map(|left| {
+                let name = file_name(&left);
+                right
+                    .iter()
+                    .find(|right| file_name(right) == name)
+                    .map(|right| (left, right.clone()))
+            })
+            .collect()),
+        Pairing::Suffix => Ok(left
+            .into_iter()
+            .filter_map(|left| {
+                let key = file_stem(&left)
+                    .strip_suffix(&pairing.left_suffix)?
+                    .to_string();
+                right
+                    .iter()
+                    .find(|right| {
+                        file_stem(right).strip_suffix(&pairing.right_suffix) == Some(&key)
+                    })
+                    .map(|right| (left, right.clone()))
+            })
+            .collect()),
+        Pairing::Timestamp => Ok(left
+            .into_iter()
+            .filter_map(|left| {
+                let stamp = file_timestamp(&left)?;
+                let (index, delta) = right
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, right)| {
+                        file_timestamp(right).map(|other| (index, stamp.abs_diff(other)))
+                    })
+                    .min_by_key(|(_, delta)| *delta)?;
+                // every right image is used at most once
+                (delta <= pairing.max_time_delta).then(|| (left, right.remove(index)))
+            })
+            .collect()),
+        Pairing::Csv => csv_pairs(
+            left_dir,
+            right_dir,
+            pairing
+                .pairs_file
+                .as_deref()
+                .ok_or("--pairs-file is required")?,
+        ),
+    }
+}
+
+// intrinsics and extrinsics of both cameras with the rectification of the pair
+struct Solution {
+    rms: f64,
+    k1: Mat,
+    d1: Mat,
+    k2: Mat,
+    d2: Mat,
+    r: Mat,
+    t: Mat,
+    r1: Mat,
+    r2: Mat,
+    p1: Mat,
+    p2: Mat,
+    q: Mat,
+}
+
+impl Solution {
+    fn new(rms: f64, k1: Mat, d1: Mat, k2: Mat, d2: Mat, r: Mat, t: Mat) -> Self {
+        Solution {
+            rms,
+            k1,
+            d1,
+            k2,
+            d2,
+            r,
+            t,
+            r1: Mat::default(),
+            r2: Mat::default(),
+            p1: Mat::default(),
+            p2: Mat::default(),
+            q: Mat::default(),
+        }
+    }
+}
+
+fn solve_fisheye(
+    objpoints: &Vector<Vector<Point3f>>,
+    left_points: &Vector<Vector<Point2f>>,
+    right_points: &Vector<Vector<Point2f>>,
+    image_size: Size,
+) -> opencv::Result<Solution> {
+    let mut k1 = Mat::default();
+    let mut d1 = Mat::default();
+    let mut k2 = Mat::default();
+    let mut d2 = Mat::default();
+    let mut r = Mat::default();
+    let mut t = Mat::default();
+    let rms = fisheye_stereo_calibrate(
+        objpoints,
+        left_points,
+        right_points,
+        &mut k1,
+        &mut d1,
+        &mut k2,
+        &mut d2,
+        image_size,
+        &mut r,
+        &mut t,
+        Fisheye_CALIB_RECOMPUTE_EXTRINSIC | Fisheye_CALIB_CHECK_COND | Fisheye_CALIB_FIX_SKEW,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 100, 1e-6)?,
+    )?;
+    let mut solution = Solution::new(rms, k1, d1, k2, d2, r, t);
+    fisheye_stereo_rectify(
+        &solution.k1,
+        &solution.d1,
+        &solution.k2,
+        &solution.d2,
+        image_size,
+        &solution.r,
+        &solution.t,
+        &mut solution.r1,
+        &mut solution.r2,
+        &mut solution.p1,
+        &mut solution.p2,
+        &mut solution.q,
+        CALIB_ZERO_DISPARITY,
+        image_size,
+        0.0,
+        1.0,
+    )?;
+    Ok(solution)
+}
+
+// each camera on its own first, the pair then only solves for the extrinsics
+fn solve_pinhole(
+    objpoints: &Vector<Vector<Point3f>>,
+    left_points: &Vector<Vector<Point2f>>,
+    right_points: &Vector<Vector<Point2f>>,
+    image_size: Size,
+) -> opencv::Result<Solution> {
+    let intrinsics = |points: &Vector<Vector<Point2f>>, name: &str| {
+        let mut k = Mat::default();
+        let mut d = Mat::default();
+        let rms = calibrate_camera_def(
+            objpoints,
+            points,
+            image_size,
+            &mut k,
+            &mut d,
+            &mut Vector::<Mat>::new(),
+            &mut Vector::<Mat>::new(),
+        )?;
+        info!("{name} camera rms {rms:.4}");
+        Ok::<_, opencv::Error>((k, d))
+    };
+    let (mut k1, mut d1) = intrinsics(left_points, "left")?;
+    let (mut k2, mut d2) = intrinsics(right_points, "right")?;
+    let mut r = Mat::default();
+    let mut t = Mat::default();
+    let rms = stereo_calibrate(
+        objpoints,
+        left_points,
+        right_points,
+        &mut k1,
+        &mut d1,
+        &mut k2,
+        &mut d2,
+        image_size,
+        &mut r,
+        &mut t,
+        &mut Mat::default(),
+        &mut Mat::default(),
+        CALIB_FIX_INTRINSIC,
+        TermCriteria::new(TermCriteria_COUNT + TermCriteria_EPS, 100, 1e-6)?,
+    )?;
+    let mut solution = Solution::new(rms, k1, d1, k2, d2, r, t);
+    stereo_rectify(
+        &solution.k1,
+        &solution.d1,
+        &solution.k2,
+        &solution.d2,
+        image_size,
+        &solution.r,
+        &solution.t,
+        &mut solution.r1,
+        &mut solution.r2,
+        &mut solution.p1,
+        &mut solution.p2,
+        &mut solution.q,
+        CALIB_ZERO_DISPARITY,
+        0.0,
+        image_size,
+        &mut Rect::default(),
+        &mut Rect::default(),
+    )?;
+    Ok(solution)
+}
+
+/// stereo calibration of a fisheye or a pinhole rig from left/right image pairs
+pub fn calibrate(
+    left_dir: &str,
+    right_dir: &str,
+    calibration_file: &str,
+    pairing: &PairingArgs,
+    board: &Board,
+    model: CameraModel,
+    rig_ply: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if !matches!(model, CameraModel::Pinhole | CameraModel::Fisheye) {
+        return Err(format!(
+            "stereo rigs are calibrated with the pinhole or fisheye model, not {model:?}"
+        )
+        .into());
+    }
+    let square_size_mm = board.square_size_mm;
+    let objp = board.object_points();
+
+    let mut objpoints = Vector::<Vector<Point3f>>::new();
+    let mut left_points = Vector::<Vector<Point2f>>::new();
+    let mut right_points = Vector::<Vector<Point2f>>::new();
+    let mut image_size = Size::default();
+
+    let pairs = image_pairs(left_dir, right_dir, pairing, board.extensions())?;
+    let pb = progress::progress_bar(pairs.len() as u64);
+    info!("[1/3] process image pairs");
+    let started = Instant::now();
+    for (left, right) in &pairs {
+        pb.inc(1);
+        let left_img = board.read_image(left)?;
+        let right_img = board.read_image(right)?;
+        image_size = left_img.size()?;
+        match (board.detect(&left_img)?, board.detect(&right_img)?) {
+            (Some(left_corners), Some(right_corners)) => {
+                objpoints.push(objp.clone());
+                left_points.push(left_corners);
+                right_points.push(right_corners);
+                pb.set_message(format!(
+                    "{left} processed. in progress for {}",
+                    HumanDuration(started.elapsed())
+                ));
+            }
+            _ => warn!("board not found in both images of pair {left} {right}"),
+        }
+    }
+    if objpoints.is_empty() {
+        if let Some((left, _)) = pairs.get(pairs.len() / 2) {
+            diagnose::no_boards(left, board)?;
+        }
+        return Err(Failure::new(Code::NoBoards, "no image pair with a visible chessboard").into());
+    }
+
+    info!("[2/3] compute {model:?} stereo calibration");
+    let Solution {
+        rms,
+        k1,
+        d1,
+        k2,
+        d2,
+        r,
+        t,
+        r1,
+        r2,
+        p1,
+        p2,
+        q,
+    } = match model {
+        CameraModel::Fisheye => solve_fisheye(&objpoints, &left_points, &right_points, image_size)?,
+        _ => solve_pinhole(&objpoints, &left_points, &right_points, image_size)?,
+    };
+
+    let views = (0..objpoints.len())
+        .map(|i| {
+            Ok((
+                view_pose(model, &objpoints.get(i)?, &left_points.get(i)?, &k1, &d1)?,
+                view_pose(model, &objpoints.get(i)?, &right_points.get(i)?, &k2, &d2)?,
+            ))
+        })
+        .collect::<opencv::Result<Vec<_>>>()?;
+    // spread of the baseline seen by the individual views
+    let baselines = views
+        .iter()
+        .map(
+            |((left_rotation, left_translation), (right_rotation, right_translation))| {
+                let rotation = *right_rotation * left_rotation.transpose();
+                (*right_translation - rotation * *left_translation).length()
+            },
+        )
+        .collect::<Vec<f64>>();
+    let mean = baselines.iter().sum::<f64>() / baselines.len() as f64;
+    let baseline_std =
+        (baselines.iter().map(|b| (b - mean).powi(2)).sum::<f64>() / baselines.len() as f64).sqrt();
+    let t_vec = mat_to_vec(&t)?;
+    let baseline = DVec3::from_slice(&t_vec).length();
+    let p1_vec = mat_to_vec(&p1)?;
+    match square_size_mm {
+        Some(_) => {
+            info!(
+                "baseline {baseline:.2} mm +/- {baseline_std:.2} mm ({} views)",
+                baselines.len()
+            );
+            // depth step for one pixel of disparity: dz = z^2 / (f * B)
+            let focal = p1_vec[0];
+            info!("distance     depth resolution");
+            for z in [500.0, 1000.0, 2000.0, 5000.0, 10000.0] {
+                info!(
+                    "{:>6.1} m     {:>8.1} mm",
+                    z / 1000.0,
+                    z * z / (focal * baseline)
+                );
+            }
+        }
+        None => info!(
+            "baseline {baseline:.3} squares +/- {baseline_std:.3}, pass --square-size-mm for metric units"
+        ),
+    }
+
+    if let Some(rig_ply) = rig_ply {
+        let size = (image_size.width, image_size.height);
+        let rotation = DMat3::from_cols_slice(&mat_to_vec(&r)?).transpose();
+        let mut rig = Rig::default();
+        rig.add_camera(
+            &mat_to_vec(&k1)?,
+            size,
+            (DMat3::IDENTITY, DVec3::ZERO),
+            baseline,
+            rig::LEFT_COLOR,
+        );
+        // right camera center and orientation in the left camera frame
+        rig.add_camera(
+            &mat_to_vec(&k2)?,
+            size,
+            (
+                rotation.transpose(),
+                -(rotation.transpose() * DVec3::from_slice(&t_vec)),
+            ),
+            baseline,
+            rig::RIGHT_COLOR,
+        );
+        let square = square_size_mm.unwrap_or(1.0) as f64;
+        let (board_width, board_height) = (
+            (board.width - 1) as f64 * square,
+            (board.height - 1) as f64 * square,
+        );
+        for ((board_rotation, board_translation), _) in views.iter().take(5) {
+            rig.add_board(
+                &[
+                    DVec3::ZERO,
+                    DVec3::new(board_width, 0.0, 0.0),
+                    DVec3::new(board_width, board_height, 0.0),
+                    DVec3::new(0.0, board_height, 0.0),
+                ]
+                .map(|corner| *board_rotation * corner + *board_translation),
+            );
+        }
+        info!("save rig geometry {rig_ply}");
+        fs::write(rig_ply, rig.to_ply())?;
+    }
+
+    let calibration = StereoCalibration {
+        image_width: image_size.width,
+        image_height: image_size.height,
+        left: Calibration {
+            version: SCHEMA_VERSION,
+            model,
+            camera_matrix: mat_to_vec(&k1)?,
+            dist_coeffs: mat_to_vec(&d1)?,
+            image_size: Some([image_size.width, image_size.height]),
+            board: Some(board.geometry()),
+            omnidir: None,
+            rms: None,
+            new_camera_matrix: None,
+            roi: None,
+            provenance: None,
+        },
+        right: Calibration {
+            version: SCHEMA_VERSION,
+            model,
+            camera_matrix: mat_to_vec(&k2)?,
+            dist_coeffs: mat_to_vec(&d2)?,
+            image_size: Some([image_size.width, image_size.height]),
+            board: Some(board.geometry()),
+            omnidir: None,
+            rms: None,
+            new_camera_matrix: None,
+            roi: None,
+            provenance: None,
+        },
+        r: mat_to_vec(&r)?,
+        t: t_vec,
+        r1: mat_to_vec(&r1)?,
+        r2: mat_to_vec(&r2)?,
+        p1: p1_vec,
+        p2: mat_to_vec(&p2)?,
+        q: mat_to_vec(&q)?,
+        rms,
+        square_size_mm,
+        baseline_mm: square_size_mm.map(|_| baseline),
+        baseline_std_mm: square_size_mm.map(|_| baseline_std),
+        provenance: Some(Provenance::new()),
+    };
+    info!("[3/3] store to file {calibration_file}, rms {rms:.4}");
+    fs::write(calibration_file, serde_json::to_string(&calibration)?)?;
+    info!("done in {}", HumanDuration(started.elapsed()));
+    pb.finish_and_clear();
+    Ok(())
+}
+
+// board pose in one camera, solved on undistorted normalized points
+fn view_pose(
+    model: CameraModel,
+    objp: &Vector<Point3f>,
+    corners: &Vector<Point2f>,
+    k: &Mat,
+    d: &Mat,
+) -> opencv::Result<(DMat3, DVec3)> {
+    let mut normalized = Vector::<Point2f>::new();
+    match model {
+        CameraModel::Fisheye => fisheye_undistort_points_def(corners, &mut normalized, k, d)?,
+        _ => undistort_points_def(corners, &mut normalized, k, d)?,
+    }
+    let identity = Mat::eye(3, 3, f64::opencv_type())?.to_mat()?;
+    let mut rvec = Mat::default();
+    let mut tvec = Mat::default();
+    solve_pnp_def(
+        objp,
+        &normalized,
+        &identity,
+        &no_array(),
+        &mut rvec,
+        &mut tvec,
+    )?;
+    let mut rotation = Mat::default();
+    rodrigues_def(&rvec, &mut rotation)?;
+    Ok((
+        DMat3::from_cols_slice(&mat_to_vec(&rotation)?).transpose(),
+        DVec3::from_slice(&mat_to_vec(&tvec)?),
+    ))
+}
+
+// rectification maps for one camera of the rig
+fn rectify_maps(
+    camera: &Calibration,
+    r: &[f64],
+    p: &[f64],
+    size: Size,
+) -> opencv::Result<(Mat, Mat)> {
+    let (k, d) = camera.matrices()?;
+    let r = Mat::new_rows_cols_with_data(3, 3, r)?;
+    let p = Mat::new_rows_cols_with_data(3, 4, p)?;
+    let mut mapx = Mat::default();
+    let mut mapy = Mat::default();
+    match camera.model {
+        CameraModel::Fisheye => fisheye_init_undistort_rectify_map(
+            &k,
+            &d,
+            &r,
+            &p,
+            size,
+            f32::opencv_type(),
+            &mut mapx,
+            &mut mapy,
+        )?,
+        _ => init_undistort_rectify_map(
+            &k,
+            &d,
+            &r,
+            &p,
+            size,
+            f32::opencv_type(),
+            &mut mapx,
+            &mut mapy,
+        )?,
+    }
+    Ok((mapx, mapy))
+}
+
+pub fn correct(
+    calibration_file: &str,
+    left_dir: &str,
+    right_dir: &str,
+    output_dir: &str,
+    pairing: &PairingArgs,
+) -> Result<(), Box<dyn Error>> {
+    let calibration: StereoCalibration = serde_json::from_slice(&fs::read(calibration_file)?)?;
+    let size = Size::new(calibration.image_width, calibration.image_height);
+    let (left_mapx, left_mapy) =
+        rectify_maps(&calibration.left, &calibration.r1, &calibration.p1, size)?;
+    let (right_mapx, right_mapy) =
+        rectify_maps(&calibration.right, &calibration.r2, &calibration.p2, size)?;
+
+    for (left, right) in image_pairs(left_dir, right_dir, pairing, &IMAGE_EXTENSIONS)? {
+        for (image, prefix, mapx, mapy) in [
+            (&left, "l", &left_mapx, &left_mapy),
+            (&right, "r", &right_mapx, &right_mapy),
+        ] {
+            let img = imgcodecs::imread_def(image)?;
+            let mut rectified = Mat::default();
+            imgproc::remap_def(&img, &mut rectified, mapx, mapy, imgproc::INTER_LINEAR)?;
+            let new_image = format!("{output_dir}/{prefix}_{}", file_name(image));
+            info!("save new image {new_image}");
+            imwrite_def(&new_image, &rectified)?;
+        }
+    }
+    Ok(())
+}
+
+// ros camera_info pair, the right projection carries the baseline in P[0][3], in metres as
+// stereo_image_proc reads it
+pub fn export_ros(calibration_file: &str, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    let calibration: StereoCalibration = serde_json::from_slice(&fs::read(calibration_file)?)?;
+    if calibration.square_size_mm.is_none() {
+        return Err(format!(
+            "{calibration_file} has its baseline in board squares, calibrate with \
+             --square-size-mm for a metric one"
+        )
+        .into());
+    }
+    // the translation column of the projection is in mm like the board
+    let mut p2 = calibration.p2.clone();
+    for translation in p2.iter_mut().skip(3).step_by(4) {
+        *translation /= 1000.0;
+    }
+    for (name, camera, r, p) in [
+        ("left", &calibration.left, &calibration.r1, &calibration.p1),
+        ("right", &calibration.right, &calibration.r2, &p2),
+    ] {
+        let yaml = ros::camera_info_yaml(
+            name,
+            calibration.image_width,
+            calibration.image_height,
+            camera,
+            match camera.model {
+                CameraModel::Fisheye => "equidistant",
+                _ if camera.dist_coeffs.len() > 5 => "rational_polynomial",
+                _ => "plumb_bob",
+            },
+            r,
+            p,
+        );
+        let file = format!("{output_dir}/{name}.yaml");
+        info!("save camera info {file}");
+        fs::write(file, yaml)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::path::Path;
+
+use log::info;
+use opencv::core::{Point2f, Size, Vector};
+use opencv::imgcodecs::{self, IMWRITE_EXR_TYPE, IMWRITE_EXR_TYPE_FLOAT};
+use opencv::prelude::*;
+
+use crate::{Calibration, undistort_maps};
+
+// stmap pixel for a source position: red = u, green = v with v going up from the bottom row
+// as nuke expects, blue unused. opencv stores bgr
+fn st(x: f32, y: f32, size: Size) -> [f32; 3] {
+    let u = (x + 0.5) / size.width as f32;
+    let v = 1.0 - (y + 0.5) / size.height as f32;
+    [0.0, v, u]
+}
+
+/// let opencv read and write exr, it only does when asked to before the codec is first used.
+/// The stmaps and the exr maps of `mapfile` need it
+///
+/// # Safety
+///
+/// sets an environment variable, call it before any other thread runs, opencv's included
+pub unsafe fn enable_exr() {
+    if std::env::var_os("OPENCV_IO_ENABLE_OPENEXR").is_none() {
+        // SAFETY: the caller runs no other thread yet
+        unsafe { std::env::set_var("OPENCV_IO_ENABLE_OPENEXR", "1") };
+    }
+}
+
+pub(crate) fn write_exr(
+    path: &Path,
+    size: Size,
+    pixels: &[[f32; 3]],
+) -> Result<(), Box<dyn Error>> {
+    let data = pixels.as_flattened();
+    let mat = Mat::new_rows_cols_with_data(size.height, size.width * 3, data)?
+        .reshape(3, size.height)?
+        .try_clone()?;
+    let params = Vector::from_slice(&[IMWRITE_EXR_TYPE, IMWRITE_EXR_TYPE_FLOAT]);
+    let path = path.to_string_lossy();
+    if !imgcodecs::imwrite(&path, &mat, &params)? {
+        return Err(format!("could not write {path}").into());
+    }
+    info!("{path} written");
+    Ok(())
+}
+
+/// 32 bit exr stmaps for compositing: `undistort_stmap.exr` looks up the distorted plate for
+/// every undistorted pixel, `distort_stmap.exr` applies the lens distortion back to a clean
+/// render. Needs `enable_exr` at startup
+pub fn export(
+    calibration_file: &str,
+    width: i32,
+    height: i32,
+    output_dir: &str,
+) -> Result<(), Box<dyn Error>> {
+    let calibration = Calibration::load(calibration_file)?;
+    let size = Size::new(width, height);
+
+    let (mapx, mapy) = undistort_maps(&calibration, size)?;
+    let undistort = mapx
+        .data_typed::<f32>()?
+        .iter()
+        .zip(mapy.data_typed::<f32>()?)
+        .map(|(x, y)| st(*x, *y, size))
+        .collect::<Vec<[f32; 3]>>();
+    write_exr(
+        &Path::new(output_dir).join("undistort_stmap.exr"),
+        size,
+        &undistort,
+    )?;
+
+    // for every distorted pixel, where it sits in the undistorted image
+    let pixels = Vector::<Point2f>::from_iter(
+        (0..height).flat_map(|y| (0..width).map(move |x| Point2f::new(x as f32, y as f32))),
+    );
+    let mut undistorted = Vector::<Point2f>::new();
+    calibration.undistort_points(&pixels, &mut undistorted, size)?;
+    let distort = undistorted
+        .iter()
+        .map(|point| st(point.x, point.y, size))
+        .collect::<Vec<[f32; 3]>>();
+    write_exr(
+        &Path::new(output_dir).join("distort_stmap.exr"),
+        size,
+        &distort,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,302 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::Instant;
+
+use aws_sdk_s3::Client;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use futures::StreamExt;
+use futures::stream;
+use indicatif::HumanDuration;
+use log::{info, warn};
+use opencv::core::Vector;
+use opencv::imgcodecs::{self, IMREAD_COLOR};
+use opencv::imgproc;
+use opencv::prelude::*;
+
+use crate::exit::{Code, Failure};
+use crate::manifest::{Entry, Manifest};
+use crate::{
+    Calibration, IMAGE_EXTENSIONS, list_files, order, progress, provenance, select, threads,
+    undistort_maps,
+};
+
+// objects above this size are uploaded in parts of this size
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+pub fn is_s3(location: &str) -> bool {
+    location.starts_with("s3://")
+}
+
+enum Store {
+    Local(String),
+    S3 {
+        client: Client,
+        bucket: String,
+        prefix: String,
+    },
+}
+
+impl Store {
+    async fn open(location: &str, endpoint: Option<&str>) -> Self {
+        let Some(path) = location.strip_prefix("s3://") else {
+            return Store::Local(location.to_string());
+        };
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        let mut loader = aws_config::from_env();
+        if let Some(endpoint) = endpoint {
+            loader = loader.endpoint_url(endpoint);
+        }
+        let config = aws_sdk_s3::config::Builder::from(&loader.load().await)
+            // minio and most self hosted stores don't support virtual host buckets
+            .force_path_style(endpoint.is_some())
+            .build();
+        Store::S3 {
+            client: Client::from_conf(config),
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn key(prefix: &str, name: &str) -> String {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{prefix}/{name}")
+        }
+    }
+
+    // image file names directly under the location, the extensions `correct` reads
+    async fn list(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        match self {
+            Store::Local(dir) => Ok(list_files(dir, &IMAGE_EXTENSIONS)?
+                .iter()
+                .filter_map(|path| Path::new(path).file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .collect()),
+            Store::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let mut names = Vec::new();
+                let mut token = None;
+                loop {
+                    let page = client
+                        .list_objects_v2()
+                        .bucket(bucket)
+                        .prefix(Self::key(prefix, ""))
+                        .delimiter("/")
+                        .set_continuation_token(token)
+                        .send()
+                        .await?;
+                    names.extend(
+                        page.contents()
+                            .iter()
+                            .filter_map(|object| object.key())
+                            .filter(|key| select::has_extension(Path::new(key), &IMAGE_EXTENSIONS))
+                            .filter_map(|key| key.rsplit('/').next())
+                            .map(str::to_string),
+                    );
+                    token = page.next_continuation_token().map(str::to_string);
+                    if token.is_none() {
+                        break;
+                    }
+                }
+                // no local mtime, objects fall back to name order
+                order::sort(&mut names);
+                Ok(names)
+            }
+        }
+    }
+
+    async fn read(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            Store::Local(dir) => Ok(tokio::fs::read(Path::new(dir).join(name)).await?),
+            Store::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let object = client
+                    .get_object()
+                    .bucket(bucket)
+                    .key(Self::key(prefix, name))
+                    .send()
+                    .await?;
+                Ok(object.body.collect().await?.into_bytes().to_vec())
+            }
+        }
+    }
+
+    async fn write(&self, name: &str, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Store::Local(dir) => Ok(tokio::fs::write(Path::new(dir).join(name), data).await?),
+            Store::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let key = Self::key(prefix, name);
+                if data.len() <= PART_SIZE {
+                    client
+                        .put_object()
+                        .bucket(bucket)
+                        .key(&key)
+                        .body(ByteStream::from(data))
+                        .send()
+                        .await?;
+                    return Ok(());
+                }
+                let upload = client
+                    .create_multipart_upload()
+                    .bucket(bucket)
+                    .key(&key)
+                    .send()
+                    .await?;
+                let upload_id = upload.upload_id().ok_or("no multipart upload id")?;
+                let mut parts = Vec::new();
+                for (index, chunk) in data.chunks(PART_SIZE).enumerate() {
+                    let part_number = index as i32 + 1;
+                    let part = client
+                        .upload_part()
+                        .bucket(bucket)
+                        .key(&key)
+                        .upload_id(upload_id)
+                        .part_number(part_number)
+                        .body(ByteStream::from(chunk.to_vec()))
+                        .send()
+                        .await?;
+                    parts.push(
+                        CompletedPart::builder()
+                            .set_e_tag(part.e_tag().map(str::to_string))
+                            .part_number(part_number)
+                            .build(),
+                    );
+                }
+                client
+                    .complete_multipart_upload()
+                    .bucket(bucket)
+                    .key(&key)
+                    .upload_id(upload_id)
+                    .multipart_upload(
+                        CompletedMultipartUpload::builder()
+                            .set_parts(Some(parts))
+                            .build(),
+                    )
+                    .send()
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+// encoded in the format of the input, named by `name`
+fn undistort_image(name: &str, data: &[u8], calibration: &Calibration) -> opencv::Result<Vec<u8>> {
+    let img = imgcodecs::imdecode(&Vector::<u8>::from_slice(data), IMREAD_COLOR)?;
+    let (mapx, mapy) = undistort_maps(calibration, img.size()?)?;
+    let mut dst_undistort = Mat::default();
+    imgproc::remap_def(
+        &img,
+        &mut dst_undistort,
+        &mapx,
+        &mapy,
+        imgproc::INTER_LINEAR,
+    )?;
+    let mut buf = Vector::<u8>::new();
+    let extension = Path::new(name)
+        .extension()
+        .map_or("jpg".to_string(), |ext| {
+            ext.to_string_lossy().to_lowercase()
+        });
+    imgcodecs::imencode_def(&format!(".{extension}"), &dst_undistort, &mut buf)?;
+    let mut data = buf.to_vec();
+    if matches!(extension.as_str(), "jpg" | "jpeg") {
+        provenance::tag_jpeg(&mut data, calibration);
+    }
+    Ok(data)
+}
+
+/// Correct with the input and/or the output on s3 compatible object storage
+pub fn correct(
+    calibration: &Calibration,
+    correction_dir: &str,
+    output_dir: &str,
+    endpoint: Option<&str>,
+    concurrency: usize,
+    manifest_file: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let runtime = threads::runtime()?;
+    runtime.block_on(async {
+        let input = Store::open(correction_dir, endpoint).await;
+        let output = Store::open(output_dir, endpoint).await;
+        let mut manifest = Manifest::new("correct", provenance::calibration_checksum(calibration));
+        let names = input.list().await?;
+        if let Store::Local(dir) = &input {
+            manifest.skip_other_files(dir, false, &list_files(dir, &IMAGE_EXTENSIONS)?)?;
+        }
+        let pb = progress::progress_bar(names.len() as u64);
+        let started = Instant::now();
+        let entries = stream::iter(names)
+            .map(|name| {
+                let (input, output, pb) = (&input, &output, &pb);
+                async move {
+                    let image_started = Instant::now();
+                    let new_image = format!("u_{name}");
+                    let result = async {
+                        let data = input.read(&name).await?;
+                        let (camera, image) = (calibration.clone(), name.clone());
+                        // opencv work is blocking, keep it off the io threads
+                        let corrected = tokio::task::spawn_blocking(move || {
+                            undistort_image(&image, &data, &camera)
+                        })
+                        .await??;
+                        output.write(&new_image, corrected.clone()).await?;
+                        Ok::<Vec<u8>, Box<dyn Error>>(corrected)
+                    }
+                    .await;
+                    pb.inc(1);
+                    let input_path = format!("{correction_dir}/{name}");
+                    match result {
+                        Ok(corrected) => {
+                            pb.set_message(format!("{new_image} saved"));
+                            Entry::corrected(
+                                &input_path,
+                                &format!("{output_dir}/{new_image}"),
+                                &corrected,
+                                image_started.elapsed(),
+                            )
+                        }
+                        Err(e) => {
+                            warn!("{name}: {e}");
+                            Entry::failed(&input_path, e.as_ref(), image_started.elapsed())
+                        }
+                    }
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        pb.finish_and_clear();
+        let images = entries.len();
+        entries.into_iter().for_each(|entry| manifest.push(entry));
+        let failed = manifest.failed();
+        info!(
+            "{} images corrected, {failed} failed in {}",
+            images - failed,
+            HumanDuration(started.elapsed())
+        );
+        if let Some(manifest_file) = manifest_file {
+            manifest.write(manifest_file)?;
+        }
+        if failed > 0 {
+            return Err(Failure::new(
+                Code::PartialFailure,
+                format!("{failed} images could not be corrected"),
+            )
+            .into());
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,49 @@
+use opencv::core::{CV_8U, NORM_MINMAX, bitwise_not_def, no_array, normalize};
+use opencv::imgcodecs::{self, IMREAD_UNCHANGED};
+use opencv::imgproc;
+use opencv::prelude::*;
+
+/// thermal cameras write 16-bit single channel tiff or png, radiometric jpgs are 8-bit
+pub const EXTENSIONS: [&str; 4] = ["jpg", "png", "tif", "tiff"];
+
+/// read an image for board detection only: thermal images are stretched from their own
+/// minimum and maximum to 8 bits and a heated board, whose dark squares are the warm ones, is
+/// inverted back to a regular chessboard. The result is BGR like `imread_def`
+pub fn read(path: &str, thermal: bool, inverted: bool) -> opencv::Result<Mat> {
+    if !thermal && !inverted {
+        return imgcodecs::imread_def(path);
+    }
+    let img = imgcodecs::imread(path, IMREAD_UNCHANGED)?;
+    if img.empty() {
+        return Ok(img);
+    }
+    let mut gray = Mat::default();
+    match img.channels() {
+        1 => gray = img,
+        4 => imgproc::cvt_color_def(&img, &mut gray, imgproc::COLOR_BGRA2GRAY)?,
+        _ => imgproc::cvt_color_def(&img, &mut gray, imgproc::COLOR_BGR2GRAY)?,
+    }
+    let mut stretched = Mat::default();
+    if thermal {
+        normalize(
+            &gray,
+            &mut stretched,
+            0.0,
+            255.0,
+            NORM_MINMAX,
+            CV_8U,
+            &no_array(),
+        )?;
+    } else {
+        stretched = gray;
+    }
+    let mut detection = Mat::default();
+    if inverted {
+        bitwise_not_def(&stretched, &mut detection)?;
+    } else {
+        detection = stretched;
+    }
+    let mut bgr = Mat::default();
+    imgproc::cvt_color_def(&detection, &mut bgr, imgproc::COLOR_GRAY2BGR)?;
+    Ok(bgr)
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+use std::ops::Deref;
+
+/// pool of the workers of an image batch. Each worker runs opencv, which has its own threads,
+/// so callers running several workers should bound them with `opencv::core::set_num_threads`
+/// once, the binary gives every worker its share of `--threads`
+pub struct WorkerPool {
+    pool: rayon::ThreadPool,
+}
+
+impl WorkerPool {
+    /// `jobs` workers, or as many as rayon's global pool, which the binary sizes from
+    /// `--threads`
+    pub fn new(jobs: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.unwrap_or_else(rayon::current_num_threads))
+            .build()?;
+        Ok(WorkerPool { pool })
+    }
+}
+
+impl Deref for WorkerPool {
+    type Target = rayon::ThreadPool;
+
+    fn deref(&self) -> &rayon::ThreadPool {
+        &self.pool
+    }
+}
+
+/// tokio runtime with as many workers and blocking threads as the global pool, the blocking
+/// pool runs the opencv work
+#[cfg(any(feature = "grpc", feature = "s3"))]
+pub(crate) fn runtime() -> std::io::Result<tokio::runtime::Runtime> {
+    let threads = rayon::current_num_threads();
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(threads)
+        .max_blocking_threads(threads)
+        .enable_all()
+        .build()
+}
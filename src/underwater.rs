@@ -0,0 +1,68 @@
+use std::error::Error;
+
+use log::{info, warn};
+use opencv::calib3d::find_homography_def;
+use opencv::core::{Point2f, Vector, perspective_transform};
+use opencv::prelude::*;
+
+use crate::board::Board;
+use crate::{Calibration, list_files};
+
+// residual of the in-water validation above which the calibration does not describe the
+// housing
+const MAX_VALIDATION_RMS: f64 = 1.0;
+
+/// what the fisheye model can not capture behind a dome port
+pub fn warn_refraction() {
+    warn!(
+        "underwater: a dome port only acts like part of the lens when the camera's entrance pupil sits at the dome center, otherwise the result depends on the distance to the scene"
+    );
+    warn!(
+        "underwater: calibrate in water of the same kind as the dive, a calibration in air does not transfer to the housing"
+    );
+}
+
+/// undistort the board corners of in-water images and measure how far they are from a
+/// perfect plane, a large residual means refraction the model did not absorb
+pub fn validate(calibration: &Calibration, dir: &str, board: &Board) -> Result<(), Box<dyn Error>> {
+    let objp = board.object_points();
+    let plane =
+        Vector::<Point2f>::from_iter(objp.iter().map(|point| Point2f::new(point.x, point.y)));
+    let mut squared = 0.0;
+    let mut points = 0;
+    for image in list_files(dir, board.extensions())? {
+        let img = board.read_image(&image)?;
+        let Some(corners) = board.detect(&img)? else {
+            warn!("validation: board not found for image {image}");
+            continue;
+        };
+        let mut undistorted = Vector::<Point2f>::new();
+        calibration.undistort_points(&corners, &mut undistorted, img.size()?)?;
+        let homography = find_homography_def(&plane, &undistorted, &mut Mat::default())?;
+        let mut projected = Vector::<Point2f>::new();
+        perspective_transform(&plane, &mut projected, &homography)?;
+        let image_squared = projected
+            .iter()
+            .zip(undistorted.iter())
+            .map(|(a, b)| ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)) as f64)
+            .sum::<f64>();
+        info!(
+            "validation: {image} straightness rms {:.3}px",
+            (image_squared / corners.len() as f64).sqrt()
+        );
+        squared += image_squared;
+        points += corners.len();
+    }
+    if points == 0 {
+        return Err(format!("validation: no chessboard found in {dir}").into());
+    }
+    let rms = (squared / points as f64).sqrt();
+    if rms > MAX_VALIDATION_RMS {
+        warn!(
+            "validation: in-water rms {rms:.3}px, the undistorted boards are not flat, calibrate with in-water images at the working distance"
+        );
+    } else {
+        info!("validation: in-water rms {rms:.3}px");
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use std::error::Error;
+
+use clap::ValueEnum;
+use log::{info, warn};
+use opencv::calib3d::solve_pnp_def;
+use opencv::core::{Point2f, Vector, no_array};
+use opencv::prelude::*;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::board::Board;
+use crate::{Calibration, CameraModel, WorkerPool, exit, pose};
+
+/// figure of the reprojection errors compared with `--max-error`
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Statistic {
+    Mean,
+    Median,
+    #[default]
+    P95,
+}
+
+/// distribution of reprojection errors in pixels
+#[derive(Serialize, Clone, Copy)]
+pub struct Stats {
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    fn of(errors: &mut [f64]) -> Self {
+        errors.sort_by(f64::total_cmp);
+        let n = errors.len();
+        // nearest rank
+        let percentile = |q: f64| errors[((q * n as f64).ceil() as usize).clamp(1, n) - 1];
+        Stats {
+            mean: errors.iter().sum::<f64>() / n as f64,
+            median: (errors[(n - 1) / 2] + errors[n / 2]) / 2.0,
+            p95: percentile(0.95),
+            max: errors[n - 1],
+        }
+    }
+
+    pub fn get(&self, statistic: Statistic) -> f64 {
+        match statistic {
+            Statistic::Mean => self.mean,
+            Statistic::Median => self.median,
+            Statistic::P95 => self.p95,
+        }
+    }
+}
+
+/// reprojection errors of the corners of one image
+#[derive(Serialize)]
+pub struct ImageScore {
+    pub image: String,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+/// an image that could not be read
+#[derive(Serialize)]
+pub struct Unreadable {
+    pub image: String,
+    pub reason: String,
+}
+
+/// reprojection errors of a calibration on images it was not solved from, the `--json`
+/// output of `validate`
+#[derive(Serialize)]
+pub struct Report {
+    pub images: usize,
+    /// over the corners of all images
+    pub overall: Stats,
+    pub rms: f64,
+    pub per_image: Vec<ImageScore>,
+    pub not_found: Vec<String>,
+    pub failed: Vec<Unreadable>,
+}
+
+// distance of every detected corner to the board projected through the lens at the pose
+// solved with the fixed intrinsics, None when the board is not found
+fn corner_errors(
+    calibration: &Calibration,
+    board: &Board,
+    image: &str,
+) -> opencv::Result<Option<Vec<f64>>> {
+    let img = board.read_image(image)?;
+    let size = img.size()?;
+    let Some(corners) = board.detect(&img)? else {
+        return Ok(None);
+    };
+    let calibration = calibration.scaled(size);
+    let mut undistorted = Vector::<Point2f>::new();
+    calibration.undistort_points(&corners, &mut undistorted, size)?;
+    let objp = board.object_points();
+    let mut rvec = Mat::default();
+    let mut tvec = Mat::default();
+    if !solve_pnp_def(
+        &objp,
+        &undistorted,
+        &calibration.new_matrix()?,
+        &no_array(),
+        &mut rvec,
+        &mut tvec,
+    )? {
+        return Ok(None);
+    }
+    let reprojected = pose::project(&calibration, &objp, (&rvec, &tvec), size, false)?;
+    Ok(Some(
+        corners
+            .iter()
+            .zip(reprojected.iter())
+            .map(|(a, b)| (a.x - b.x).hypot(a.y - b.y) as f64)
+            .collect(),
+    ))
+}
+
+/// detect the board in held-out images, solve its pose with the intrinsics of the
+/// calibration and measure how far the detected corners are from the reprojected board
+pub fn score(
+    calibration: &Calibration,
+    board: &Board,
+    images: &[String],
+    jobs: Option<usize>,
+) -> Result<Report, Box<dyn Error>> {
+    if !matches!(
+        calibration.model,
+        CameraModel::Pinhole | CameraModel::Fisheye
+    ) {
+        return Err(format!(
+            "validation needs a pinhole or fisheye calibration, not {:?}",
+            calibration.model
+        )
+        .into());
+    }
+    let pool = WorkerPool::new(jobs)?;
+    let results = pool.install(|| {
+        images
+            .par_iter()
+            .map(|image| corner_errors(calibration, board, image))
+            .collect::<Vec<_>>()
+    });
+
+    let mut per_image = Vec::new();
+    let mut not_found = Vec::new();
+    let mut failed = Vec::new();
+    let mut all = Vec::new();
+    for (image, result) in images.iter().zip(results) {
+        match result {
+            Ok(Some(mut errors)) => {
+                all.extend_from_slice(&errors);
+                per_image.push(ImageScore {
+                    image: image.clone(),
+                    stats: Stats::of(&mut errors),
+                });
+            }
+            Ok(None) => {
+                warn!("board not found for image {image}");
+                not_found.push(image.clone());
+            }
+            Err(e) => {
+                warn!("{image}: {e}");
+                failed.push(Unreadable {
+                    image: image.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+    if all.is_empty() {
+        return Err(exit::Failure::new(
+            exit::Code::NoBoards,
+            format!("no board found in any of the {} images", images.len()),
+        )
+        .into());
+    }
+
+    let name_width = per_image
+        .iter()
+        .map(|score| score.image.len())
+        .max()
+        .unwrap_or(0);
+    info!(
+        "  {:<name_width$}  {:>7}  {:>7}  {:>7}  {:>7}",
+        "image", "mean", "median", "p95", "max"
+    );
+    for ImageScore { image, stats } in &per_image {
+        info!(
+            "  {image:<name_width$}  {:>7.3}  {:>7.3}  {:>7.3}  {:>7.3}",
+            stats.mean, stats.median, stats.p95, stats.max
+        );
+    }
+    let rms = (all.iter().map(|error| error * error).sum::<f64>() / all.len() as f64).sqrt();
+    let overall = Stats::of(&mut all);
+    info!(
+        "{} of {} images, mean {:.3}px, median {:.3}px, p95 {:.3}px, max {:.3}px, rms {rms:.3}px",
+        per_image.len(),
+        images.len(),
+        overall.mean,
+        overall.median,
+        overall.p95,
+        overall.max
+    );
+    Ok(Report {
+        images: images.len(),
+        overall,
+        rms,
+        per_image,
+        not_found,
+        failed,
+    })
+}
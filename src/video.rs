@@ -0,0 +1,337 @@
+use std::error::Error;
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Instant;
+
+use clap::ValueEnum;
+use indicatif::HumanDuration;
+use log::{debug, info, warn};
+use opencv::core::{Point2f, Scalar, Size, Vector};
+use opencv::imgproc;
+use opencv::prelude::*;
+use opencv::videoio::{CAP_PROP_FPS, CAP_PROP_FRAME_COUNT, VideoCapture, VideoWriter};
+
+use crate::board::Board;
+use crate::{Calibration, Undistorter, progress};
+
+// a sampled board closer than this fraction of the image diagonal to an earlier one, at
+// about the same size, adds nothing to the calibration
+const MIN_SHIFT: f64 = 0.05;
+const MIN_SCALE_CHANGE: f64 = 0.15;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Encoder {
+    /// opencv VideoWriter, falling back to ffmpeg when it can't handle the codec
+    Auto,
+    Opencv,
+    /// raw frames piped to an ffmpeg subprocess
+    Ffmpeg,
+}
+
+// ffmpeg encoder for the codec names users typically ask for
+fn ffmpeg_encoder(codec: &str) -> &str {
+    match codec.to_lowercase().as_str() {
+        "prores" | "apcn" | "apch" => "prores_ks",
+        "av1" | "av01" => "libsvtav1",
+        "h264" | "avc1" | "x264" => "libx264",
+        "hevc" | "h265" | "hvc1" => "libx265",
+        "mjpg" | "mjpeg" => "mjpeg",
+        "vp9" | "vp09" => "libvpx-vp9",
+        _ => codec,
+    }
+}
+
+fn fourcc(codec: &str) -> Option<i32> {
+    let chars = codec.chars().collect::<Vec<char>>();
+    match chars[..] {
+        [c1, c2, c3, c4] => VideoWriter::fourcc(c1, c2, c3, c4).ok(),
+        _ => None,
+    }
+}
+
+pub enum VideoSink {
+    Opencv(VideoWriter),
+    Ffmpeg { child: Child, stdin: ChildStdin },
+}
+
+impl VideoSink {
+    // raw frames on stdin, `output` are the encoder and destination arguments
+    fn spawn_ffmpeg(
+        output: &[&str],
+        fps: f64,
+        size: Size,
+        channels: i32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt"])
+            .arg(if channels == 1 { "gray" } else { "bgr24" })
+            .arg("-s")
+            .arg(format!("{}x{}", size.width, size.height))
+            .arg("-r")
+            .arg(fps.to_string())
+            .args(["-i", "-"])
+            .args(output)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not start ffmpeg: {e}"))?;
+        let stdin = child.stdin.take().ok_or("ffmpeg stdin is not available")?;
+        Ok(VideoSink::Ffmpeg { child, stdin })
+    }
+
+    // the audio of `audio_from` is copied as it is, if it has any
+    fn ffmpeg(
+        path: &str,
+        codec: &str,
+        fps: f64,
+        size: Size,
+        channels: i32,
+        audio_from: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let encoder = ["-c:v", ffmpeg_encoder(codec), path];
+        match audio_from {
+            Some(audio) => Self::spawn_ffmpeg(
+                &[
+                    &["-i", audio, "-map", "0:v", "-map", "1:a?", "-c:a", "copy"],
+                    &encoder[..],
+                ]
+                .concat(),
+                fps,
+                size,
+                channels,
+            ),
+            None => Self::spawn_ffmpeg(&encoder, fps, size, channels),
+        }
+    }
+
+    /// low latency h264 published to an rtsp server such as go2rtc or mediamtx
+    pub fn rtsp(url: &str, fps: f64, size: Size, channels: i32) -> Result<Self, Box<dyn Error>> {
+        Self::spawn_ffmpeg(
+            &[
+                "-c:v",
+                "libx264",
+                "-preset",
+                "ultrafast",
+                "-tune",
+                "zerolatency",
+                "-pix_fmt",
+                "yuv420p",
+                "-f",
+                "rtsp",
+                "-rtsp_transport",
+                "tcp",
+                url,
+            ],
+            fps,
+            size,
+            channels,
+        )
+    }
+
+    /// video file writer, ffmpeg also copies the audio of `audio_from`, opencv drops it
+    pub fn open(
+        path: &str,
+        codec: &str,
+        fps: f64,
+        size: Size,
+        channels: i32,
+        encoder: Encoder,
+        audio_from: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if encoder != Encoder::Ffmpeg {
+            if let Some(fourcc) = fourcc(codec) {
+                let writer = VideoWriter::new(path, fourcc, fps, size, channels == 3)?;
+                if writer.is_opened()? {
+                    return Ok(VideoSink::Opencv(writer));
+                }
+            }
+            if encoder == Encoder::Opencv {
+                return Err(format!("opencv can't write {codec} to {path}").into());
+            }
+            warn!("opencv can't write {codec} to {path}, using ffmpeg");
+        }
+        Self::ffmpeg(path, codec, fps, size, channels, audio_from)
+    }
+
+    pub fn write(&mut self, frame: &Mat) -> Result<(), Box<dyn Error>> {
+        match self {
+            VideoSink::Opencv(writer) => writer.write(frame)?,
+            VideoSink::Ffmpeg { stdin, .. } => stdin.write_all(frame.data_bytes()?)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            VideoSink::Opencv(mut writer) => writer.release()?,
+            VideoSink::Ffmpeg { mut child, stdin } => {
+                // closing stdin lets ffmpeg flush and finalize the container
+                drop(stdin);
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(format!("ffmpeg failed with {status}").into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn correct(
+    calibration: &Calibration,
+    input: &str,
+    output: &str,
+    codec: &str,
+    encoder: Encoder,
+) -> Result<(), Box<dyn Error>> {
+    let mut capture = VideoCapture::from_file_def(input)?;
+    if !capture.is_opened()? {
+        return Err(format!("could not open video {input}").into());
+    }
+    let fps = capture.get(CAP_PROP_FPS)?;
+    // some containers and streams don't report a frame count
+    let pb = match capture.get(CAP_PROP_FRAME_COUNT)? as u64 {
+        0 => progress::spinner(),
+        frames => progress::progress_bar(frames),
+    };
+    let started = Instant::now();
+
+    let mut frame = Mat::default();
+    let mut sink: Option<(VideoSink, Undistorter)> = None;
+    while capture.read(&mut frame)? && !frame.empty() {
+        if sink.is_none() {
+            let size = frame.size()?;
+            let video = VideoSink::open(
+                output,
+                codec,
+                fps,
+                size,
+                frame.channels(),
+                encoder,
+                Some(input),
+            )?;
+            if let VideoSink::Opencv(_) = video {
+                info!("opencv writes no audio, use --encoder ffmpeg to keep it");
+            }
+            sink = Some((video, Undistorter::new(calibration, size)?));
+        }
+        let (video, undistorter) = sink.as_mut().unwrap();
+        video.write(&undistorter.undistort(&frame)?)?;
+        pb.inc(1);
+    }
+    let (video, _) = sink.ok_or_else(|| format!("no frames in {input}"))?;
+    video.finish()?;
+    pb.finish_and_clear();
+    info!("{output} written in {}", HumanDuration(started.elapsed()));
+    Ok(())
+}
+
+/// board detections sampled from a calibration video
+pub struct Samples {
+    /// board points with the index of the frame they were detected in
+    pub corners: Vec<(usize, Vector<Point2f>)>,
+    pub size: Size,
+    /// frames read from the video
+    pub frames: usize,
+}
+
+// variance of the laplacian, low for motion blurred and out of focus frames
+fn sharpness(frame: &Mat) -> opencv::Result<f64> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(frame, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    let mut laplacian = Mat::default();
+    imgproc::laplacian_def(&gray, &mut laplacian, opencv::core::CV_64F)?;
+    let mut mean = Scalar::default();
+    let mut stddev = Scalar::default();
+    opencv::core::mean_std_dev_def(&laplacian, &mut mean, &mut stddev)?;
+    Ok(stddev[0] * stddev[0])
+}
+
+// center and rms radius of the board points
+fn footprint(corners: &Vector<Point2f>) -> (Point2f, f64) {
+    let n = corners.len().max(1) as f32;
+    let center = corners.iter().fold(Point2f::new(0.0, 0.0), |a, p| a + p) / n;
+    let radius = (corners
+        .iter()
+        .map(|p| ((p.x - center.x).powi(2) + (p.y - center.y).powi(2)) as f64)
+        .sum::<f64>()
+        / n as f64)
+        .sqrt();
+    (center, radius)
+}
+
+/// the sharpest frame of every `interval` frames of a video, kept when the board is visible
+/// in a part of the image or at a distance not covered by an earlier sample, until
+/// `max_frames` are sampled
+pub fn sample_boards(
+    input: &str,
+    board: &Board,
+    interval: usize,
+    max_frames: usize,
+) -> Result<Samples, Box<dyn Error>> {
+    let mut capture = VideoCapture::from_file_def(input)?;
+    if !capture.is_opened()? {
+        return Err(format!("could not open video {input}").into());
+    }
+    let pb = match capture.get(CAP_PROP_FRAME_COUNT)? as u64 {
+        0 => progress::spinner(),
+        frames => progress::progress_bar(frames),
+    };
+    let interval = interval.max(1);
+    let mut samples = Samples {
+        corners: Vec::new(),
+        size: Size::default(),
+        frames: 0,
+    };
+    let mut footprints = Vec::<(Point2f, f64)>::new();
+    let mut frame = Mat::default();
+    let mut best: Option<(f64, usize, Mat)> = None;
+    loop {
+        let read = capture.read(&mut frame)? && !frame.empty();
+        if read {
+            samples.size = frame.size()?;
+            let score = sharpness(&frame)?;
+            if best.as_ref().is_none_or(|(best, _, _)| score > *best) {
+                best = Some((score, samples.frames, frame.try_clone()?));
+            }
+            samples.frames += 1;
+            pb.inc(1);
+        }
+        let window_done = !read || samples.frames.is_multiple_of(interval);
+        if let Some((score, index, candidate)) = best.take_if(|_| window_done) {
+            let diagonal = (samples.size.width as f64).hypot(samples.size.height as f64);
+            match board.detect(&candidate)? {
+                Some(corners) => {
+                    let (center, radius) = footprint(&corners);
+                    let covered = footprints.iter().any(|(other, other_radius)| {
+                        let shift =
+                            ((center.x - other.x) as f64).hypot((center.y - other.y) as f64);
+                        shift < MIN_SHIFT * diagonal
+                            && (radius / other_radius - 1.0).abs() < MIN_SCALE_CHANGE
+                    });
+                    if covered {
+                        debug!("frame {index}: board already covered");
+                    } else {
+                        debug!("frame {index}: board sampled, sharpness {score:.1}");
+                        footprints.push((center, radius));
+                        samples.corners.push((index, corners));
+                        pb.set_message(format!("{} boards", samples.corners.len()));
+                    }
+                }
+                None => debug!("frame {index}: no board"),
+            }
+        }
+        if !read || samples.corners.len() >= max_frames {
+            break;
+        }
+    }
+    pb.finish_and_clear();
+    if samples.frames == 0 {
+        return Err(format!("no frames in {input}").into());
+    }
+    info!(
+        "{input}: {} boards sampled from {} frames",
+        samples.corners.len(),
+        samples.frames
+    );
+    Ok(samples)
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvTimeoutError, channel};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::select::Selection;
+
+// how often files still being written are checked again
+const POLL: Duration = Duration::from_millis(200);
+
+/// the output was written from the current version of every source, the input image, the
+/// calibration and the like, by this or an earlier run. Outputs must be written aside and
+/// renamed, a truncated one would be newer than its sources
+pub fn up_to_date(sources: &[&str], output: &str) -> bool {
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified());
+    let Ok(output) = modified(output) else {
+        return false;
+    };
+    sources
+        .iter()
+        .all(|source| modified(source).is_ok_and(|source| output >= source))
+}
+
+// a file that changed lately, ready once its size stayed the same for the settle time
+struct Pending {
+    size: u64,
+    since: Instant,
+}
+
+/// hand every selected file of `dir` to `ready`, first the ones already there, then each new
+/// or rewritten one once it stopped growing for `settle`. Runs until the process is stopped
+pub fn run(
+    dir: &str,
+    selection: &Selection,
+    extensions: &[&str],
+    settle: Duration,
+    mut ready: impl FnMut(&str),
+) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mode = if selection.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    // watching before listing, a file arriving in between is seen twice rather than never
+    watcher.watch(Path::new(dir), mode)?;
+    for image in selection.files(dir, extensions)? {
+        ready(&image);
+    }
+    info!("watching {dir} for new images");
+
+    let mut pending = HashMap::<PathBuf, Pending>::new();
+    loop {
+        match rx.recv_timeout(POLL) {
+            Ok(Ok(event)) => {
+                // reading the images for the correction must not bring them back
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_))
+                        | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                ) {
+                    for path in event.paths {
+                        if selection.matches(dir, &path, extensions)? {
+                            pending.entry(path).or_insert(Pending {
+                                size: 0,
+                                since: Instant::now(),
+                            });
+                        }
+                    }
+                }
+            }
+            Ok(Err(e)) => warn!("watching {dir}: {e}"),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(format!("stopped watching {dir}").into());
+            }
+        }
+
+        let mut settled = Vec::new();
+        pending.retain(|path, file| {
+            // removed or renamed away before it settled
+            let Ok(metadata) = fs::metadata(path) else {
+                return false;
+            };
+            if metadata.len() != file.size {
+                file.size = metadata.len();
+                file.since = Instant::now();
+            } else if file.size > 0 && file.since.elapsed() >= settle {
+                settled.push(path.clone());
+                return false;
+            }
+            true
+        });
+        settled.sort();
+        for path in settled {
+            ready(&path.to_string_lossy());
+        }
+    }
+}
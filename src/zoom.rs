@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{Calibration, SCHEMA_VERSION, exif, provenance};
+
+/// calibration of a zoom lens at one focal length
+#[derive(Serialize, Deserialize)]
+pub struct ZoomProfile {
+    focal_length_mm: f64,
+    calibration: Calibration,
+}
+
+/// calibrations of one zoom lens sorted by focal length, a single profile can't serve the
+/// whole zoom range
+#[derive(Serialize, Deserialize, Default)]
+pub struct ZoomProfiles {
+    profiles: Vec<ZoomProfile>,
+}
+
+impl ZoomProfiles {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let profiles: ZoomProfiles = serde_json::from_slice(&fs::read(path)?)?;
+        if profiles.profiles.is_empty() {
+            return Err(format!("{path} has no zoom profiles").into());
+        }
+        Ok(profiles)
+    }
+
+    /// add or replace the calibration at a focal length, creating the file if needed
+    pub fn add(
+        path: &str,
+        focal_length_mm: f64,
+        calibration: Calibration,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut library = if Path::new(path).exists() {
+            Self::load(path)?
+        } else {
+            ZoomProfiles::default()
+        };
+        if let Some(other) = library.profiles.first().map(|profile| &profile.calibration)
+            && (other.model != calibration.model
+                || other.image_size != calibration.image_size
+                || other.dist_coeffs.len() != calibration.dist_coeffs.len())
+        {
+            return Err("zoom profiles need the same model, image size and distortion terms at every focal length".into());
+        }
+        library
+            .profiles
+            .retain(|profile| profile.focal_length_mm != focal_length_mm);
+        library.profiles.push(ZoomProfile {
+            focal_length_mm,
+            calibration,
+        });
+        library
+            .profiles
+            .sort_by(|a, b| a.focal_length_mm.total_cmp(&b.focal_length_mm));
+        fs::write(path, serde_json::to_string(&library)?)?;
+        info!(
+            "{path}: {} profiles, {focal_length_mm} mm added",
+            library.profiles.len()
+        );
+        Ok(())
+    }
+
+    /// crc32 of the profiles, the whole library is the calibration of a run
+    pub fn checksum(&self) -> String {
+        format!(
+            "{:08x}",
+            crc32fast::hash(&serde_json::to_vec(self).unwrap_or_default())
+        )
+    }
+
+    /// calibration at a focal length, linear between the neighbouring profiles and the closest
+    /// profile outside the calibrated range
+    pub fn at(&self, focal_length_mm: f64) -> Calibration {
+        let profiles = &self.profiles;
+        let (first, last) = (&profiles[0], &profiles[profiles.len() - 1]);
+        if focal_length_mm <= first.focal_length_mm || focal_length_mm >= last.focal_length_mm {
+            let closest = if focal_length_mm <= first.focal_length_mm {
+                first
+            } else {
+                last
+            };
+            if closest.focal_length_mm != focal_length_mm {
+                warn!(
+                    "focal length {focal_length_mm} mm outside the profiles, using {} mm",
+                    closest.focal_length_mm
+                );
+            }
+            return closest.calibration.clone();
+        }
+        let upper = profiles
+            .iter()
+            .position(|profile| profile.focal_length_mm >= focal_length_mm)
+            .unwrap_or(profiles.len() - 1);
+        let (a, b) = (&profiles[upper - 1], &profiles[upper]);
+        let w = (focal_length_mm - a.focal_length_mm) / (b.focal_length_mm - a.focal_length_mm);
+        let lerp = |x: &[f64], y: &[f64]| {
+            x.iter()
+                .zip(y)
+                .map(|(x, y)| x + (y - x) * w)
+                .collect::<Vec<f64>>()
+        };
+        Calibration {
+            version: SCHEMA_VERSION,
+            model: a.calibration.model,
+            camera_matrix: lerp(&a.calibration.camera_matrix, &b.calibration.camera_matrix),
+            dist_coeffs: lerp(&a.calibration.dist_coeffs, &b.calibration.dist_coeffs),
+            image_size: a.calibration.image_size,
+            board: a.calibration.board,
+            omnidir: a.calibration.omnidir,
+            rms: None,
+            new_camera_matrix: None,
+            roi: None,
+            provenance: None,
+        }
+    }
+
+    /// calibration for the exif focal length of an image
+    pub fn for_image(&self, path: &str) -> Result<Calibration, Box<dyn Error>> {
+        let focal_length_mm =
+            exif::focal_length(path)?.ok_or("no exif focal length to pick a zoom profile")?;
+        Ok(self.at(focal_length_mm))
+    }
+}
+
+/// the calibration of a run, fixed or picked for every image from zoom profiles
+pub enum Lens {
+    Fixed(Box<Calibration>),
+    Zoom(ZoomProfiles),
+}
+
+impl Lens {
+    pub fn checksum(&self) -> String {
+        match self {
+            Lens::Fixed(calibration) => provenance::calibration_checksum(calibration),
+            Lens::Zoom(profiles) => profiles.checksum(),
+        }
+    }
+
+    pub fn for_image(&self, path: &str) -> Result<Calibration, Box<dyn Error>> {
+        match self {
+            Lens::Fixed(calibration) => Ok(calibration.as_ref().clone()),
+            Lens::Zoom(profiles) => profiles.for_image(path),
+        }
+    }
+}